@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+
+/// One change recorded in `lqosd`'s audit log.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// When the change was made, in seconds since the UNIX epoch.
+    pub timestamp: u64,
+    /// Who made it: the bus client's address, plus the node manager
+    /// user (e.g. `web:admin@127.0.0.1:51234`) if there was one.
+    pub client: String,
+    /// The kind of request, e.g. `MapIpToFlow`.
+    pub request: String,
+    /// The request's parameters, as JSON.
+    pub parameters: String,
+    /// `ok`, or `failed: ` followed by the reason.
+    pub result: String,
+}
+
+/// Longest `parameters` recorded; a large request (such as a circuit
+/// with many devices, or `AssignCpus`) is cut short rather than filling
+/// the log.
+pub const MAX_AUDIT_PARAMETERS: usize = 4096;
+
+/// Cuts `parameters` to `MAX_AUDIT_PARAMETERS` bytes, noting how long
+/// it was.
+pub fn bounded_parameters(mut parameters: String) -> String {
+    if parameters.len() > MAX_AUDIT_PARAMETERS {
+        let length = parameters.len();
+        let mut end = MAX_AUDIT_PARAMETERS;
+        while !parameters.is_char_boundary(end) {
+            end -= 1;
+        }
+        parameters.truncate(end);
+        parameters.push_str(&format!("... ({length} bytes)"));
+    }
+    parameters
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn long_parameters_are_cut_at_a_character() {
+        assert_eq!(bounded_parameters("{}".to_string()), "{}");
+        let long = "é".repeat(MAX_AUDIT_PARAMETERS);
+        let cut = bounded_parameters(long.clone());
+        assert!(cut.ends_with(&format!("... ({} bytes)", long.len())));
+        assert!(cut.len() < MAX_AUDIT_PARAMETERS + 32);
+    }
+}
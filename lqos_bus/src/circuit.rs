@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+use crate::{IpStats, ShapedDevice, TcHandle};
+
+/// Ways to look up a circuit with `BusRequest::FindCircuits`. At most
+/// `MAX_CIRCUITS_FOUND` circuits are returned.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum CircuitQuery {
+    /// Exact circuit ID.
+    Id(String),
+    /// Case-insensitive substring of the circuit name.
+    Name(String),
+    /// An IP address, matched against the devices' addresses and
+    /// subnets (longest prefix wins).
+    Ip(String),
+    /// A device MAC address. Case and `-`/`:` separators are ignored.
+    Mac(String),
+    /// The circuit's download or upload class.
+    TcHandle(TcHandle),
+}
+
+/// The most circuits `BusRequest::FindCircuits` returns, in
+/// `ShapedDevices.csv` order. A short name can match most of the
+/// network, and each circuit comes with its hosts and queues.
+pub const MAX_CIRCUITS_FOUND: usize = 100;
+
+/// Counters for one of a circuit's queues, from `tc`. Counters are
+/// totals since the queue was created.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct QueueStats {
+    /// Queue discipline, `cake` or `fq_codel`.
+    pub kind: String,
+    pub bytes: u64,
+    pub packets: u64,
+    pub drops: u64,
+    /// Bytes currently queued.
+    pub backlog: u64,
+    /// CAKE's per-tin counters, lowest priority first (for `diffserv4`:
+    /// bulk, best effort, video, voice). Empty for `fq_codel`.
+    pub tins: Vec<CakeTinStats>,
+}
+
+/// Counters for one CAKE tin. Counts are totals since the queue was
+/// created; delays are CAKE's current estimates.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CakeTinStats {
+    pub sent_bytes: u64,
+    pub sent_packets: u64,
+    pub drops: u64,
+    pub ecn_marks: u64,
+    pub backlog_bytes: u64,
+    pub avg_delay_us: u64,
+    pub peak_delay_us: u64,
+    pub base_delay_us: u64,
+}
+
+/// Everything `lqosd` knows about a circuit. Tuples are
+/// (download, upload).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CircuitInfo {
+    pub circuit_id: String,
+    pub circuit_name: String,
+    pub parent_node: String,
+    /// Names of the `network.json` nodes above the circuit, from the
+    /// top of the tree down to its parent node. Empty for a flat
+    /// network.
+    pub parents: Vec<String>,
+    pub devices: Vec<ShapedDevice>,
+    pub plan_min_mbps: (u32, u32),
+    pub plan_max_mbps: (u32, u32),
+    /// Classes from `queuingStructure.json`, if LibreQoS has built the
+    /// circuit's queues.
+    pub tc_handles: Option<(TcHandle, TcHandle)>,
+    pub bits_per_second: (u64, u64),
+    /// Median of the circuit's host RTTs, or 0 if none were measured.
+    pub median_rtt_ms: f32,
+    /// Current traffic of each of the circuit's hosts, busiest first.
+    pub hosts: Vec<IpStats>,
+    /// Queue counters, as of the last time the queues were read.
+    pub queues: Option<(QueueStats, QueueStats)>,
+}
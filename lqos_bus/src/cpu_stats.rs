@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+
+/// A circuit's plan rates, used to choose a CPU for it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CircuitPlan {
+    pub circuit_id: String,
+    pub download_mbps: u64,
+    pub upload_mbps: u64,
+}
+
+/// The CPU (and so the HTB tree and TX queue) chosen for a circuit.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CpuAssignment {
+    pub circuit_id: String,
+    pub cpu: u32,
+}
+
+/// Load on one CPU. Tuples are (download, upload).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CpuStats {
+    pub cpu: u32,
+    /// Excluded CPUs don't shape anything (e.g. they handle NIC IRQs).
+    pub excluded: bool,
+    /// Circuits assigned to this CPU in `queuingStructure.json`.
+    pub circuits: u32,
+    /// Traffic seen by the XDP program while running on this CPU.
+    pub xdp_bits_per_second: (u64, u64),
+    /// Mapped traffic redirected to this CPU for shaping.
+    pub shaped_bits_per_second: (u64, u64),
+}
@@ -26,4 +26,81 @@ pub struct XdpPpingResult {
     pub max: f32,
     pub median: f32,
     pub samples: u32,
-}
\ No newline at end of file
+}
+
+/// Coarse classification of an IP address that isn't mapped to a circuit.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpClassification {
+    /// RFC1918 (IPv4) or unique-local `fc00::/7` (IPv6) space.
+    Private,
+    /// Carrier-grade NAT space, `100.64.0.0/10`.
+    CarrierGradeNat,
+    /// Link-local addresses (`169.254.0.0/16`, `fe80::/10`).
+    LinkLocal,
+    /// Everything else.
+    Public,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UnknownIp {
+    pub ip_address: String,
+    pub classification: IpClassification,
+    /// The configured subnet (from `allowedSubnets`) the address falls in, if any.
+    pub subnet: Option<String>,
+    pub first_seen_secs_ago: u64,
+    pub last_seen_secs_ago: u64,
+    pub total_bytes: (u64, u64),
+    pub total_packets: (u64, u64),
+}
+
+/// Statistics for a single tracked flow (one direction of a 5-tuple).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FlowStats {
+    pub src_ip: String,
+    pub dst_ip: String,
+    pub src_port: u16,
+    pub dst_port: u16,
+    /// IP protocol number (6 = TCP, 17 = UDP, etc.)
+    pub protocol: u8,
+    pub bits_per_second: u64,
+    pub packets_per_second: u64,
+    pub total_bytes: u64,
+    pub total_packets: u64,
+    /// Most recent TCP round-trip time in milliseconds, 0 if unknown.
+    pub last_rtt: f32,
+    pub last_seen_secs_ago: u64,
+    pub tc_handle: TcHandle,
+}
+
+/// Coarse application category, decided by the XDP program from the L4
+/// protocol and well-known ports. The order matches the bucket numbers
+/// in `protocol_buckets.h`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProtocolBucket {
+    Other,
+    Web,
+    Video,
+    Gaming,
+    Voip,
+    Dns,
+}
+
+impl ProtocolBucket {
+    /// Every bucket, in XDP bucket-number order.
+    pub const ALL: [ProtocolBucket; 6] = [
+        ProtocolBucket::Other,
+        ProtocolBucket::Web,
+        ProtocolBucket::Video,
+        ProtocolBucket::Gaming,
+        ProtocolBucket::Voip,
+        ProtocolBucket::Dns,
+    ];
+}
+
+/// Traffic in a single protocol bucket. Tuples are (download, upload).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProtocolStats {
+    pub bucket: ProtocolBucket,
+    pub bits_per_second: (u64, u64),
+    pub total_bytes: (u64, u64),
+}
@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+
+/// How an XDP program is attached to an interface.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum XdpAttachMode {
+    /// Offloaded to the NIC.
+    Hardware,
+    /// Native, in the NIC driver.
+    Driver,
+    /// Generic (SKB) mode. Works everywhere, but is much slower.
+    Generic,
+    /// Attached, but the mode couldn't be determined.
+    Unknown,
+}
+
+/// The state of the XDP/TC programs on one interface, as recorded when
+/// `lqosd` attached them.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InterfaceStatus {
+    pub interface: String,
+    /// "Internet", "ISP" or "On a stick"
+    pub direction: String,
+    pub xdp_mode: XdpAttachMode,
+    pub xdp_program_id: u32,
+    /// True if an already-attached program was replaced in place.
+    pub hot_replaced: bool,
+    /// TC egress program id, 0 if it isn't attached.
+    pub tc_egress_program_id: u32,
+    /// TC ingress (bridge) program id, 0 if it isn't attached.
+    pub tc_ingress_program_id: u32,
+}
+
+/// A pinned eBPF map.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MapStatus {
+    pub name: String,
+    /// Kernel map id, 0 if the map isn't pinned.
+    pub id: u32,
+}
+
+/// Everything `lqosd` knows about its XDP/TC programs and maps.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KernelStatus {
+    pub pin_root: String,
+    pub interfaces: Vec<InterfaceStatus>,
+    pub maps: Vec<MapStatus>,
+}
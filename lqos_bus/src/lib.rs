@@ -1,15 +1,41 @@
 mod ip_stats;
 use anyhow::Result;
-pub use ip_stats::{IpMapping, IpStats, XdpPpingResult};
+pub use ip_stats::{IpMapping, IpStats, XdpPpingResult, IpClassification, UnknownIp, FlowStats, ProtocolBucket, ProtocolStats};
+pub use lqos_config::ShapedDevice;
 use serde::{Deserialize, Serialize};
 mod tc_handle;
 pub use tc_handle::TcHandle;
+mod kernel_status;
+mod cpu_stats;
+mod tuning;
+mod network_tree;
+mod circuit;
+mod audit;
+pub use cpu_stats::{CircuitPlan, CpuAssignment, CpuStats};
+pub use kernel_status::{InterfaceStatus, KernelStatus, MapStatus, XdpAttachMode};
+pub use tuning::TuningResult;
+pub use network_tree::NetworkNodeStats;
+pub use circuit::{CakeTinStats, CircuitInfo, CircuitQuery, QueueStats, MAX_CIRCUITS_FOUND};
+pub use audit::{bounded_parameters, AuditEntry, MAX_AUDIT_PARAMETERS};
 
 pub const BUS_BIND_ADDRESS: &str = "127.0.0.1:9999";
 
+/// The address `lqosd` listens on, for clients: `bus_bind_address` from
+/// `/etc/lqos` (or the file named by `LQOS_CONFIG`), or
+/// `BUS_BIND_ADDRESS` if it isn't set.
+pub fn bus_address() -> String {
+    lqos_config::EtcLqos::load()
+        .ok()
+        .and_then(|etc| etc.bus_bind_address)
+        .unwrap_or_else(|| BUS_BIND_ADDRESS.to_string())
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct BusSession {
     pub auth_cookie: u32,
+    /// Who is asking, for the audit log (e.g. `web:<username>`). The
+    /// daemon adds the connection's address.
+    pub client: Option<String>,
     pub requests: Vec<BusRequest>,
 }
 
@@ -35,8 +61,30 @@ pub enum BusRequest {
     RttHistogram,
     HostCounts,
     AllUnknownIps,
+    UnknownIpDetails,
+    SuggestShapedDevice(String), // The string is the unknown IP address
     ReloadLibreQoS,
     GetRawQueueData(String), // The string is the circuit ID
+    GetTopFlows {
+        circuit_id: String,
+        n: u32,
+    },
+    GetProtocolBreakdown(Option<String>), // Circuit ID, or None for the whole network
+    KernelStatus,
+    CpuStats,
+    AssignCpus(Vec<CircuitPlan>),
+    TuningStatus,
+    RestoreTuning,
+    NetworkTree,
+    FindCircuits(CircuitQuery),
+    /// Records a change made outside the bus (e.g. a node manager edit
+    /// of `ShapedDevices.csv`) in the audit log.
+    RecordConfigChange {
+        change: String,
+        parameters: String,
+        result: String,
+    },
+    AuditLog(u32), // The most recent n entries, newest first
     #[cfg(feature = "equinix_tests")]
     RequestLqosEquinixTest, // TODO: Feature flag this so it doesn't go into production
 }
@@ -63,16 +111,80 @@ pub enum BusResponse {
     RttHistogram(Vec<u32>),
     HostCounts((u32, u32)),
     AllUnknownIps(Vec<IpStats>),
+    UnknownIpDetails(Vec<UnknownIp>),
+    SuggestedDevice(ShapedDevice),
     ReloadLibreQoS(String),
     RawQueueData(String),
+    TopFlows(Vec<FlowStats>),
+    ProtocolBreakdown(Vec<ProtocolStats>),
+    KernelStatus(KernelStatus),
+    CpuStats(Vec<CpuStats>),
+    CpuAssignments(Vec<CpuAssignment>),
+    Tuning(Vec<TuningResult>),
+    NetworkTree(Vec<NetworkNodeStats>),
+    Circuits(Vec<CircuitInfo>),
+    AuditLog(Vec<AuditEntry>),
 }
 
+impl BusRequest {
+    /// True for requests that change something, and so are recorded in
+    /// the audit log.
+    pub fn is_mutating(&self) -> bool {
+        match self {
+            BusRequest::MapIpToFlow { .. }
+            | BusRequest::DelIpFlow { .. }
+            | BusRequest::ClearIpFlow
+            | BusRequest::ReloadLibreQoS
+            | BusRequest::AssignCpus(_)
+            | BusRequest::RestoreTuning
+            | BusRequest::RecordConfigChange { .. } => true,
+            #[cfg(feature = "equinix_tests")]
+            BusRequest::RequestLqosEquinixTest => true,
+            _ => false,
+        }
+    }
+}
+
+/// Bytes in a request's length prefix.
+pub const REQUEST_HEADER_SIZE: usize = 4;
+
+/// The largest request `lqosd` accepts.
+pub const MAX_REQUEST_SIZE: usize = 16 * 1024 * 1024;
+
+/// Requests are framed with their length (a little-endian `u32`), so
+/// `lqosd` knows how much to read however large the request is.
+/// Responses need no framing: `lqosd` closes the connection after
+/// replying.
 pub fn encode_request(request: &BusSession) -> Result<Vec<u8>> {
-    Ok(bincode::serialize(request)?)
+    let body = bincode::serialize(request)?;
+    if body.len() > MAX_REQUEST_SIZE {
+        return Err(anyhow::Error::msg(format!("Request is too large ({} bytes)", body.len())));
+    }
+    let mut bytes = Vec::with_capacity(REQUEST_HEADER_SIZE + body.len());
+    bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&body);
+    Ok(bytes)
+}
+
+/// The length of the request that follows a length prefix.
+pub fn request_length(header: [u8; REQUEST_HEADER_SIZE]) -> Result<usize> {
+    let length = u32::from_le_bytes(header) as usize;
+    if length > MAX_REQUEST_SIZE {
+        return Err(anyhow::Error::msg(format!("Request is too large ({length} bytes)")));
+    }
+    Ok(length)
 }
 
+/// Decodes a request, including its length prefix.
 pub fn decode_request(bytes: &[u8]) -> Result<BusSession> {
-    Ok(bincode::deserialize(&bytes)?)
+    let header = bytes
+        .get(..REQUEST_HEADER_SIZE)
+        .ok_or_else(|| anyhow::Error::msg("Request is missing its length"))?;
+    let length = request_length(header.try_into()?)?;
+    let body = bytes
+        .get(REQUEST_HEADER_SIZE..REQUEST_HEADER_SIZE + length)
+        .ok_or_else(|| anyhow::Error::msg("Request is shorter than its length"))?;
+    Ok(bincode::deserialize(body)?)
 }
 
 pub fn encode_response(request: &BusReply) -> Result<Vec<u8>> {
@@ -86,3 +198,33 @@ pub fn decode_response(bytes: &[u8]) -> Result<BusReply> {
 pub fn cookie_value() -> u32 {
     1234
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn large_request_round_trip() {
+        let plans = (0..200)
+            .map(|i| CircuitPlan { circuit_id: format!("circuit-{i}"), download_mbps: 100, upload_mbps: 20 })
+            .collect();
+        let session = BusSession { auth_cookie: cookie_value(), client: None, requests: vec![BusRequest::AssignCpus(plans)] };
+        let bytes = encode_request(&session).unwrap();
+        assert!(bytes.len() > 1024);
+        match &decode_request(&bytes).unwrap().requests[0] {
+            BusRequest::AssignCpus(plans) => {
+                assert_eq!(plans.len(), 200);
+                assert_eq!(plans[199].circuit_id, "circuit-199");
+            }
+            _ => panic!("Unexpected request"),
+        }
+    }
+
+    #[test]
+    fn truncated_request_is_rejected() {
+        let session = BusSession { auth_cookie: cookie_value(), client: None, requests: vec![BusRequest::Ping] };
+        let bytes = encode_request(&session).unwrap();
+        assert!(decode_request(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode_request(&bytes[..2]).is_err());
+    }
+}
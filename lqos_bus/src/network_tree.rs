@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+
+/// Traffic rolled up to one node of `network.json`, including every
+/// circuit beneath it. Tuples are (download, upload).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NetworkNodeStats {
+    pub name: String,
+    /// Index (in the returned list) of the node above this one.
+    pub immediate_parent: Option<usize>,
+    /// Configured capacity, from `network.json`.
+    pub bandwidth_mbps: (u64, u64),
+    pub circuits: u32,
+    pub bits_per_second: (u64, u64),
+    /// Current throughput as a percentage of the configured capacity.
+    pub utilization_percent: (f32, f32),
+    /// Median of the circuits' host RTTs, or 0 if none were measured.
+    pub median_rtt_ms: f32,
+    /// Total packets dropped by the circuits' queues since they were
+    /// created.
+    pub drops: (u64, u64),
+}
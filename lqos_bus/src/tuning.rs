@@ -0,0 +1,16 @@
+use serde::{Deserialize, Serialize};
+
+/// The outcome of changing (or restoring) one system setting from the
+/// `[tuning]` section of `/etc/lqos`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TuningResult {
+    /// What was changed, e.g. `sysctl net.core.netdev_budget` or
+    /// `eth1 offload gro`.
+    pub setting: String,
+    /// The value before `lqosd` changed it, if it could be read.
+    pub previous: Option<String>,
+    /// The value `lqosd` set.
+    pub value: String,
+    /// `None` on success, otherwise why it failed.
+    pub error: Option<String>,
+}
@@ -0,0 +1,163 @@
+use std::{fs::OpenOptions, io::Write, os::unix::fs::OpenOptionsExt, path::Path};
+use anyhow::{Error, Result};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use serde::{Deserialize, Serialize};
+
+/// What a node manager user may do.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UserRole {
+    /// May view everything, but change nothing.
+    ReadOnly,
+    /// May also edit shaped devices, reload LibreQoS and manage users.
+    Admin,
+}
+
+/// A node manager account. Only an Argon2 hash of the password is
+/// stored.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WebUser {
+    pub username: String,
+    pub password_hash: String,
+    pub role: UserRole,
+}
+
+/// Node manager accounts, stored in `/etc/lqusers.toml`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct WebUsers {
+    #[serde(default)]
+    users: Vec<WebUser>,
+}
+
+impl WebUsers {
+    /// The users file in use: `/etc/lqusers.toml`, or the file named by
+    /// the `LQOS_USERS` environment variable if it is set.
+    pub fn path() -> String {
+        std::env::var("LQOS_USERS").unwrap_or_else(|_| "/etc/lqusers.toml".to_string())
+    }
+
+    /// Loads the users file. A missing file means there are no users
+    /// yet.
+    pub fn load() -> Result<Self> {
+        let path = Self::path();
+        if !Path::new(&path).exists() {
+            return Ok(Self::default());
+        }
+        let raw = std::fs::read_to_string(&path)?;
+        Ok(toml::from_str(&raw)?)
+    }
+
+    /// Writes the users file, readable only by its owner. The file is
+    /// replaced in one step, so a failed write can't lose the accounts.
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path();
+        let temporary = format!("{path}.new");
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temporary)?;
+        file.write_all(toml::to_string(self)?.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&temporary, &path)?;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    /// Usernames and roles, without the password hashes.
+    pub fn list(&self) -> Vec<(String, UserRole)> {
+        self.users.iter().map(|u| (u.username.clone(), u.role)).collect()
+    }
+
+    /// Adds a user, or replaces the password and role of an existing
+    /// one.
+    pub fn add_or_update_user(&mut self, username: &str, password: &str, role: UserRole) -> Result<()> {
+        let username = username.trim();
+        if username.is_empty() {
+            return Err(Error::msg("A username is required"));
+        }
+        if password.len() < 8 {
+            return Err(Error::msg("Passwords must be at least 8 characters long"));
+        }
+        let salt = SaltString::generate(&mut rand::rngs::OsRng);
+        let password_hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|e| Error::msg(format!("Unable to hash password: {e}")))?
+            .to_string();
+        self.users.retain(|u| u.username != username);
+        self.users.push(WebUser {
+            username: username.to_string(),
+            password_hash,
+            role,
+        });
+        Ok(())
+    }
+
+    pub fn remove_user(&mut self, username: &str) -> Result<()> {
+        let before = self.users.len();
+        self.users.retain(|u| u.username != username);
+        if self.users.len() == before {
+            return Err(Error::msg(format!("User {username} does not exist")));
+        }
+        Ok(())
+    }
+
+    /// Returns the user's role if the password is correct.
+    pub fn authenticate(&self, username: &str, password: &str) -> Option<UserRole> {
+        let user = self.users.iter().find(|u| u.username == username)?;
+        let hash = PasswordHash::new(&user.password_hash).ok()?;
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .ok()
+            .map(|_| user.role)
+    }
+
+    /// Whether any user is an administrator.
+    pub fn has_admin(&self) -> bool {
+        self.users.iter().any(|u| u.role == UserRole::Admin)
+    }
+
+    /// Returns the role of an existing user.
+    pub fn role(&self, username: &str) -> Option<UserRole> {
+        self.users.iter().find(|u| u.username == username).map(|u| u.role)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn add_authenticate_remove() {
+        let mut users = WebUsers::default();
+        assert!(users.add_or_update_user("admin", "short", UserRole::Admin).is_err());
+        users.add_or_update_user("admin", "correct horse", UserRole::Admin).unwrap();
+        users.add_or_update_user("viewer", "battery staple", UserRole::ReadOnly).unwrap();
+        assert!(!users.users[0].password_hash.contains("correct horse"));
+
+        assert_eq!(users.authenticate("admin", "correct horse"), Some(UserRole::Admin));
+        assert_eq!(users.authenticate("admin", "battery staple"), None);
+        assert_eq!(users.authenticate("nobody", "correct horse"), None);
+
+        // Survives a round trip through the file format.
+        let users: WebUsers = toml::from_str(&toml::to_string(&users).unwrap()).unwrap();
+        assert_eq!(users.authenticate("viewer", "battery staple"), Some(UserRole::ReadOnly));
+
+        let mut users = users;
+        users.add_or_update_user("viewer", "new password", UserRole::Admin).unwrap();
+        assert_eq!(users.authenticate("viewer", "new password"), Some(UserRole::Admin));
+        users.remove_user("viewer").unwrap();
+        assert!(users.remove_user("viewer").is_err());
+        assert_eq!(users.list(), vec![("admin".to_string(), UserRole::Admin)]);
+        assert!(users.has_admin());
+        users.add_or_update_user("admin", "correct horse", UserRole::ReadOnly).unwrap();
+        assert!(!users.has_admin());
+    }
+}
@@ -7,6 +7,14 @@ pub struct EtcLqos {
     pub lqos_directory: String,
     pub bridge: Option<BridgeConfig>,
     pub tuning: Option<Tunables>,
+    pub tracking: Option<TrackingConfig>,
+    pub netflow: Option<NetflowConfig>,
+    pub kernel: Option<KernelConfig>,
+    pub audit: Option<AuditConfig>,
+
+    /// Address on which `lqosd` listens for bus requests. Defaults to
+    /// `BUS_BIND_ADDRESS`; only needed when running more than one instance.
+    pub bus_bind_address: Option<String>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -19,8 +27,172 @@ pub struct Tunables {
     pub disable_rxvlan: bool,
     pub disable_txvlan: bool,
     pub disable_offload: Vec<String>,
+
+    /// RX ring size to set on both interfaces. Left alone if not set.
+    #[serde(default)]
+    pub rx_ring_size: Option<u32>,
+
+    /// TX ring size to set on both interfaces. Left alone if not set.
+    #[serde(default)]
+    pub tx_ring_size: Option<u32>,
+}
+
+/// Controls which addresses `lqosd` reports in its top-N, worst-RTT
+/// and unknown-IP lists. Empty lists fall back to `allowedSubnets` and
+/// `ignoreSubnets` from `ispConfig.py`.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct TrackingConfig {
+    /// If not empty, only addresses inside these subnets are reported.
+    #[serde(default)]
+    pub allowed_subnets: Vec<String>,
+
+    /// Addresses inside these subnets are never reported.
+    #[serde(default)]
+    pub ignore_subnets: Vec<String>,
+
+    /// Also load `ignore_subnets` into the XDP program, so that
+    /// matching traffic is not tracked at all.
+    #[serde(default)]
+    pub ignore_in_xdp: bool,
+
+    /// Track individual flows (5-tuples) in the XDP program, as well as
+    /// hosts. Off by default, since it adds a map update to every packet.
+    #[serde(default)]
+    pub flow_tracking: bool,
+
+    /// Count each circuit's bytes per application bucket in the XDP
+    /// program. Off by default, since it adds a map update to every
+    /// packet.
+    #[serde(default)]
+    pub protocol_tracking: bool,
+}
+
+/// NetFlow v9 / IPFIX export. If the `[netflow]` section is present,
+/// `lqosd` exports flow records to each collector over UDP.
+#[derive(Deserialize, Clone, Debug)]
+pub struct NetflowConfig {
+    /// Collectors to send to, as `host:port` strings.
+    pub collectors: Vec<String>,
+
+    /// Export format, `"v9"` or `"ipfix"` (the default).
+    #[serde(default)]
+    pub protocol: NetflowProtocol,
+
+    /// Export one in every `sampling_rate` records. 1 (the default)
+    /// exports everything.
+    #[serde(default = "default_sampling_rate")]
+    pub sampling_rate: u32,
+
+    /// How often to export records, in seconds.
+    #[serde(default = "default_export_interval")]
+    pub export_interval_secs: u64,
+
+    /// How often to re-send templates, in seconds.
+    #[serde(default = "default_template_refresh")]
+    pub template_refresh_secs: u64,
+
+    /// Source ID (v9) or Observation Domain ID (IPFIX).
+    #[serde(default)]
+    pub observation_domain: u32,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NetflowProtocol {
+    V9,
+    #[default]
+    Ipfix,
+}
+
+fn default_sampling_rate() -> u32 { 1 }
+fn default_export_interval() -> u64 { 10 }
+fn default_template_refresh() -> u64 { 60 }
+
+/// Settings for the XDP/TC kernels.
+#[derive(Deserialize, Clone, Debug)]
+pub struct KernelConfig {
+    /// Directory in which the eBPF maps are pinned. Each `lqosd`
+    /// instance on a box needs its own, e.g. `/sys/fs/bpf/lqos/wan1`.
+    #[serde(default = "default_pin_root")]
+    pub pin_root: String,
+
+    /// Remove the pinned maps when `lqosd` exits, rather than leaving
+    /// them for the next run.
+    #[serde(default)]
+    pub unpin_on_exit: bool,
+
+    /// Detach the XDP/TC programs when `lqosd` exits. Set to `false` to
+    /// keep shaping while `lqosd` restarts (e.g. for an upgrade); the
+    /// next start swaps in the new programs without interrupting traffic.
+    #[serde(default = "default_detach_on_exit")]
+    pub detach_on_exit: bool,
+
+    /// Refuse to start if XDP can only be attached in generic (SKB)
+    /// mode, rather than falling back to it.
+    #[serde(default)]
+    pub require_native_xdp: bool,
+
+    /// Size of each CPU's cpumap queue, in packets.
+    #[serde(default = "default_cpumap_queue_size")]
+    pub cpumap_queue_size: u32,
+
+    /// CPUs that should not shape traffic (e.g. those handling NIC
+    /// interrupts). They are left out of the cpumap, and `lqosd` won't
+    /// assign circuits to them.
+    #[serde(default)]
+    pub excluded_cpus: Vec<u32>,
+}
+
+impl Default for KernelConfig {
+    fn default() -> Self {
+        Self {
+            pin_root: default_pin_root(),
+            unpin_on_exit: false,
+            detach_on_exit: default_detach_on_exit(),
+            require_native_xdp: false,
+            cpumap_queue_size: default_cpumap_queue_size(),
+            excluded_cpus: Vec::new(),
+        }
+    }
+}
+
+fn default_pin_root() -> String { DEFAULT_PIN_ROOT.to_string() }
+fn default_detach_on_exit() -> bool { true }
+fn default_cpumap_queue_size() -> u32 { 2048 }
+
+/// Where libbpf pins maps unless told otherwise.
+pub const DEFAULT_PIN_ROOT: &str = "/sys/fs/bpf";
+
+/// The audit log of changes made through the bus and the node manager.
+#[derive(Deserialize, Clone, Debug)]
+pub struct AuditConfig {
+    /// File to append entries to, one JSON object per line.
+    #[serde(default = "default_audit_log_file")]
+    pub log_file: String,
+
+    /// Rotate the log once it grows past this many bytes.
+    #[serde(default = "default_audit_max_size")]
+    pub max_size_bytes: u64,
+
+    /// Number of rotated logs (`.1`, `.2`, ...) to keep.
+    #[serde(default = "default_audit_keep")]
+    pub keep: u32,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            log_file: default_audit_log_file(),
+            max_size_bytes: default_audit_max_size(),
+            keep: default_audit_keep(),
+        }
+    }
 }
 
+fn default_audit_log_file() -> String { "/var/log/lqos_audit.log".to_string() }
+fn default_audit_max_size() -> u64 { 10 * 1024 * 1024 }
+fn default_audit_keep() -> u32 { 5 }
+
 #[derive(Deserialize, Clone, Debug)]
 pub struct BridgeConfig {
     pub use_kernel_bridge: bool,
@@ -43,13 +215,26 @@ pub struct BridgeVlan {
 }
 
 impl EtcLqos {
+    /// Loads `/etc/lqos`, or the file named by the `LQOS_CONFIG`
+    /// environment variable if it is set.
     pub fn load() -> Result<Self> {
-        if !Path::new("/etc/lqos").exists() {
-            return Err(Error::msg("You must setup /etc/lqos"));
+        let path = Self::path();
+        if !Path::new(&path).exists() {
+            return Err(Error::msg(format!("You must setup {path}")));
         }
-        let raw = std::fs::read_to_string("/etc/lqos")?;
+        let raw = std::fs::read_to_string(&path)?;
         let config: Self = toml::from_str(&raw)?;
         //println!("{:?}", config);
         Ok(config)
     }
+
+    /// The configuration file in use.
+    pub fn path() -> String {
+        std::env::var("LQOS_CONFIG").unwrap_or_else(|_| "/etc/lqos".to_string())
+    }
+
+    /// Directory in which this instance's eBPF maps are pinned.
+    pub fn pin_root(&self) -> String {
+        self.kernel.clone().unwrap_or_default().pin_root
+    }
 }
\ No newline at end of file
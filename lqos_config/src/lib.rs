@@ -1,9 +1,15 @@
+mod authentication;
 mod etc;
 mod libre_qos_config;
+mod network_json;
 mod shaped_devices;
 mod program_control;
+mod subnets;
 
+pub use authentication::{UserRole, WebUser, WebUsers};
 pub use libre_qos_config::LibreQoSConfig;
+pub use network_json::{NetworkJson, NetworkJsonNode};
 pub use shaped_devices::{ConfigShapedDevices, ShapedDevice};
 pub use program_control::load_libreqos;
-pub use etc::{EtcLqos, BridgeConfig, Tunables, BridgeInterface, BridgeVlan};
+pub use subnets::SubnetMatcher;
+pub use etc::{EtcLqos, BridgeConfig, Tunables, BridgeInterface, BridgeVlan, TrackingConfig, NetflowConfig, NetflowProtocol, KernelConfig, AuditConfig, DEFAULT_PIN_ROOT};
@@ -7,6 +7,8 @@ pub struct LibreQoSConfig {
     pub isp_interface: String,
     pub on_a_stick_mode: bool,
     pub stick_vlans: (u16, u16),
+    pub allowed_subnets: Vec<String>,
+    pub ignore_subnets: Vec<String>,
 }
 
 impl LibreQoSConfig {
@@ -29,6 +31,8 @@ impl LibreQoSConfig {
             isp_interface: String::new(),
             on_a_stick_mode: false,
             stick_vlans: (0,0),
+            allowed_subnets: Vec::new(),
+            ignore_subnets: Vec::new(),
         };
         result.parse_isp_config(path)?;
         Ok(result)
@@ -59,6 +63,12 @@ impl LibreQoSConfig {
                 let vlan : u16 = vlan_string.parse()?;
                 self.stick_vlans.1 = vlan;
             }
+            if line.starts_with("allowedSubnets") {
+                self.allowed_subnets = split_python_list(line);
+            }
+            if line.starts_with("ignoreSubnets") {
+                self.ignore_subnets = split_python_list(line);
+            }
         }
         Ok(())
     }
@@ -72,3 +82,31 @@ fn split_at_equals(line: &str) -> String {
         .replace("\"", "")
         .replace("'", "")
 }
+
+// Turns `allowedSubnets = ['100.64.0.0/10', '10.0.0.0/8']` into a list
+// of the quoted entries.
+fn split_python_list(line: &str) -> Vec<String> {
+    split_at_equals(line)
+        .replace(['[', ']'], "")
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_subnet_list() {
+        let list = split_python_list("allowedSubnets = ['100.64.0.0/10', \"10.0.0.0/8\"]");
+        assert_eq!(list, vec!["100.64.0.0/10".to_string(), "10.0.0.0/8".to_string()]);
+    }
+
+    #[test]
+    fn parse_empty_subnet_list() {
+        let list = split_python_list("ignoreSubnets = []");
+        assert!(list.is_empty());
+    }
+}
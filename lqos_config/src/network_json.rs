@@ -0,0 +1,132 @@
+use std::path::{Path, PathBuf};
+use anyhow::{Error, Result};
+use serde_json::{Map, Value};
+use crate::etc;
+
+/// A site, AP or tower from `network.json`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetworkJsonNode {
+    /// The node's name, as used in the `Parent Node` column of
+    /// `ShapedDevices.csv`.
+    pub name: String,
+    pub download_bandwidth_mbps: u64,
+    pub upload_bandwidth_mbps: u64,
+    /// Indices of every node from the top of the tree down to (and
+    /// including) this one.
+    pub parents: Vec<usize>,
+    /// Index of the node directly above this one, if any.
+    pub immediate_parent: Option<usize>,
+}
+
+/// The network hierarchy from `network.json`, flattened into a list.
+/// Parents always come before their children.
+#[derive(Clone, Debug, Default)]
+pub struct NetworkJson {
+    pub nodes: Vec<NetworkJsonNode>,
+}
+
+impl NetworkJson {
+    pub fn path() -> Result<PathBuf> {
+        let cfg = etc::EtcLqos::load()?;
+        let base_path = Path::new(&cfg.lqos_directory);
+        Ok(base_path.join("network.json"))
+    }
+
+    /// Loads `network.json`. A flat network (no file, or an empty
+    /// object) has no nodes.
+    pub fn load() -> Result<Self> {
+        let path = NetworkJson::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let raw = std::fs::read_to_string(path)?;
+        Self::from_json(&raw)
+    }
+
+    pub fn from_json(raw: &str) -> Result<Self> {
+        let json: Value = serde_json::from_str(raw)?;
+        let top = json
+            .as_object()
+            .ok_or_else(|| Error::msg("network.json must contain an object"))?;
+        let mut result = Self::default();
+        result.add_children(top, &[])?;
+        Ok(result)
+    }
+
+    fn add_children(&mut self, children: &Map<String, Value>, parents: &[usize]) -> Result<()> {
+        for (name, node) in children.iter() {
+            let node = node
+                .as_object()
+                .ok_or_else(|| Error::msg(format!("network.json: {name} is not an object")))?;
+            let bandwidth = |key: &str| -> Result<u64> {
+                node.get(key)
+                    .and_then(|v| v.as_u64())
+                    .ok_or_else(|| Error::msg(format!("network.json: {name} has no valid {key}")))
+            };
+            let index = self.nodes.len();
+            let mut node_parents = parents.to_vec();
+            node_parents.push(index);
+            self.nodes.push(NetworkJsonNode {
+                name: name.clone(),
+                download_bandwidth_mbps: bandwidth("downloadBandwidthMbps")?,
+                upload_bandwidth_mbps: bandwidth("uploadBandwidthMbps")?,
+                parents: node_parents.clone(),
+                immediate_parent: parents.last().copied(),
+            });
+            match node.get("children") {
+                Some(Value::Object(children)) => self.add_children(children, &node_parents)?,
+                Some(_) => return Err(Error::msg(format!("network.json: children of {name} is not an object"))),
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds a node by name.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|n| n.name == name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const NETWORK: &str = r#"{
+        "Site_1": {
+            "downloadBandwidthMbps": 1000,
+            "uploadBandwidthMbps": 500,
+            "children": {
+                "AP_A": { "downloadBandwidthMbps": 200, "uploadBandwidthMbps": 100 },
+                "Site_3": {
+                    "downloadBandwidthMbps": 400,
+                    "uploadBandwidthMbps": 400,
+                    "children": {
+                        "AP_B": { "downloadBandwidthMbps": 100, "uploadBandwidthMbps": 50 }
+                    }
+                }
+            }
+        },
+        "Site_2": { "downloadBandwidthMbps": 800, "uploadBandwidthMbps": 800, "children": {} }
+    }"#;
+
+    #[test]
+    fn hierarchy() {
+        let network = NetworkJson::from_json(NETWORK).unwrap();
+        assert_eq!(network.nodes.len(), 5);
+        let site_1 = network.find("Site_1").unwrap();
+        let site_3 = network.find("Site_3").unwrap();
+        let ap_b = &network.nodes[network.find("AP_B").unwrap()];
+        assert_eq!(ap_b.parents, vec![site_1, site_3, network.find("AP_B").unwrap()]);
+        assert_eq!(ap_b.immediate_parent, Some(site_3));
+        assert_eq!(ap_b.download_bandwidth_mbps, 100);
+        assert_eq!(network.nodes[site_1].immediate_parent, None);
+    }
+
+    #[test]
+    fn missing_bandwidth_is_an_error() {
+        assert!(NetworkJson::from_json(r#"{ "Site": { "uploadBandwidthMbps": 5 } }"#).is_err());
+        assert!(NetworkJson::from_json("[]").is_err());
+        assert!(NetworkJson::from_json("{}").unwrap().nodes.is_empty());
+    }
+}
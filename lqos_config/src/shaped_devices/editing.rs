@@ -0,0 +1,278 @@
+//! Adding, replacing and removing devices and circuits, and saving the
+//! result back to `ShapedDevices.csv`.
+//!
+//! Every change is checked before it is made: device IDs must be
+//! unique, and no two devices may share an address or overlapping
+//! subnet. A failed change leaves the devices untouched.
+
+use std::{io::Write, net::Ipv6Addr};
+use anyhow::{Error, Result};
+use ip_network::{IpNetwork, Ipv6Network};
+use super::{ConfigShapedDevices, ShapedDevice};
+
+/// Checks a device's own fields, before comparing it with any others.
+fn validate(device: &ShapedDevice) -> Result<()> {
+    if device.circuit_id.trim().is_empty() {
+        return Err(Error::msg("Circuit ID is required"));
+    }
+    if device.device_id.trim().is_empty() {
+        return Err(Error::msg("Device ID is required"));
+    }
+    if device.download_min_mbps > device.download_max_mbps || device.upload_min_mbps > device.upload_max_mbps {
+        return Err(Error::msg(format!("Device {}: minimum rates can't exceed maximum rates", device.device_id)));
+    }
+    for (ip, prefix) in device.ipv4.iter() {
+        if *prefix > 32 {
+            return Err(Error::msg(format!("Device {}: {ip}/{prefix} is not a valid IPv4 subnet", device.device_id)));
+        }
+    }
+    for (ip, prefix) in device.ipv6.iter() {
+        if *prefix > 128 {
+            return Err(Error::msg(format!("Device {}: {ip}/{prefix} is not a valid IPv6 subnet", device.device_id)));
+        }
+    }
+    Ok(())
+}
+
+/// A device's addresses as networks in the lookup table's (IPv6)
+/// address space.
+fn networks(device: &ShapedDevice) -> Result<Vec<Ipv6Network>> {
+    device
+        .to_ipv6_list()
+        .iter()
+        .map(|(ip, prefix)| {
+            Ipv6Network::new(*ip, *prefix as u8).map_err(|_| {
+                Error::msg(format!("Device {}: {} has host bits set", device.device_id, display(*ip, *prefix)))
+            })
+        })
+        .collect()
+}
+
+/// Formats an address from the lookup table the way it appears in
+/// `ShapedDevices.csv`.
+fn display(ip: Ipv6Addr, prefix: u32) -> String {
+    match ip.to_ipv4_mapped() {
+        Some(ip) if prefix >= 96 => format!("{ip}/{}", prefix - 96),
+        _ => format!("{ip}/{prefix}"),
+    }
+}
+
+impl ConfigShapedDevices {
+    fn position(&self, device_id: &str) -> Result<usize> {
+        self.devices
+            .iter()
+            .position(|d| d.device_id == device_id)
+            .ok_or_else(|| Error::msg(format!("Device {device_id} does not exist")))
+    }
+
+    /// Checks that `device` can join the current devices: its ID isn't
+    /// taken, and none of its addresses are inside (or contain) another
+    /// device's addresses.
+    fn check_new_device(&self, device: &ShapedDevice) -> Result<()> {
+        validate(device)?;
+        if self.devices.iter().any(|d| d.device_id == device.device_id) {
+            return Err(Error::msg(format!("Device ID {} is already in use", device.device_id)));
+        }
+        for network in networks(device)? {
+            let existing = self
+                .trie
+                .matches_ipv6(network.network_address())
+                .map(|(_, id)| *id)
+                .chain(
+                    self.trie
+                        .iter_ipv6()
+                        .filter(|(n, _)| network.contains(n.network_address()))
+                        .map(|(_, id)| *id),
+                )
+                .next();
+            if let Some(id) = existing {
+                return Err(Error::msg(format!(
+                    "{} overlaps an address of device {} (circuit {})",
+                    display(network.network_address(), network.netmask() as u32),
+                    self.devices[id].device_id,
+                    self.devices[id].circuit_id,
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn push_device(&mut self, device: ShapedDevice) -> Result<()> {
+        self.check_new_device(&device)?;
+        let id = self.devices.len();
+        for network in networks(&device)? {
+            self.trie.insert(IpNetwork::V6(network), id);
+        }
+        self.devices.push(device);
+        Ok(())
+    }
+
+    /// Applies `change` to a copy of the devices, and keeps the copy
+    /// only if every step succeeded.
+    fn transaction<F: FnOnce(&mut Self) -> Result<T>, T>(&mut self, change: F) -> Result<T> {
+        let mut copy = Self::from_devices(self.devices.clone());
+        let result = change(&mut copy)?;
+        *self = copy;
+        Ok(result)
+    }
+
+    /// Adds devices, which may belong to new or existing circuits.
+    pub fn add_devices(&mut self, devices: Vec<ShapedDevice>) -> Result<()> {
+        self.transaction(|copy| {
+            devices.into_iter().try_for_each(|d| copy.push_device(d))
+        })
+    }
+
+    /// Replaces the device with ID `device_id`. The replacement may have
+    /// a different ID.
+    pub fn replace_device(&mut self, device_id: &str, device: ShapedDevice) -> Result<()> {
+        self.transaction(|copy| {
+            let index = copy.position(device_id)?;
+            copy.devices.remove(index);
+            *copy = Self::from_devices(std::mem::take(&mut copy.devices));
+            copy.push_device(device)?;
+            // Keep the device where it was in the file.
+            let device = copy.devices.pop().unwrap();
+            copy.devices.insert(index, device);
+            *copy = Self::from_devices(std::mem::take(&mut copy.devices));
+            Ok(())
+        })
+    }
+
+    /// Removes a device, returning it.
+    pub fn remove_device(&mut self, device_id: &str) -> Result<ShapedDevice> {
+        let index = self.position(device_id)?;
+        let device = self.devices.remove(index);
+        *self = Self::from_devices(std::mem::take(&mut self.devices));
+        Ok(device)
+    }
+
+    /// Replaces every device of circuit `circuit_id` with `devices`. The
+    /// replacement may have a different circuit ID, but not that of
+    /// another existing circuit.
+    pub fn replace_circuit(&mut self, circuit_id: &str, devices: Vec<ShapedDevice>) -> Result<()> {
+        if let Some(taken) = devices
+            .iter()
+            .map(|d| d.circuit_id.as_str())
+            .find(|id| *id != circuit_id && self.devices.iter().any(|d| d.circuit_id == *id))
+        {
+            return Err(Error::msg(format!("Circuit {taken} already exists")));
+        }
+        self.transaction(|copy| {
+            copy.remove_circuit(circuit_id)?;
+            devices.into_iter().try_for_each(|d| copy.push_device(d))
+        })
+    }
+
+    /// Removes every device of circuit `circuit_id`, returning them.
+    pub fn remove_circuit(&mut self, circuit_id: &str) -> Result<Vec<ShapedDevice>> {
+        let (removed, kept): (Vec<ShapedDevice>, Vec<ShapedDevice>) = std::mem::take(&mut self.devices)
+            .into_iter()
+            .partition(|d| d.circuit_id == circuit_id);
+        *self = Self::from_devices(kept);
+        if removed.is_empty() {
+            return Err(Error::msg(format!("Circuit {circuit_id} does not exist")));
+        }
+        Ok(removed)
+    }
+
+    /// Writes `ShapedDevices.csv`. The new file is written alongside
+    /// the old one, flushed to disk and renamed over it, so neither
+    /// readers nor a crash ever leave a partial file. The previous
+    /// version is kept as `ShapedDevices.csv.backup`.
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        let temporary = path.with_extension("csv.new");
+        {
+            let mut file = std::fs::File::create(&temporary)?;
+            file.write_all(self.to_csv_string()?.as_bytes())?;
+            file.sync_all()?;
+        }
+        if path.exists() {
+            std::fs::copy(&path, path.with_extension("csv.backup"))?;
+        }
+        std::fs::rename(&temporary, &path)?;
+        // Make the rename itself durable.
+        if let Some(directory) = path.parent() {
+            std::fs::File::open(directory)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn device(circuit_id: &str, device_id: &str, ipv4: &str) -> ShapedDevice {
+        ShapedDevice {
+            circuit_id: circuit_id.to_string(),
+            device_id: device_id.to_string(),
+            ipv4: ShapedDevice::parse_ipv4(ipv4),
+            download_max_mbps: 100,
+            upload_max_mbps: 20,
+            ..Default::default()
+        }
+    }
+
+    fn devices() -> ConfigShapedDevices {
+        ConfigShapedDevices::from_devices(vec![
+            device("1", "1a", "100.64.0.1"),
+            device("1", "1b", "100.64.0.2"),
+            device("2", "2a", "100.64.1.0/24"),
+        ])
+    }
+
+    #[test]
+    fn duplicate_addresses_are_rejected() {
+        let mut devices = devices();
+        // Same address, inside an existing subnet, and containing existing addresses.
+        assert!(devices.add_devices(vec![device("3", "3a", "100.64.0.1")]).is_err());
+        assert!(devices.add_devices(vec![device("3", "3a", "100.64.1.9")]).is_err());
+        assert!(devices.add_devices(vec![device("3", "3a", "100.64.0.0/16")]).is_err());
+        // Duplicates within one request.
+        assert!(devices.add_devices(vec![device("3", "3a", "100.64.2.1"), device("3", "3b", "100.64.2.1")]).is_err());
+        assert!(devices.add_devices(vec![device("3", "1a", "100.64.2.1")]).is_err());
+        assert_eq!(devices.devices.len(), 3);
+
+        devices.add_devices(vec![device("3", "3a", "100.64.2.1"), device("3", "3b", "100.64.2.2")]).unwrap();
+        assert_eq!(devices.devices.len(), 5);
+        assert!(devices.trie.longest_match(ShapedDevice::parse_cidr_v4("100.64.2.2").unwrap().0.to_ipv6_mapped()).is_some());
+    }
+
+    #[test]
+    fn replace_and_remove() {
+        let mut devices = devices();
+        // A device may keep its own address.
+        devices.replace_device("1b", device("1", "1b", "100.64.0.2")).unwrap();
+        assert!(devices.replace_device("1b", device("1", "1b", "100.64.0.1")).is_err());
+        devices.replace_device("1b", device("1", "1c", "100.64.0.3")).unwrap();
+        assert_eq!(devices.devices[1].device_id, "1c");
+
+        devices.replace_circuit("2", vec![device("2", "2a", "100.64.1.1")]).unwrap();
+        assert!(devices.replace_circuit("9", vec![device("9", "9a", "100.64.9.1")]).is_err());
+        // A circuit can't take over another circuit's ID.
+        assert!(devices.replace_circuit("2", vec![device("1", "2a", "100.64.1.1")]).is_err());
+        assert_eq!(devices.devices.iter().filter(|d| d.circuit_id == "1").count(), 2);
+        assert_eq!(devices.remove_circuit("1").unwrap().len(), 2);
+        assert_eq!(devices.remove_device("2a").unwrap().circuit_id, "2");
+        assert!(devices.remove_device("2a").is_err());
+        assert!(devices.devices.is_empty());
+        assert_eq!(devices.trie.len(), (0, 0));
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let mut original = device("1", "1a", "100.64.0.1, 100.64.3.0/24");
+        original.circuit_name = "Smith, J.".to_string();
+        original.ipv6 = ShapedDevice::parse_ipv6("fdd7:b724::1, fdd7:b724:0:100::/56");
+        let csv = ConfigShapedDevices::from_devices(vec![original.clone()]).to_csv_string().unwrap();
+        assert!(csv.starts_with("\"Circuit ID\",\"Circuit Name\""));
+
+        let loaded = ConfigShapedDevices::from_csv_reader(csv::Reader::from_reader(csv.as_bytes()));
+        assert_eq!(loaded.devices.len(), 1);
+        assert_eq!(loaded.devices[0].circuit_name, original.circuit_name);
+        assert_eq!(loaded.devices[0].ipv4, original.ipv4);
+        assert_eq!(loaded.devices[0].ipv6, original.ipv6);
+    }
+}
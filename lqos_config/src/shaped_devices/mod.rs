@@ -1,17 +1,32 @@
 mod shaped_device;
 mod serializable;
+mod editing;
 use csv::{WriterBuilder, QuoteStyle};
 pub use shaped_device::ShapedDevice;
-use std::{path::{Path, PathBuf}};
+use std::{path::{Path, PathBuf}, net::IpAddr, collections::BTreeMap};
 use anyhow::Result;
 use crate::etc;
 use serializable::SerializableShapedDevice;
 
+// How many leading bits an unknown address must share with a mapped
+// device before we consider them "nearby" (a /16 for IPv4, /48 for IPv6).
+const MIN_SHARED_PREFIX_V4: u32 = 96 + 16;
+const MIN_SHARED_PREFIX_V6: u32 = 48;
+
 pub struct ConfigShapedDevices {
     pub devices: Vec<ShapedDevice>,
     pub trie: ip_network_table::IpNetworkTable<usize>,
 }
 
+impl Default for ConfigShapedDevices {
+    fn default() -> Self {
+        Self {
+            devices: Vec::new(),
+            trie: ip_network_table::IpNetworkTable::new(),
+        }
+    }
+}
+
 impl ConfigShapedDevices {
     pub fn path() -> Result<PathBuf> {
         let cfg = etc::EtcLqos::load()?;
@@ -21,8 +36,11 @@ impl ConfigShapedDevices {
 
     pub fn load() -> Result<Self> {
         let final_path = ConfigShapedDevices::path()?;
-        let mut reader = csv::Reader::from_path(final_path)?;
-        
+        let reader = csv::Reader::from_path(final_path)?;
+        Ok(Self::from_csv_reader(reader))
+    }
+
+    fn from_csv_reader<R: std::io::Read>(mut reader: csv::Reader<R>) -> Self {
         // Example: StringRecord(["1", "968 Circle St., Gurnee, IL 60031", "1", "Device 1", "", "", "192.168.101.2", "", "25", "5", "10000", "10000", ""])
         let mut devices = Vec::new();
         for result in reader.records() {
@@ -32,8 +50,13 @@ impl ConfigShapedDevices {
                 }
             }
         }
+        Self::from_devices(devices)
+    }
+
+    /// Builds the device list and IP lookup table from a set of devices.
+    pub fn from_devices(devices: Vec<ShapedDevice>) -> Self {
         let trie = ConfigShapedDevices::make_trie(&devices);
-        Ok(Self{ devices, trie })
+        Self{ devices, trie }
     }
 
     fn make_trie(devices: &[ShapedDevice]) -> ip_network_table::IpNetworkTable<usize> {
@@ -66,6 +89,52 @@ impl ConfigShapedDevices {
         Ok(data)
     }
 
+    /// Guesses which parent node an unmapped IP address belongs to, by
+    /// finding the mapped devices whose addresses share the longest
+    /// prefix with it. The most common parent among those devices wins.
+    /// Returns `None` if nothing is close enough to be a useful guess.
+    pub fn guess_parent_node(&self, ip: IpAddr) -> Option<String> {
+        let (target, mut best) = match ip {
+            IpAddr::V4(ip) => (u128::from(ip.to_ipv6_mapped()), MIN_SHARED_PREFIX_V4),
+            IpAddr::V6(ip) => (u128::from(ip), MIN_SHARED_PREFIX_V6),
+        };
+        let mut candidates: Vec<&str> = Vec::new();
+        for device in self.devices.iter().filter(|d| !d.parent_node.is_empty()) {
+            for (address, _) in device.to_ipv6_list() {
+                let shared = (u128::from(address) ^ target).leading_zeros();
+                if shared > best {
+                    best = shared;
+                    candidates.clear();
+                }
+                if shared == best {
+                    candidates.push(&device.parent_node);
+                }
+            }
+        }
+
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        candidates.iter().for_each(|parent| *counts.entry(parent).or_insert(0) += 1);
+        counts
+            .iter()
+            .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0)))
+            .map(|(parent, _)| parent.to_string())
+    }
+
+    /// Builds a pre-filled `ShapedDevice` for an unmapped IP address,
+    /// ready to be completed and added to `ShapedDevices.csv`. The
+    /// parent node is guessed with `guess_parent_node`.
+    pub fn suggest_device(&self, ip: IpAddr) -> ShapedDevice {
+        let mut device = ShapedDevice {
+            parent_node: self.guess_parent_node(ip).unwrap_or_default(),
+            ..Default::default()
+        };
+        match ip {
+            IpAddr::V4(ip) => device.ipv4.push((ip, 32)),
+            IpAddr::V6(ip) => device.ipv6.push((ip, 128)),
+        }
+        device
+    }
+
     pub fn write_csv(&self, filename: &str) -> Result<()> {
         let cfg = etc::EtcLqos::load()?;
         let base_path = Path::new(&cfg.lqos_directory);
@@ -197,4 +266,51 @@ mod test {
         let v6 = addr.to_ipv6_mapped();
         assert!(trie.longest_match(v6).is_some());
     }
+
+    fn parent_test_devices() -> ConfigShapedDevices {
+        let devices = vec![
+            ShapedDevice{
+                parent_node: "AP_A".to_string(),
+                ipv4: ShapedDevice::parse_ipv4("100.64.1.2"),
+                ..Default::default()
+            },
+            ShapedDevice{
+                parent_node: "AP_A".to_string(),
+                ipv4: ShapedDevice::parse_ipv4("100.64.1.3"),
+                ..Default::default()
+            },
+            ShapedDevice{
+                parent_node: "AP_B".to_string(),
+                ipv4: ShapedDevice::parse_ipv4("100.64.2.2"),
+                ipv6: ShapedDevice::parse_ipv6("fd77:1::/64"),
+                ..Default::default()
+            },
+        ];
+        let trie = ConfigShapedDevices::make_trie(&devices);
+        ConfigShapedDevices { devices, trie }
+    }
+
+    #[test]
+    fn guess_parent_from_neighbors() {
+        let cfg = parent_test_devices();
+        assert_eq!(cfg.guess_parent_node("100.64.1.9".parse().unwrap()), Some("AP_A".to_string()));
+        assert_eq!(cfg.guess_parent_node("100.64.2.9".parse().unwrap()), Some("AP_B".to_string()));
+        assert_eq!(cfg.guess_parent_node("fd77:1::5".parse().unwrap()), Some("AP_B".to_string()));
+    }
+
+    #[test]
+    fn no_guess_when_far_away() {
+        let cfg = parent_test_devices();
+        assert!(cfg.guess_parent_node("8.8.8.8".parse().unwrap()).is_none());
+        assert!(cfg.guess_parent_node("2001:db8::1".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn suggest_device_fills_ip_and_parent() {
+        let cfg = parent_test_devices();
+        let device = cfg.suggest_device("100.64.1.9".parse().unwrap());
+        assert_eq!(device.parent_node, "AP_A");
+        assert_eq!(device.ipv4, vec![("100.64.1.9".parse().unwrap(), 32)]);
+        assert!(device.ipv6.is_empty());
+    }
 }
\ No newline at end of file
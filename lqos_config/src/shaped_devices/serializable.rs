@@ -6,18 +6,31 @@ use crate::ShapedDevice;
 // Example: StringRecord(["1", "968 Circle St., Gurnee, IL 60031", "1", "Device 1", "", "", "192.168.101.2", "", "25", "5", "10000", "10000", ""])
 #[derive(Serialize)]
 pub(crate) struct SerializableShapedDevice {
+    #[serde(rename = "Circuit ID")]
     pub circuit_id: String,
+    #[serde(rename = "Circuit Name")]
     pub circuit_name: String,
+    #[serde(rename = "Device ID")]
     pub device_id: String,
+    #[serde(rename = "Device Name")]
     pub device_name: String,
+    #[serde(rename = "Parent Node")]
     pub parent_node: String,
+    #[serde(rename = "MAC")]
     pub mac: String,
+    #[serde(rename = "IPv4")]
     pub ipv4: String,
+    #[serde(rename = "IPv6")]
     pub ipv6: String,
+    #[serde(rename = "Download Min Mbps")]
     pub download_min_mbps: u32,
+    #[serde(rename = "Upload Min Mbps")]
     pub upload_min_mbps: u32,
+    #[serde(rename = "Download Max Mbps")]
     pub download_max_mbps: u32,
+    #[serde(rename = "Upload Max Mbps")]
     pub upload_max_mbps: u32,
+    #[serde(rename = "Comment")]
     pub comment: String,
 }
 
@@ -50,22 +63,11 @@ fn ipv4_to_string(ip: &(Ipv4Addr, u32)) -> String {
 }
 
 fn ipv4_list_to_string(ips: &[(Ipv4Addr, u32)]) -> String {
-    if ips.len() == 0 {
-        return String::new();
-    }
-    if ips.len() == 1 {
-        return ipv4_to_string(&ips[0]);
-    }
-    let mut buffer = String::new();
-    for i in 0..ips.len()-1 {
-        buffer += &format!("{}, ", ipv4_to_string(&ips[i]));
-    }
-    buffer += &ipv4_to_string(&ips[ips.len()-1]);
-    String::new()
+    ips.iter().map(ipv4_to_string).collect::<Vec<String>>().join(", ")
 }
 
 fn ipv6_to_string(ip: &(Ipv6Addr, u32)) -> String {
-    if ip.1 == 32 {
+    if ip.1 == 128 {
         format!("{}", ip.0)
     } else {
         format!{"{}/{}", ip.0, ip.1}
@@ -73,16 +75,5 @@ fn ipv6_to_string(ip: &(Ipv6Addr, u32)) -> String {
 }
 
 fn ipv6_list_to_string(ips: &[(Ipv6Addr, u32)]) -> String {
-    if ips.len() == 0 {
-        return String::new();
-    }
-    if ips.len() == 1 {
-        return ipv6_to_string(&ips[0]);
-    }
-    let mut buffer = String::new();
-    for i in 0..ips.len()-1 {
-        buffer += &format!("{}, ", ipv6_to_string(&ips[i]));
-    }
-    buffer += &ipv6_to_string(&ips[ips.len()-1]);
-    String::new()
-}
\ No newline at end of file
+    ips.iter().map(ipv6_to_string).collect::<Vec<String>>().join(", ")
+}
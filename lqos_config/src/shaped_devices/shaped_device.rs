@@ -78,7 +78,7 @@ impl ShapedDevice {
         }
     }
 
-    pub(crate) fn parse_ipv4(str: &str) -> Vec<(Ipv4Addr, u32)> {
+    pub fn parse_ipv4(str: &str) -> Vec<(Ipv4Addr, u32)> {
         let mut result = Vec::new();
         if str.contains(",") {
             for ip in str.split(",") {
@@ -115,7 +115,7 @@ impl ShapedDevice {
         }
     }
 
-    pub(crate) fn parse_ipv6(str: &str) -> Vec<(Ipv6Addr, u32)> {
+    pub fn parse_ipv6(str: &str) -> Vec<(Ipv6Addr, u32)> {
         let mut result = Vec::new();
         if str.contains(",") {
             for ip in str.split(",") {
@@ -134,7 +134,9 @@ impl ShapedDevice {
         result
     }
 
-    pub(crate) fn to_ipv6_list(&self) -> Vec<(Ipv6Addr, u32)> {
+    /// All of the device's addresses as IPv6 subnets, with IPv4
+    /// addresses mapped into `::ffff:0:0/96`.
+    pub fn to_ipv6_list(&self) -> Vec<(Ipv6Addr, u32)> {
         let mut result = Vec::new();
 
         for (ipv4, cidr) in &self.ipv4 {
@@ -0,0 +1,120 @@
+use std::net::IpAddr;
+use ip_network::IpNetwork;
+use ip_network_table::IpNetworkTable;
+
+/// A list of configured subnets (for example `allowedSubnets` from
+/// `ispConfig.py`), stored in a trie for fast membership tests.
+///
+/// IPv4 subnets are stored as IPv6-mapped networks, matching the
+/// way `ConfigShapedDevices` builds its trie.
+pub struct SubnetMatcher {
+    subnets: Vec<String>,
+    trie: IpNetworkTable<usize>,
+}
+
+impl SubnetMatcher {
+    /// Builds a matcher from a list of CIDR strings (e.g. `100.64.0.0/10`).
+    /// Entries that cannot be parsed are skipped.
+    pub fn new(subnets: &[String]) -> Self {
+        let mut result = Self {
+            subnets: Vec::new(),
+            trie: IpNetworkTable::new(),
+        };
+        for subnet in subnets.iter() {
+            if let Some(network) = parse_network(subnet) {
+                result.trie.insert(network, result.subnets.len());
+                result.subnets.push(subnet.trim().to_string());
+            }
+        }
+        result
+    }
+
+    /// Returns the configured subnet containing `ip`, if there is one.
+    /// The most specific match wins.
+    pub fn find(&self, ip: IpAddr) -> Option<&str> {
+        self.trie
+            .longest_match(to_ipv6(ip))
+            .map(|(_, idx)| self.subnets[*idx].as_str())
+    }
+
+    /// Returns true if `ip` falls inside any of the configured subnets.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.find(ip).is_some()
+    }
+
+    /// Returns true if no subnets were configured (or none could be parsed).
+    pub fn is_empty(&self) -> bool {
+        self.subnets.is_empty()
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> std::net::Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+fn parse_network(subnet: &str) -> Option<IpNetwork> {
+    let subnet = subnet.trim();
+    let (address, prefix) = if let Some((address, prefix)) = subnet.split_once('/') {
+        (address, Some(prefix.parse::<u8>().ok()?))
+    } else {
+        (subnet, None)
+    };
+    match address.parse::<IpAddr>().ok()? {
+        IpAddr::V4(ip) => {
+            let prefix = prefix.unwrap_or(32);
+            if prefix > 32 {
+                return None;
+            }
+            IpNetwork::new_truncate(ip.to_ipv6_mapped(), prefix + 96).ok()
+        }
+        IpAddr::V6(ip) => IpNetwork::new_truncate(ip, prefix.unwrap_or(128)).ok(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn empty_matcher() {
+        let m = SubnetMatcher::new(&[]);
+        assert!(m.is_empty());
+        assert!(!m.contains("1.2.3.4".parse().unwrap()));
+    }
+
+    #[test]
+    fn ipv4_match() {
+        let m = SubnetMatcher::new(&["100.64.0.0/10".to_string()]);
+        assert_eq!(m.find("100.72.1.2".parse().unwrap()), Some("100.64.0.0/10"));
+        assert!(!m.contains("8.8.8.8".parse().unwrap()));
+    }
+
+    #[test]
+    fn ipv4_host_bits_are_ignored() {
+        let m = SubnetMatcher::new(&["192.168.1.17/24".to_string()]);
+        assert!(m.contains("192.168.1.200".parse().unwrap()));
+    }
+
+    #[test]
+    fn ipv6_match() {
+        let m = SubnetMatcher::new(&["fd77::/16".to_string()]);
+        assert!(m.contains("fd77::1:5".parse().unwrap()));
+        assert!(!m.contains("fd78::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn most_specific_wins() {
+        let m = SubnetMatcher::new(&["10.0.0.0/8".to_string(), "10.1.0.0/16".to_string()]);
+        assert_eq!(m.find("10.1.2.3".parse().unwrap()), Some("10.1.0.0/16"));
+        assert_eq!(m.find("10.2.2.3".parse().unwrap()), Some("10.0.0.0/8"));
+    }
+
+    #[test]
+    fn bad_entries_are_skipped() {
+        let m = SubnetMatcher::new(&["bad wolf".to_string(), "1.2.3.0/33".to_string()]);
+        assert!(m.is_empty());
+    }
+}
@@ -0,0 +1,98 @@
+//! Errors from the JSON API. Every failed call is answered with a status
+//! code and a body of the form `{"error": "..."}`, so scripts (and
+//! people) can tell what went wrong.
+
+use std::fmt;
+use rocket::http::Status;
+use rocket::response::{self, status, Responder};
+use rocket::serde::{json::Json, Serialize};
+use rocket::Request;
+use crate::cache_control::NoCache;
+
+/// A failed API call.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: Status,
+    pub message: String,
+}
+
+/// The result of an API call that returns JSON.
+pub type ApiResult<T> = Result<NoCache<Json<T>>, ApiError>;
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ErrorBody {
+    pub error: String,
+}
+
+impl ApiError {
+    pub fn new(status: Status, message: impl Into<String>) -> Self {
+        Self { status, message: message.into() }
+    }
+
+    /// The request was invalid, e.g. a rejected edit.
+    pub fn bad_request(e: impl fmt::Display) -> Self {
+        Self::new(Status::BadRequest, e.to_string())
+    }
+
+    /// The thing the call names doesn't exist.
+    pub fn not_found(e: impl fmt::Display) -> Self {
+        Self::new(Status::NotFound, e.to_string())
+    }
+
+    /// The node manager itself failed, e.g. to save a file.
+    pub fn internal(e: impl fmt::Display) -> Self {
+        Self::new(Status::InternalServerError, e.to_string())
+    }
+
+    /// `lqosd` couldn't be reached.
+    pub fn lqosd_unavailable(e: impl fmt::Display) -> Self {
+        Self::new(Status::ServiceUnavailable, format!("Unable to reach lqosd: {e}"))
+    }
+
+    /// `lqosd` answered, but not with what we asked for.
+    pub fn bad_reply(e: impl fmt::Display) -> Self {
+        Self::new(Status::BadGateway, format!("Invalid reply from lqosd: {e}"))
+    }
+
+    /// `lqosd` reported that the request failed.
+    pub fn lqosd_failed(e: impl fmt::Display) -> Self {
+        Self::new(Status::InternalServerError, e.to_string())
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.status)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        NoCache::new(status::Custom(self.status, Json(ErrorBody { error: self.message }))).respond_to(request)
+    }
+}
+
+/// Why a request guard refused a request, kept so the catcher can
+/// explain it.
+pub struct GuardFailure(pub Option<&'static str>);
+
+/// Records why a guard refused `request`.
+pub fn guard_failed(request: &Request<'_>, reason: &'static str) {
+    request.local_cache(|| GuardFailure(Some(reason)));
+}
+
+/// Answers every failed API call that didn't produce its own error
+/// (a missing login, a bad CSRF token, an unknown route, a malformed
+/// body...) in the same JSON form. `lqos.js` turns a 401 into a trip to
+/// the login page.
+#[catch(default)]
+pub fn api_error(status: Status, request: &Request) -> ApiError {
+    let message = match request.local_cache(|| GuardFailure(None)).0 {
+        Some(reason) => reason.to_string(),
+        None => status.reason().unwrap_or("Error").to_string(),
+    };
+    ApiError::new(status, message)
+}
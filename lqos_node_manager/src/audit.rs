@@ -0,0 +1,58 @@
+//! Records the node manager's edits in `lqosd`'s audit log, and shows
+//! the most recent entries to administrators.
+
+use lqos_bus::{bounded_parameters, AuditEntry, BusResponse, BusRequest};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use crate::api_error::{ApiError, ApiResult};
+use crate::auth_guard::{AuthenticatedUser, AdminUser};
+use crate::bus::{bus_request_one, unexpected};
+use crate::cache_control::NoCache;
+
+/// Records a change made by `user`, and passes on its `result`.
+/// `parameters` is JSON describing the change. If a change was made
+/// but can't be recorded, the caller gets an error saying so.
+pub async fn record_change<T>(
+    user: &AuthenticatedUser,
+    change: &str,
+    parameters: String,
+    result: Result<T, ApiError>,
+) -> Result<T, ApiError> {
+    let outcome = match &result {
+        Ok(_) => "ok".to_string(),
+        Err(e) => format!("failed: {}", e.message),
+    };
+    let request = BusRequest::RecordConfigChange {
+        change: change.to_string(),
+        parameters: bounded_parameters(parameters),
+        result: outcome,
+    };
+    let recorded = match bus_request_one(user.bus_client(), request).await {
+        Ok(BusResponse::Ack) => Ok(()),
+        Ok(other) => Err(unexpected(other)),
+        Err(e) => Err(e),
+    };
+    match (result, recorded) {
+        (Ok(_), Err(e)) => {
+            println!("Unable to record {change} in the audit log: {e}");
+            Err(ApiError::new(
+                Status::InternalServerError,
+                format!("The change was made, but couldn't be recorded in the audit log: {}", e.message),
+            ))
+        }
+        (result, Err(e)) => {
+            println!("Unable to record {change} in the audit log: {e}");
+            result
+        }
+        (result, Ok(())) => result,
+    }
+}
+
+/// The `n` most recent audit log entries, newest first.
+#[get("/api/audit_log/<n>")]
+pub async fn audit_log(admin: AdminUser, n: u32) -> ApiResult<Vec<AuditEntry>> {
+    match bus_request_one(admin.0.bus_client(), BusRequest::AuditLog(n)).await? {
+        BusResponse::AuditLog(entries) => Ok(NoCache::new(Json(entries))),
+        other => Err(unexpected(other)),
+    }
+}
@@ -0,0 +1,367 @@
+//! Logins, sessions and the request guards that protect every page and
+//! API route.
+//!
+//! Sessions live in memory, so restarting the node manager logs
+//! everyone out. The session token is kept in an `HttpOnly` cookie. A
+//! second, script-readable cookie holds the session's CSRF token, which
+//! `lqos.js` sends back in the `X-CSRF-Token` header. Requests other
+//! than `GET` are refused without it.
+//!
+//! Creating the first administrator needs a one-time setup token, which
+//! is printed to the log while there are no users. Addresses that keep
+//! failing to log in have to wait longer and longer between attempts.
+
+use std::{collections::HashMap, net::{IpAddr, SocketAddr}, time::{Duration, Instant}};
+use lazy_static::*;
+use lqos_config::{UserRole, WebUsers};
+use parking_lot::{Mutex, RwLock};
+use rand::Rng;
+use rocket::{
+    http::{Cookie, CookieJar, Method, SameSite, Status},
+    outcome::Outcome,
+    request::{self, FromRequest},
+    response::Redirect,
+    serde::{json::{json, Json}, Deserialize, Serialize},
+    Request,
+};
+use crate::api_error::{guard_failed, ApiError, ApiResult};
+use crate::audit::record_change;
+use crate::cache_control::NoCache;
+
+const SESSION_COOKIE: &str = "lqos_session";
+const CSRF_COOKIE: &str = "lqos_csrf";
+const CSRF_HEADER: &str = "X-CSRF-Token";
+const SESSION_LIFETIME: Duration = Duration::from_secs(12 * 60 * 60);
+
+struct Session {
+    username: String,
+    role: UserRole,
+    csrf_token: String,
+    expires: Instant,
+}
+
+/// Failed logins allowed from one address before it has to wait.
+const FREE_LOGIN_ATTEMPTS: u32 = 5;
+const MAX_LOGIN_BACKOFF: Duration = Duration::from_secs(5 * 60);
+/// Failures are forgotten after this long without another.
+const FAILED_LOGIN_MEMORY: Duration = Duration::from_secs(15 * 60);
+
+struct FailedLogins {
+    count: u32,
+    last: Instant,
+}
+
+lazy_static! {
+    static ref SESSIONS: RwLock<HashMap<String, Session>> = RwLock::new(HashMap::new());
+    static ref FAILED_LOGINS: Mutex<HashMap<IpAddr, FailedLogins>> = Mutex::new(HashMap::new());
+    /// Held while the users file is read, changed and written back, so
+    /// that concurrent changes can't overwrite (or race) each other.
+    static ref USERS_FILE: Mutex<()> = Mutex::new(());
+    static ref SETUP_TOKEN: Mutex<Option<String>> = Mutex::new(None);
+}
+
+fn random_token() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Compares tokens in constant time.
+fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// A logged-in user. Use as a guard on any route that needs a login.
+pub struct AuthenticatedUser {
+    pub username: String,
+    pub role: UserRole,
+}
+
+impl AuthenticatedUser {
+    /// Identifies the user to `lqosd`, for its audit log.
+    pub fn bus_client(&self) -> Option<String> {
+        Some(format!("web:{}", self.username))
+    }
+}
+
+/// A logged-in administrator. Use as a guard on routes that change
+/// anything.
+pub struct AdminUser(pub AuthenticatedUser);
+
+/// Refuses a request, noting why for the API's error catcher.
+fn refuse<T>(request: &Request<'_>, status: Status, reason: &'static str) -> request::Outcome<T, &'static str> {
+    guard_failed(request, reason);
+    Outcome::Failure((status, reason))
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthenticatedUser {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let Some(token) = request.cookies().get(SESSION_COOKIE).map(|c| c.value().to_string()) else {
+            return refuse(request, Status::Unauthorized, "Not logged in");
+        };
+        let mut sessions = SESSIONS.write();
+        sessions.retain(|_, s| s.expires > Instant::now());
+        let Some(session) = sessions.get(&token) else {
+            return refuse(request, Status::Unauthorized, "Session expired");
+        };
+        if !matches!(request.method(), Method::Get | Method::Head) {
+            let header = request.headers().get_one(CSRF_HEADER).unwrap_or_default();
+            if !tokens_match(header, &session.csrf_token) {
+                return refuse(request, Status::Forbidden, "Missing or invalid CSRF token");
+            }
+        }
+        Outcome::Success(AuthenticatedUser {
+            username: session.username.clone(),
+            role: session.role,
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminUser {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match AuthenticatedUser::from_request(request).await {
+            Outcome::Success(user) if user.role == UserRole::Admin => Outcome::Success(AdminUser(user)),
+            Outcome::Success(_) => refuse(request, Status::Forbidden, "Administrators only"),
+            Outcome::Failure(e) => Outcome::Failure(e),
+            Outcome::Forward(f) => Outcome::Forward(f),
+        }
+    }
+}
+
+/// Sends browsers that aren't logged in to the login page.
+#[catch(401)]
+pub fn redirect_to_login() -> Redirect {
+    Redirect::to("/login")
+}
+
+fn start_session(cookies: &CookieJar<'_>, username: &str, role: UserRole) {
+    let token = random_token();
+    let csrf_token = random_token();
+    SESSIONS.write().insert(token.clone(), Session {
+        username: username.to_string(),
+        role,
+        csrf_token: csrf_token.clone(),
+        expires: Instant::now() + SESSION_LIFETIME,
+    });
+    cookies.add(Cookie::build(SESSION_COOKIE, token).path("/").http_only(true).same_site(SameSite::Strict).finish());
+    cookies.add(Cookie::build(CSRF_COOKIE, csrf_token).path("/").same_site(SameSite::Strict).finish());
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Login {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct FirstUser {
+    pub username: String,
+    pub password: String,
+    /// The token printed in the node manager's log.
+    pub setup_token: String,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct WhoAmI {
+    pub username: String,
+    pub role: UserRole,
+}
+
+type AuthResult = ApiResult<WhoAmI>;
+
+fn bad_request(e: anyhow::Error) -> ApiError {
+    ApiError::bad_request(e)
+}
+
+/// How long an address must wait after `failures` failed logins.
+fn login_backoff(failures: u32) -> Duration {
+    if failures < FREE_LOGIN_ATTEMPTS {
+        return Duration::ZERO;
+    }
+    let doublings = (failures - FREE_LOGIN_ATTEMPTS).min(16);
+    Duration::from_secs(1 << doublings).min(MAX_LOGIN_BACKOFF)
+}
+
+/// Refuses a login attempt from `ip` until its backoff has passed.
+fn check_login_backoff(ip: IpAddr) -> Result<(), ApiError> {
+    let mut failed = FAILED_LOGINS.lock();
+    failed.retain(|_, f| f.last.elapsed() < FAILED_LOGIN_MEMORY);
+    if let Some(f) = failed.get(&ip) {
+        let wait = login_backoff(f.count).saturating_sub(f.last.elapsed());
+        if !wait.is_zero() {
+            let message = format!("Too many failed logins, wait {}s and try again", wait.as_secs() + 1);
+            return Err(ApiError::new(Status::TooManyRequests, message));
+        }
+    }
+    Ok(())
+}
+
+fn record_login(ip: IpAddr, succeeded: bool) {
+    let mut failed = FAILED_LOGINS.lock();
+    if succeeded {
+        failed.remove(&ip);
+    } else {
+        let entry = failed.entry(ip).or_insert(FailedLogins { count: 0, last: Instant::now() });
+        entry.count += 1;
+        entry.last = Instant::now();
+    }
+}
+
+#[post("/api/login", data = "<login>")]
+pub fn login(remote: SocketAddr, cookies: &CookieJar<'_>, login: Json<Login>) -> AuthResult {
+    check_login_backoff(remote.ip())?;
+    let users = WebUsers::load().map_err(bad_request)?;
+    let role = users.authenticate(&login.username, &login.password);
+    record_login(remote.ip(), role.is_some());
+    match role {
+        Some(role) => {
+            start_session(cookies, &login.username, role);
+            Ok(NoCache::new(Json(WhoAmI { username: login.username.clone(), role })))
+        }
+        None => Err(ApiError::new(Status::Unauthorized, "Invalid username or password")),
+    }
+}
+
+#[post("/api/logout")]
+pub fn logout(_user: AuthenticatedUser, cookies: &CookieJar<'_>) -> NoCache<Json<bool>> {
+    if let Some(token) = cookies.get(SESSION_COOKIE) {
+        SESSIONS.write().remove(token.value());
+    }
+    cookies.remove(Cookie::named(SESSION_COOKIE));
+    cookies.remove(Cookie::named(CSRF_COOKIE));
+    NoCache::new(Json(true))
+}
+
+#[get("/api/whoami")]
+pub fn whoami(user: AuthenticatedUser) -> NoCache<Json<WhoAmI>> {
+    NoCache::new(Json(WhoAmI { username: user.username, role: user.role }))
+}
+
+/// The token needed to create the first administrator. It is made (and
+/// logged) when first needed, so only someone who can read the node
+/// manager's log can claim a fresh install.
+fn setup_token() -> String {
+    SETUP_TOKEN
+        .lock()
+        .get_or_insert_with(|| {
+            let token = random_token();
+            println!("There are no node manager users. Create the first administrator at /login with the setup token {token}");
+            token
+        })
+        .clone()
+}
+
+/// True (and logs the setup token) if there are no users yet.
+pub fn first_run_pending() -> bool {
+    let pending = WebUsers::load().map(|u| u.is_empty()).unwrap_or(false);
+    if pending {
+        setup_token();
+    }
+    pending
+}
+
+/// True until the first administrator has been created.
+#[get("/api/first_run")]
+pub fn first_run() -> NoCache<Json<bool>> {
+    NoCache::new(Json(first_run_pending()))
+}
+
+/// Creates the first administrator and logs them in. Only allowed
+/// while there are no users, and with the setup token from the log.
+#[post("/api/first_user", data = "<first>")]
+pub fn first_user(remote: SocketAddr, cookies: &CookieJar<'_>, first: Json<FirstUser>) -> AuthResult {
+    check_login_backoff(remote.ip())?;
+    let _lock = USERS_FILE.lock();
+    let mut users = WebUsers::load().map_err(bad_request)?;
+    if !users.is_empty() {
+        return Err(ApiError::new(Status::Forbidden, "An administrator already exists"));
+    }
+    let token_ok = tokens_match(first.setup_token.trim(), &setup_token());
+    record_login(remote.ip(), token_ok);
+    if !token_ok {
+        return Err(ApiError::new(Status::Unauthorized, "Invalid setup token; it is printed in the node manager's log"));
+    }
+    users.add_or_update_user(&first.username, &first.password, UserRole::Admin).map_err(bad_request)?;
+    users.save().map_err(bad_request)?;
+    *SETUP_TOKEN.lock() = None;
+    let username = first.username.trim().to_string();
+    start_session(cookies, &username, UserRole::Admin);
+    Ok(NoCache::new(Json(WhoAmI { username, role: UserRole::Admin })))
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewUser {
+    pub username: String,
+    pub password: String,
+    pub role: UserRole,
+}
+
+#[get("/api/users")]
+pub fn list_users(_admin: AdminUser) -> ApiResult<Vec<WhoAmI>> {
+    let users = WebUsers::load().map_err(bad_request)?;
+    Ok(NoCache::new(Json(
+        users.list().into_iter().map(|(username, role)| WhoAmI { username, role }).collect(),
+    )))
+}
+
+type UserResult = ApiResult<bool>;
+
+/// Refuses a change that would leave nobody able to manage the node
+/// manager.
+fn keep_an_admin(users: &WebUsers) -> Result<(), ApiError> {
+    match users.has_admin() {
+        true => Ok(()),
+        false => Err(ApiError::bad_request("There must be at least one administrator")),
+    }
+}
+
+fn save_user(user: &NewUser) -> UserResult {
+    let _lock = USERS_FILE.lock();
+    let mut users = WebUsers::load().map_err(bad_request)?;
+    users.add_or_update_user(&user.username, &user.password, user.role).map_err(bad_request)?;
+    keep_an_admin(&users)?;
+    users.save().map_err(bad_request)?;
+    let username = user.username.trim();
+    SESSIONS.write().retain(|_, s| s.username != username);
+    Ok(NoCache::new(Json(true)))
+}
+
+/// Adds a user, or changes an existing user's password and role. The
+/// user's existing sessions are ended. The last administrator can't be
+/// demoted.
+#[post("/api/users", data = "<user>")]
+pub async fn add_user(admin: AdminUser, user: Json<NewUser>) -> UserResult {
+    let result = save_user(&user);
+    let parameters = json!({ "username": user.username.trim(), "role": user.role }).to_string();
+    record_change(&admin.0, "SaveUser", parameters, result).await
+}
+
+fn remove_user(admin: &AdminUser, username: &str) -> UserResult {
+    if admin.0.username == username {
+        return Err(ApiError::bad_request("You can't remove yourself"));
+    }
+    let _lock = USERS_FILE.lock();
+    let mut users = WebUsers::load().map_err(bad_request)?;
+    users.remove_user(username).map_err(bad_request)?;
+    keep_an_admin(&users)?;
+    users.save().map_err(bad_request)?;
+    SESSIONS.write().retain(|_, s| s.username != username);
+    Ok(NoCache::new(Json(true)))
+}
+
+/// Removes a user and ends their sessions. Administrators can't remove
+/// themselves, or the last administrator.
+#[delete("/api/users/<username>")]
+pub async fn delete_user(admin: AdminUser, username: String) -> UserResult {
+    let result = remove_user(&admin, &username);
+    record_change(&admin.0, "DeleteUser", json!({ "username": username }).to_string(), result).await
+}
@@ -0,0 +1,50 @@
+//! Talks to `lqosd` over the local bus.
+
+use lazy_static::*;
+use lqos_bus::{BusResponse, BusSession, BusRequest, bus_address, encode_request, decode_response};
+use rocket::tokio::io::{AsyncWriteExt, AsyncReadExt};
+use rocket::tokio::net::TcpStream;
+use crate::api_error::ApiError;
+
+lazy_static! {
+    /// Read once, since `lqosd` must be restarted to change it.
+    static ref BUS_ADDRESS: String = bus_address();
+}
+
+/// Sends `requests` to `lqosd` and returns its responses, one per
+/// request. `client` identifies the user to `lqosd`'s audit log.
+pub async fn bus_request(client: Option<String>, requests: Vec<BusRequest>) -> Result<Vec<BusResponse>, ApiError> {
+    let expected = requests.len();
+    let mut stream = TcpStream::connect(BUS_ADDRESS.as_str()).await.map_err(ApiError::lqosd_unavailable)?;
+    let test = BusSession {
+        auth_cookie: 1234,
+        client,
+        requests,
+    };
+    let msg = encode_request(&test).map_err(ApiError::bad_request)?;
+    stream.write_all(&msg).await.map_err(ApiError::lqosd_unavailable)?;
+
+    // Receive reply
+    let mut buf = Vec::new();
+    stream.read_to_end(&mut buf).await.map_err(ApiError::lqosd_unavailable)?;
+    let reply = decode_response(&buf).map_err(ApiError::bad_reply)?;
+    if reply.responses.len() != expected {
+        return Err(ApiError::bad_reply(format!("expected {expected} responses, got {}", reply.responses.len())));
+    }
+    Ok(reply.responses)
+}
+
+/// Sends a single request to `lqosd`. A `Fail` response becomes an
+/// error.
+pub async fn bus_request_one(client: Option<String>, request: BusRequest) -> Result<BusResponse, ApiError> {
+    match bus_request(client, vec![request]).await?.pop() {
+        Some(BusResponse::Fail(e)) => Err(ApiError::lqosd_failed(e)),
+        Some(response) => Ok(response),
+        None => Err(ApiError::bad_reply("no response")),
+    }
+}
+
+/// The error for a response of the wrong kind.
+pub fn unexpected(response: BusResponse) -> ApiError {
+    ApiError::bad_reply(format!("unexpected response {response:?}"))
+}
@@ -0,0 +1,108 @@
+//! Everything the circuit page shows about one circuit: its plan, parents
+//! and devices from `lqosd`, live throughput and RTT for each of its
+//! hosts, and its queues' CAKE statistics.
+
+use std::net::IpAddr;
+use lqos_bus::{BusRequest, BusResponse, CircuitInfo, CircuitQuery, IpStats, QueueStats, ShapedDevice, TcHandle};
+use rocket::http::Status;
+use rocket::serde::{json::Json, Serialize};
+use crate::api_error::{ApiError, ApiResult};
+use crate::auth_guard::AuthenticatedUser;
+use crate::bus::{bus_request_one, unexpected};
+use crate::cache_control::NoCache;
+use crate::query::to_ipv6;
+use crate::tracker::SHAPED_DEVICES;
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CircuitDashboard {
+    pub circuit: CircuitSummary,
+    /// The circuit's hosts, busiest first, with the device each belongs to.
+    pub hosts: Vec<HostRow>,
+}
+
+/// `CircuitInfo` without its hosts, which are listed once, in
+/// `CircuitDashboard::hosts`.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CircuitSummary {
+    pub circuit_id: String,
+    pub circuit_name: String,
+    pub parent_node: String,
+    pub parents: Vec<String>,
+    pub devices: Vec<ShapedDevice>,
+    pub plan_min_mbps: (u32, u32),
+    pub plan_max_mbps: (u32, u32),
+    pub tc_handles: Option<(TcHandle, TcHandle)>,
+    pub bits_per_second: (u64, u64),
+    pub median_rtt_ms: f32,
+    pub queues: Option<(QueueStats, QueueStats)>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct HostRow {
+    pub device_id: Option<String>,
+    pub device_name: Option<String>,
+    #[serde(flatten)]
+    pub stats: IpStats,
+}
+
+#[get("/api/circuit/<circuit_id>")]
+pub async fn circuit_dashboard(_user: AuthenticatedUser, circuit_id: String) -> ApiResult<CircuitDashboard> {
+    let request = BusRequest::FindCircuits(CircuitQuery::Id(circuit_id.clone()));
+    let circuit = match bus_request_one(None, request).await? {
+        BusResponse::Circuits(circuits) => circuits.into_iter().next().ok_or_else(|| {
+            ApiError::new(Status::NotFound, format!("No circuit has the ID {circuit_id}"))
+        })?,
+        other => return Err(unexpected(other)),
+    };
+    let CircuitInfo {
+        circuit_id,
+        circuit_name,
+        parent_node,
+        parents,
+        devices,
+        plan_min_mbps,
+        plan_max_mbps,
+        tc_handles,
+        bits_per_second,
+        median_rtt_ms,
+        hosts,
+        queues,
+    } = circuit;
+
+    let hosts = {
+        let shaped = SHAPED_DEVICES.read();
+        hosts
+            .into_iter()
+            .map(|stats| {
+                let device = stats
+                    .ip_address
+                    .parse::<IpAddr>()
+                    .ok()
+                    .and_then(|ip| shaped.trie.longest_match(to_ipv6(ip)))
+                    .map(|(_, id)| &shaped.devices[*id]);
+                HostRow {
+                    device_id: device.map(|d| d.device_id.clone()),
+                    device_name: device.map(|d| d.device_name.clone()),
+                    stats,
+                }
+            })
+            .collect()
+    };
+    let circuit = CircuitSummary {
+        circuit_id,
+        circuit_name,
+        parent_node,
+        parents,
+        devices,
+        plan_min_mbps,
+        plan_max_mbps,
+        tc_handles,
+        bits_per_second,
+        median_rtt_ms,
+        queues,
+    };
+    Ok(NoCache::new(Json(CircuitDashboard { circuit, hosts })))
+}
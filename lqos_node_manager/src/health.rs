@@ -0,0 +1,65 @@
+//! `/api/health`, for monitoring. It needs no login, and answers 200 if
+//! everything is working or 503 (with the same details) if not.
+
+use std::time::{Duration, UNIX_EPOCH};
+use lqos_bus::{BusRequest, BusResponse};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::{json::Json, Serialize};
+use rocket::tokio::time::timeout;
+use crate::bus::bus_request_one;
+use crate::cache_control::NoCache;
+use crate::tracker::{LAST_SUCCESSFUL_POLL, SHAPED_DEVICES, SHAPED_DEVICES_ERROR};
+
+/// How long to wait for `lqosd` to answer a ping.
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Statistics older than this mean polling has stopped working.
+const STALE_POLL: Duration = Duration::from_secs(10);
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Health {
+    /// True if `lqosd` answered a ping.
+    pub lqosd_reachable: bool,
+    /// Why `lqosd` didn't answer, if it didn't.
+    pub lqosd_error: Option<String>,
+    /// When statistics were last fetched from `lqosd`, in seconds since
+    /// the UNIX epoch.
+    pub last_poll: Option<u64>,
+    pub seconds_since_last_poll: Option<u64>,
+    /// True if `ShapedDevices.csv` loaded the last time it was read.
+    pub shaped_devices_loaded: bool,
+    pub shaped_devices_error: Option<String>,
+    pub shaped_devices: usize,
+}
+
+#[get("/api/health")]
+pub async fn health() -> NoCache<status::Custom<Json<Health>>> {
+    let lqosd_error = match timeout(PING_TIMEOUT, bus_request_one(None, BusRequest::Ping)).await {
+        Ok(Ok(BusResponse::Ack)) => None,
+        Ok(Ok(other)) => Some(format!("Unexpected response {other:?}")),
+        Ok(Err(e)) => Some(e.message),
+        Err(_) => Some("Timed out waiting for lqosd".to_string()),
+    };
+    let last_poll = *LAST_SUCCESSFUL_POLL.read();
+    let since_last_poll = last_poll.and_then(|t| t.elapsed().ok());
+    // Reading the devices first makes sure they have been loaded.
+    let shaped_devices = SHAPED_DEVICES.read().devices.len();
+    let shaped_devices_error = SHAPED_DEVICES_ERROR.read().clone();
+
+    let healthy = lqosd_error.is_none()
+        && since_last_poll.map(|d| d < STALE_POLL).unwrap_or(false)
+        && shaped_devices_error.is_none();
+    let health = Health {
+        lqosd_reachable: lqosd_error.is_none(),
+        lqosd_error,
+        last_poll: last_poll.and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()),
+        seconds_since_last_poll: since_last_poll.map(|d| d.as_secs()),
+        shaped_devices_loaded: shaped_devices_error.is_none(),
+        shaped_devices_error,
+        shaped_devices,
+    };
+    let status = if healthy { Status::Ok } else { Status::ServiceUnavailable };
+    NoCache::new(status::Custom(status, Json(health)))
+}
@@ -1,5 +1,11 @@
 #[macro_use] extern crate rocket;
 use rocket::fairing::AdHoc;
+mod api_error;
+mod audit;
+mod auth_guard;
+mod bus;
+mod circuit;
+mod health;
 mod static_pages;
 mod tracker;
 mod shaped_devices;
@@ -7,6 +13,8 @@ mod unknown_devices;
 mod cache_control;
 use rocket_async_compression::Compression;
 mod queue_info;
+mod protocols;
+mod query;
 
 #[launch]
 fn rocket() -> _ {
@@ -17,8 +25,16 @@ fn rocket() -> _ {
                 rocket::tokio::spawn(tracker::update_tracking());
             })
         }))
+        .attach(AdHoc::on_liftoff("Setup token", |_| {
+            Box::pin(async move {
+                auth_guard::first_run_pending();
+            })
+        }))
+        .register("/", catchers![auth_guard::redirect_to_login])
+        .register("/api", catchers![api_error::api_error])
         .mount("/", routes![
             static_pages::index,
+            static_pages::login_page,
             static_pages::shaped_devices_csv_page,
             static_pages::shaped_devices_add_page,
             static_pages::unknown_devices_page,
@@ -28,6 +44,16 @@ fn rocket() -> _ {
             static_pages::lqos_js,
 
             // API calls
+            auth_guard::login,
+            auth_guard::logout,
+            auth_guard::whoami,
+            auth_guard::first_run,
+            auth_guard::first_user,
+            auth_guard::list_users,
+            auth_guard::add_user,
+            auth_guard::delete_user,
+            audit::audit_log,
+            health::health,
             tracker::current_throughput,
             tracker::throughput_ring,
             tracker::cpu_usage,
@@ -36,17 +62,31 @@ fn rocket() -> _ {
             tracker::worst_10_rtt,
             tracker::rtt_histogram,
             tracker::host_counts,
+            tracker::dashboard_stream,
             shaped_devices::all_shaped_devices,
             shaped_devices::shaped_devices_count,
             shaped_devices::shaped_devices_range,
             shaped_devices::shaped_devices_search,
+            query::shaped_devices_query,
+            query::unknown_devices_query,
+            shaped_devices::add_circuit,
+            shaped_devices::replace_circuit,
+            shaped_devices::delete_circuit,
+            shaped_devices::add_device,
+            shaped_devices::replace_device,
+            shaped_devices::delete_device,
             shaped_devices::reload_required,
             shaped_devices::reload_libreqos,
             unknown_devices::all_unknown_devices,
             unknown_devices::unknown_devices_count,
             unknown_devices::unknown_devices_range,
+            unknown_devices::unknown_devices_detail,
+            unknown_devices::suggest_device,
+            circuit::circuit_dashboard,
             queue_info::raw_queue_by_circuit,
             queue_info::run_btest,
+            protocols::network_protocols,
+            protocols::circuit_protocols,
 
             // Supporting files
             static_pages::bootsrap_css,
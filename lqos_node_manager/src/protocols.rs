@@ -0,0 +1,23 @@
+use lqos_bus::{BusResponse, BusRequest, ProtocolStats};
+use rocket::serde::json::Json;
+use crate::api_error::{ApiError, ApiResult};
+use crate::auth_guard::AuthenticatedUser;
+use crate::bus::{bus_request_one, unexpected};
+use crate::cache_control::NoCache;
+
+async fn protocol_breakdown(circuit_id: Option<String>) -> Result<Vec<ProtocolStats>, ApiError> {
+    match bus_request_one(None, BusRequest::GetProtocolBreakdown(circuit_id)).await? {
+        BusResponse::ProtocolBreakdown(stats) => Ok(stats),
+        other => Err(unexpected(other)),
+    }
+}
+
+#[get("/api/protocols")]
+pub async fn network_protocols(_user: AuthenticatedUser) -> ApiResult<Vec<ProtocolStats>> {
+    Ok(NoCache::new(Json(protocol_breakdown(None).await?)))
+}
+
+#[get("/api/circuit_protocols/<circuit_id>")]
+pub async fn circuit_protocols(_user: AuthenticatedUser, circuit_id: String) -> ApiResult<Vec<ProtocolStats>> {
+    Ok(NoCache::new(Json(protocol_breakdown(Some(circuit_id)).await?)))
+}
@@ -0,0 +1,413 @@
+//! Paged, sorted and filtered lists of shaped devices and unknown IPs,
+//! for the UI and for scripts working with large networks.
+//!
+//! Both queries take `offset` and `limit` (at most `MAX_LIMIT`), a
+//! `sort` column and an `order` (`asc` or `desc`). Without a `sort`,
+//! rows keep their usual order: shaped devices as in `ShapedDevices.csv`,
+//! unknown IPs most recently seen first. They return the number of
+//! matching rows as well as the requested page.
+
+use std::{cmp::Ordering, collections::HashMap, net::{IpAddr, Ipv6Addr}, sync::Arc, time::{Duration, Instant}};
+use lazy_static::*;
+use lqos_bus::{BusRequest, BusResponse, IpStats};
+use lqos_config::{ConfigShapedDevices, ShapedDevice};
+use parking_lot::Mutex;
+use rocket::serde::{json::Json, Serialize};
+use crate::api_error::{ApiError, ApiResult};
+use crate::auth_guard::AuthenticatedUser;
+use crate::bus::{bus_request_one, unexpected};
+use crate::cache_control::NoCache;
+use crate::tracker::{SHAPED_DEVICES, UNKNOWN_DEVICES};
+
+const DEFAULT_LIMIT: usize = 25;
+const MAX_LIMIT: usize = 1000;
+
+/// How long host statistics are reused for. `lqosd` updates them once
+/// a second, so paging through devices doesn't need to ask again.
+const HOST_STATS_MAX_AGE: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    /// The last host statistics fetched, and when.
+    static ref HOST_STATS: Mutex<Option<(Instant, Arc<Vec<IpStats>>)>> = Mutex::new(None);
+}
+
+/// One page of results.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Page<T> {
+    /// How many rows matched the filters.
+    pub total: usize,
+    pub offset: usize,
+    pub items: Vec<T>,
+}
+
+#[derive(FromForm, Default)]
+pub struct DeviceQuery {
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+    pub sort: Option<String>,
+    pub order: Option<String>,
+    /// Part of a circuit or device name or ID, ignoring case.
+    pub search: Option<String>,
+    /// An address or subnet that overlaps one of the device's.
+    pub ip: Option<String>,
+    /// The parent node, ignoring case.
+    pub parent: Option<String>,
+    /// Part of the MAC address, ignoring case and `-`/`:` separators.
+    pub mac: Option<String>,
+    /// Part of the comment, ignoring case.
+    pub comment: Option<String>,
+}
+
+#[derive(FromForm, Default)]
+pub struct UnknownIpQuery {
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+    pub sort: Option<String>,
+    pub order: Option<String>,
+    /// An address or subnet containing the address.
+    pub ip: Option<String>,
+}
+
+/// A shaped device, with its hosts' current traffic. The statistics are
+/// `null` if `lqosd` couldn't be asked for them.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ShapedDeviceRow {
+    #[serde(flatten)]
+    pub device: ShapedDevice,
+    pub bits_per_second: Option<(u64, u64)>,
+    /// Median of the device's hosts' RTTs, if any were measured.
+    pub median_rtt_ms: Option<f32>,
+}
+
+/// An address or subnet, in the IPv6 space `ShapedDevices.csv` is
+/// matched in (IPv4 is mapped into `::ffff:0:0/96`).
+struct IpFilter {
+    network: u128,
+    prefix: u32,
+}
+
+impl IpFilter {
+    fn parse(filter: &str) -> Result<Self, ApiError> {
+        let invalid = || ApiError::bad_request(format!("{filter} is not an IP address or subnet"));
+        let (address, prefix) = match filter.trim().split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix.parse::<u32>().map_err(|_| invalid())?)),
+            None => (filter.trim(), None),
+        };
+        let (address, prefix) = match address.parse::<IpAddr>().map_err(|_| invalid())? {
+            IpAddr::V4(ip) if prefix.unwrap_or(32) <= 32 => (ip.to_ipv6_mapped(), prefix.unwrap_or(32) + 96),
+            IpAddr::V6(ip) if prefix.unwrap_or(128) <= 128 => (ip, prefix.unwrap_or(128)),
+            _ => return Err(invalid()),
+        };
+        Ok(Self { network: u128::from(address), prefix })
+    }
+
+    /// True if the filter and the subnet share any addresses.
+    fn overlaps(&self, ip: Ipv6Addr, prefix: u32) -> bool {
+        let shared = self.prefix.min(prefix);
+        let mask = if shared == 0 { 0 } else { u128::MAX << (128 - shared) };
+        (self.network ^ u128::from(ip)) & mask == 0
+    }
+}
+
+pub(crate) fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+fn contains_ignoring_case(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+fn normalize_mac(mac: &str) -> String {
+    mac.trim().to_lowercase().replace(['-', ':'], "")
+}
+
+fn descending(order: &Option<String>) -> Result<bool, ApiError> {
+    match order.as_deref() {
+        None | Some("asc") => Ok(false),
+        Some("desc") => Ok(true),
+        Some(other) => Err(ApiError::bad_request(format!("Unknown order {other}; use asc or desc"))),
+    }
+}
+
+/// Cuts out the requested page, after sorting.
+fn page<T>(mut rows: Vec<T>, offset: Option<usize>, limit: Option<usize>) -> Page<T> {
+    let total = rows.len();
+    let offset = offset.unwrap_or(0).min(total);
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let items = rows.drain(offset..).take(limit).collect();
+    Page { total, offset, items }
+}
+
+fn compare_f32(a: f32, b: f32) -> Ordering {
+    a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+}
+
+const DEVICE_COLUMNS: &str = "circuit_id, circuit_name, device_id, device_name, parent_node, mac, ip, \
+    download_min_mbps, upload_min_mbps, download_max_mbps, upload_max_mbps, download_bps, upload_bps, rtt";
+
+type DeviceOrder = fn(&ShapedDeviceRow, &ShapedDeviceRow) -> Ordering;
+
+/// How to order rows by `column`, and whether it needs statistics
+/// from `lqosd`.
+fn device_order(column: &str) -> Result<(DeviceOrder, bool), ApiError> {
+    let order: DeviceOrder = match column {
+        "circuit_id" => |a, b| a.device.circuit_id.cmp(&b.device.circuit_id),
+        "circuit_name" => |a, b| a.device.circuit_name.to_lowercase().cmp(&b.device.circuit_name.to_lowercase()),
+        "device_id" => |a, b| a.device.device_id.cmp(&b.device.device_id),
+        "device_name" => |a, b| a.device.device_name.to_lowercase().cmp(&b.device.device_name.to_lowercase()),
+        "parent_node" => |a, b| a.device.parent_node.to_lowercase().cmp(&b.device.parent_node.to_lowercase()),
+        "mac" => |a, b| normalize_mac(&a.device.mac).cmp(&normalize_mac(&b.device.mac)),
+        "ip" => |a, b| {
+            let first_ip = |r: &ShapedDeviceRow| r.device.to_ipv6_list().first().map(|(ip, _)| *ip);
+            first_ip(a).cmp(&first_ip(b))
+        },
+        "download_min_mbps" => |a, b| a.device.download_min_mbps.cmp(&b.device.download_min_mbps),
+        "upload_min_mbps" => |a, b| a.device.upload_min_mbps.cmp(&b.device.upload_min_mbps),
+        "download_max_mbps" => |a, b| a.device.download_max_mbps.cmp(&b.device.download_max_mbps),
+        "upload_max_mbps" => |a, b| a.device.upload_max_mbps.cmp(&b.device.upload_max_mbps),
+        "download_bps" => |a, b| a.bits_per_second.map(|b| b.0).cmp(&b.bits_per_second.map(|b| b.0)),
+        "upload_bps" => |a, b| a.bits_per_second.map(|b| b.1).cmp(&b.bits_per_second.map(|b| b.1)),
+        "rtt" => |a, b| match (a.median_rtt_ms, b.median_rtt_ms) {
+            (Some(a), Some(b)) => compare_f32(a, b),
+            (a, b) => a.is_some().cmp(&b.is_some()),
+        },
+        _ => return Err(ApiError::bad_request(format!("Unknown sort column {column}; use one of {DEVICE_COLUMNS}"))),
+    };
+    Ok((order, matches!(column, "download_bps" | "upload_bps" | "rtt")))
+}
+
+fn matches_device(device: &ShapedDevice, query: &DeviceQuery, ip: &Option<IpFilter>) -> bool {
+    if let Some(search) = &query.search {
+        let found = [&device.circuit_id, &device.circuit_name, &device.device_id, &device.device_name]
+            .iter()
+            .any(|field| contains_ignoring_case(field, search));
+        if !found {
+            return false;
+        }
+    }
+    if let Some(parent) = &query.parent {
+        if !device.parent_node.eq_ignore_ascii_case(parent.trim()) {
+            return false;
+        }
+    }
+    if let Some(mac) = &query.mac {
+        if !normalize_mac(&device.mac).contains(&normalize_mac(mac)) {
+            return false;
+        }
+    }
+    if let Some(comment) = &query.comment {
+        if !contains_ignoring_case(&device.comment, comment) {
+            return false;
+        }
+    }
+    if let Some(ip) = ip {
+        if !device.to_ipv6_list().iter().any(|(address, prefix)| ip.overlaps(*address, *prefix)) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Current traffic for every host `lqosd` is tracking. Fetched at most
+/// once per `HOST_STATS_MAX_AGE`, however many pages are requested.
+async fn host_stats() -> Result<Arc<Vec<IpStats>>, ApiError> {
+    if let Some((fetched, stats)) = HOST_STATS.lock().as_ref() {
+        if fetched.elapsed() < HOST_STATS_MAX_AGE {
+            return Ok(stats.clone());
+        }
+    }
+    let stats = match bus_request_one(None, BusRequest::GetTopNDownloaders(u32::MAX)).await? {
+        BusResponse::TopDownloaders(stats) => Arc::new(stats),
+        other => return Err(unexpected(other)),
+    };
+    *HOST_STATS.lock() = Some((Instant::now(), stats.clone()));
+    Ok(stats)
+}
+
+/// Adds up each device's hosts: total throughput, and the median of
+/// their RTTs. Keyed by the device's index.
+fn device_stats(devices: &ConfigShapedDevices, hosts: &[IpStats]) -> HashMap<usize, ((u64, u64), Vec<f32>)> {
+    let mut result: HashMap<usize, ((u64, u64), Vec<f32>)> = HashMap::new();
+    for host in hosts {
+        let Ok(ip) = host.ip_address.parse::<IpAddr>() else { continue };
+        if let Some((_, id)) = devices.trie.longest_match(to_ipv6(ip)) {
+            let entry = result.entry(*id).or_default();
+            entry.0 .0 += host.bits_per_second.0;
+            entry.0 .1 += host.bits_per_second.1;
+            if host.median_tcp_rtt > 0.0 {
+                entry.1.push(host.median_tcp_rtt);
+            }
+        }
+    }
+    result
+}
+
+fn median(mut values: Vec<f32>) -> Option<f32> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| compare_f32(*a, *b));
+    Some(values[values.len() / 2])
+}
+
+/// Shaped devices, e.g.
+/// `/api/shaped_devices_query?parent=AP7&sort=download_bps&order=desc&limit=50`.
+#[get("/api/shaped_devices_query?<query..>")]
+pub async fn shaped_devices_query(_user: AuthenticatedUser, query: DeviceQuery) -> ApiResult<Page<ShapedDeviceRow>> {
+    let ip = query.ip.as_deref().map(IpFilter::parse).transpose()?;
+    let order = query.sort.as_deref().map(device_order).transpose()?;
+    let needs_stats = order.map(|(_, needs_stats)| needs_stats).unwrap_or(false);
+    let descending = descending(&query.order)?;
+
+    // Statistics are optional unless we are sorting by them.
+    let hosts = match host_stats().await {
+        Ok(hosts) => Some(hosts),
+        Err(e) if needs_stats => return Err(e),
+        Err(_) => None,
+    };
+
+    let mut rows: Vec<ShapedDeviceRow> = {
+        let devices = SHAPED_DEVICES.read();
+        let mut stats = hosts.as_ref().map(|hosts| device_stats(&devices, hosts));
+        devices
+            .devices
+            .iter()
+            .enumerate()
+            .filter(|(_, d)| matches_device(d, &query, &ip))
+            .map(|(i, d)| {
+                let (bits_per_second, median_rtt_ms) = match stats.as_mut() {
+                    Some(stats) => match stats.remove(&i) {
+                        Some((bits, rtts)) => (Some(bits), median(rtts)),
+                        None => (Some((0, 0)), None),
+                    },
+                    None => (None, None),
+                };
+                ShapedDeviceRow { device: d.clone(), bits_per_second, median_rtt_ms }
+            })
+            .collect()
+    };
+    if let Some((order, _)) = order {
+        rows.sort_by(|a, b| if descending { order(b, a) } else { order(a, b) });
+    }
+    Ok(NoCache::new(Json(page(rows, query.offset, query.limit))))
+}
+
+/// Unknown IPs, e.g. `/api/unknown_devices_query?ip=100.64.0.0/10&sort=rtt&order=desc`.
+#[get("/api/unknown_devices_query?<query..>")]
+pub fn unknown_devices_query(_user: AuthenticatedUser, query: UnknownIpQuery) -> ApiResult<Page<IpStats>> {
+    let ip = query.ip.as_deref().map(IpFilter::parse).transpose()?;
+    let descending = descending(&query.order)?;
+    let order: Option<fn(&IpStats, &IpStats) -> Ordering> = match query.sort.as_deref() {
+        None => None,
+        Some("ip") => Some(|a, b| a.ip_address.parse::<IpAddr>().ok().cmp(&b.ip_address.parse::<IpAddr>().ok())),
+        Some("download_bps") => Some(|a, b| a.bits_per_second.0.cmp(&b.bits_per_second.0)),
+        Some("upload_bps") => Some(|a, b| a.bits_per_second.1.cmp(&b.bits_per_second.1)),
+        Some("rtt") => Some(|a, b| compare_f32(a.median_tcp_rtt, b.median_tcp_rtt)),
+        Some(other) => {
+            return Err(ApiError::bad_request(format!(
+                "Unknown sort column {other}; use one of ip, download_bps, upload_bps, rtt"
+            )))
+        }
+    };
+
+    let mut rows: Vec<IpStats> = UNKNOWN_DEVICES
+        .read()
+        .iter()
+        .filter(|host| match &ip {
+            Some(filter) => host
+                .ip_address
+                .parse::<IpAddr>()
+                .map(|address| filter.overlaps(to_ipv6(address), 128))
+                .unwrap_or(false),
+            None => true,
+        })
+        .cloned()
+        .collect();
+    if let Some(order) = order {
+        rows.sort_by(|a, b| if descending { order(b, a) } else { order(a, b) });
+    }
+    Ok(NoCache::new(Json(page(rows, query.offset, query.limit))))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn v4(ip: &str) -> Ipv6Addr {
+        ip.parse::<Ipv4Addr>().unwrap().to_ipv6_mapped()
+    }
+
+    #[test]
+    fn ipv4_filters_are_mapped() {
+        let filter = IpFilter::parse("100.64.0.0/10").unwrap();
+        assert_eq!(filter.prefix, 106);
+        assert!(filter.overlaps(v4("100.64.1.2"), 128));
+        assert!(filter.overlaps(v4("100.127.255.255"), 128));
+        assert!(!filter.overlaps(v4("100.128.0.0"), 128));
+        assert!(filter.overlaps(v4("100.0.0.0"), 104));
+    }
+
+    #[test]
+    fn whole_address_space() {
+        let v4_all = IpFilter::parse("0.0.0.0/0").unwrap();
+        assert!(v4_all.overlaps(v4("192.168.1.1"), 128));
+        assert!(!v4_all.overlaps("2001:db8::1".parse().unwrap(), 128));
+
+        let v6_all = IpFilter::parse("::/0").unwrap();
+        assert_eq!(v6_all.prefix, 0);
+        assert!(v6_all.overlaps("2001:db8::1".parse().unwrap(), 128));
+        assert!(v6_all.overlaps(v4("10.0.0.1"), 128));
+    }
+
+    #[test]
+    fn single_addresses() {
+        let filter = IpFilter::parse("2001:db8::1/128").unwrap();
+        assert!(filter.overlaps("2001:db8::1".parse().unwrap(), 128));
+        assert!(!filter.overlaps("2001:db8::2".parse().unwrap(), 128));
+        assert!(filter.overlaps("2001:db8::".parse().unwrap(), 64));
+
+        let bare = IpFilter::parse(" 10.0.0.1 ").unwrap();
+        assert_eq!(bare.prefix, 128);
+        assert!(bare.overlaps(v4("10.0.0.1"), 128));
+        assert!(!bare.overlaps(v4("10.0.0.2"), 128));
+    }
+
+    #[test]
+    fn invalid_filters_are_rejected() {
+        for filter in ["10.0.0.0/33", "2001:db8::/129", "10.0.0.0/", "10.0.0.0/x", "not an ip", ""] {
+            assert!(IpFilter::parse(filter).is_err(), "{filter} should be rejected");
+        }
+    }
+
+    #[test]
+    fn pages() {
+        let rows: Vec<u32> = (0..10).collect();
+        let first = page(rows.clone(), None, Some(3));
+        assert_eq!((first.total, first.offset, first.items), (10, 0, vec![0, 1, 2]));
+        let last = page(rows.clone(), Some(8), Some(3));
+        assert_eq!((last.total, last.offset, last.items), (10, 8, vec![8, 9]));
+        let default = page(rows.clone(), None, None);
+        assert_eq!(default.items.len(), 10);
+    }
+
+    #[test]
+    fn offset_past_the_end_is_empty() {
+        let rows: Vec<u32> = (0..10).collect();
+        let past = page(rows, Some(50), Some(5));
+        assert_eq!(past.total, 10);
+        assert_eq!(past.offset, 10);
+        assert!(past.items.is_empty());
+    }
+
+    #[test]
+    fn limit_is_capped() {
+        let rows: Vec<usize> = (0..MAX_LIMIT + 10).collect();
+        assert_eq!(page(rows, None, Some(usize::MAX)).items.len(), MAX_LIMIT);
+    }
+}
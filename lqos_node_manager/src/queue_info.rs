@@ -1,59 +1,29 @@
-use lqos_bus::{BusResponse, BUS_BIND_ADDRESS, BusSession, BusRequest, encode_request, decode_response};
+use lqos_bus::{BusResponse, BusRequest};
 use rocket::response::content::RawJson;
-use rocket::tokio::io::{AsyncWriteExt, AsyncReadExt};
-use rocket::tokio::net::TcpStream;
+use crate::api_error::ApiError;
+use crate::auth_guard::{AuthenticatedUser, AdminUser};
+use crate::bus::{bus_request_one, unexpected};
 use crate::cache_control::NoCache;
 
 #[get("/api/raw_queue_by_circuit/<circuit_id>")]
-pub async fn raw_queue_by_circuit(circuit_id: String) -> NoCache<RawJson<String>> {
-    let mut stream = TcpStream::connect(BUS_BIND_ADDRESS).await.unwrap();
-    let test = BusSession {
-        auth_cookie: 1234,
-        requests: vec![
-            BusRequest::GetRawQueueData(circuit_id),
-        ],
-    };
-    let msg = encode_request(&test).unwrap();
-    stream.write(&msg).await.unwrap();
-
-    // Receive reply
-    let mut buf = Vec::new();
-    let _ = stream.read_to_end(&mut buf).await.unwrap();
-    let reply = decode_response(&buf).unwrap();
-
-    let result = match &reply.responses[0] {
-        BusResponse::RawQueueData(msg) => msg.clone(),
-        _ => "Unable to request queue".to_string(),
-    };
-    NoCache::new(RawJson(result))
+pub async fn raw_queue_by_circuit(_user: AuthenticatedUser, circuit_id: String) -> Result<NoCache<RawJson<String>>, ApiError> {
+    match bus_request_one(None, BusRequest::GetRawQueueData(circuit_id)).await? {
+        BusResponse::RawQueueData(msg) => Ok(NoCache::new(RawJson(msg))),
+        other => Err(unexpected(other)),
+    }
 }
 
 #[cfg(feature = "equinix_tests")]
-#[get("/api/run_btest")]
-pub async fn run_btest() -> NoCache<RawJson<String>> {
-    let mut stream = TcpStream::connect(BUS_BIND_ADDRESS).await.unwrap();
-    let test = BusSession {
-        auth_cookie: 1234,
-        requests: vec![
-            BusRequest::RequestLqosEquinixTest,
-        ],
-    };
-    let msg = encode_request(&test).unwrap();
-    stream.write(&msg).await.unwrap();
-
-    // Receive reply
-    let mut buf = Vec::new();
-    let _ = stream.read_to_end(&mut buf).await.unwrap();
-    let reply = decode_response(&buf).unwrap();
-
-    let result = match &reply.responses[0] {
-        BusResponse::Ack => String::new(),
-        _ => "Unable to request test".to_string(),
-    };
-    NoCache::new(RawJson(result))
+#[post("/api/run_btest")]
+pub async fn run_btest(admin: AdminUser) -> Result<NoCache<RawJson<String>>, ApiError> {
+    match bus_request_one(admin.0.bus_client(), BusRequest::RequestLqosEquinixTest).await? {
+        BusResponse::Ack => Ok(NoCache::new(RawJson(String::new()))),
+        other => Err(unexpected(other)),
+    }
 }
 
 #[cfg(not(feature = "equinix_tests"))]
-pub async fn run_btest() -> NoCache<RawJson<String>> {
-    NoCache::new(RawJson("No!"))
-}
\ No newline at end of file
+#[post("/api/run_btest")]
+pub async fn run_btest(_admin: AdminUser) -> Result<NoCache<RawJson<String>>, ApiError> {
+    Err(ApiError::new(rocket::http::Status::NotImplemented, "Built without the Equinix tests"))
+}
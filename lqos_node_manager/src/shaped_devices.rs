@@ -1,36 +1,42 @@
-use lqos_bus::{BusResponse, BUS_BIND_ADDRESS, BusSession, BusRequest, encode_request, decode_response};
-use lqos_config::ShapedDevice;
-use rocket::serde::json::Json;
-use rocket::tokio::io::{AsyncWriteExt, AsyncReadExt};
-use rocket::tokio::net::TcpStream;
+use lqos_bus::{BusResponse, BusRequest};
+use lqos_config::{ConfigShapedDevices, ShapedDevice};
+use rocket::serde::{Deserialize, Serialize, json::{Json, Value, json}};
+use crate::api_error::{ApiError, ApiResult};
+use crate::audit::record_change;
+use crate::auth_guard::{AuthenticatedUser, AdminUser};
+use crate::bus::{bus_request_one, unexpected};
 use crate::cache_control::NoCache;
 use crate::tracker::SHAPED_DEVICES;
 use lazy_static::*;
 use parking_lot::RwLock;
+use rocket::tokio::{sync::Mutex, task::spawn_blocking};
 
 lazy_static! {
     static ref RELOAD_REQUIRED : RwLock<bool> = RwLock::new(false);
+    /// Held for the whole of an edit, so that edits made at the same
+    /// time can't overwrite each other.
+    static ref EDITING : Mutex<()> = Mutex::new(());
 }
 
 #[get("/api/all_shaped_devices")]
-pub fn all_shaped_devices() -> NoCache<Json<Vec<ShapedDevice>>> {
+pub fn all_shaped_devices(_user: AuthenticatedUser) -> NoCache<Json<Vec<ShapedDevice>>> {
     NoCache::new(Json(SHAPED_DEVICES.read().devices.clone()))
 }
 
 #[get("/api/shaped_devices_count")]
-pub fn shaped_devices_count() -> NoCache<Json<usize>> {
+pub fn shaped_devices_count(_user: AuthenticatedUser) -> NoCache<Json<usize>> {
     NoCache::new(Json(SHAPED_DEVICES.read().devices.len()))
 }
 
 #[get("/api/shaped_devices_range/<start>/<end>")]
-pub fn shaped_devices_range(start: usize, end: usize) -> NoCache<Json<Vec<ShapedDevice>>> {
+pub fn shaped_devices_range(_user: AuthenticatedUser, start: usize, end: usize) -> NoCache<Json<Vec<ShapedDevice>>> {
     let reader = SHAPED_DEVICES.read();
-    let result: Vec<ShapedDevice> = reader.devices.iter().skip(start).take(end).cloned().collect();
+    let result: Vec<ShapedDevice> = reader.devices.iter().skip(start).take(end.saturating_sub(start)).cloned().collect();
     NoCache::new(Json(result))
 }
 
 #[get("/api/shaped_devices_search/<term>")]
-pub fn shaped_devices_search(term: String) -> NoCache<Json<Vec<ShapedDevice>>> {
+pub fn shaped_devices_search(_user: AuthenticatedUser, term: String) -> NoCache<Json<Vec<ShapedDevice>>> {
     let term = term.trim().to_lowercase();
     let reader = SHAPED_DEVICES.read();
     let result: Vec<ShapedDevice> = reader
@@ -45,34 +51,253 @@ pub fn shaped_devices_search(term: String) -> NoCache<Json<Vec<ShapedDevice>>> {
     NoCache::new(Json(result))
 }
 
+/// A circuit, and its devices, as submitted to the write API.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct CircuitForm {
+    pub circuit_id: String,
+    pub circuit_name: String,
+    pub parent_node: String,
+    pub download_min_mbps: u32,
+    pub upload_min_mbps: u32,
+    pub download_max_mbps: u32,
+    pub upload_max_mbps: u32,
+    #[serde(default)]
+    pub comment: String,
+    pub devices: Vec<DeviceForm>,
+}
+
+/// A device as submitted to the write API. Addresses are comma-separated
+/// lists, as in `ShapedDevices.csv`.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct DeviceForm {
+    pub device_id: String,
+    pub device_name: String,
+    #[serde(default)]
+    pub mac: String,
+    #[serde(default)]
+    pub ipv4: String,
+    #[serde(default)]
+    pub ipv6: String,
+}
+
+type EditResult = ApiResult<Vec<ShapedDevice>>;
+
+/// Parses a comma-separated address list, rejecting it if any entry
+/// can't be parsed (rather than skipping that entry).
+fn parse_addresses<T>(list: &str, parse: fn(&str) -> Vec<T>) -> anyhow::Result<Vec<T>> {
+    let list = list.trim();
+    let entries = list.split(',').filter(|ip| !ip.trim().is_empty()).count();
+    let result = parse(list);
+    if result.len() != entries {
+        return Err(anyhow::Error::msg(format!("{list} is not a valid address list")));
+    }
+    Ok(result)
+}
+
+impl DeviceForm {
+    /// Builds a device, taking the circuit fields from `circuit`.
+    fn to_device(&self, circuit: &ShapedDevice) -> anyhow::Result<ShapedDevice> {
+        Ok(ShapedDevice {
+            device_id: self.device_id.trim().to_string(),
+            device_name: self.device_name.clone(),
+            mac: self.mac.trim().to_string(),
+            ipv4: parse_addresses(&self.ipv4, ShapedDevice::parse_ipv4)?,
+            ipv6: parse_addresses(&self.ipv6, ShapedDevice::parse_ipv6)?,
+            ..circuit.clone()
+        })
+    }
+}
+
+impl CircuitForm {
+    /// What the audit log records: the circuit's fields, with devices
+    /// listed by ID only.
+    fn summary(&self) -> Value {
+        json!({
+            "circuit_id": self.circuit_id,
+            "circuit_name": self.circuit_name,
+            "parent_node": self.parent_node,
+            "download_min_mbps": self.download_min_mbps,
+            "upload_min_mbps": self.upload_min_mbps,
+            "download_max_mbps": self.download_max_mbps,
+            "upload_max_mbps": self.upload_max_mbps,
+            "comment": self.comment,
+            "device_ids": self.devices.iter().map(|d| &d.device_id).collect::<Vec<_>>(),
+        })
+    }
+
+    fn to_devices(&self) -> anyhow::Result<Vec<ShapedDevice>> {
+        if self.devices.is_empty() {
+            return Err(anyhow::Error::msg("A circuit needs at least one device"));
+        }
+        let circuit = ShapedDevice {
+            circuit_id: self.circuit_id.trim().to_string(),
+            circuit_name: self.circuit_name.clone(),
+            parent_node: self.parent_node.clone(),
+            download_min_mbps: self.download_min_mbps,
+            upload_min_mbps: self.upload_min_mbps,
+            download_max_mbps: self.download_max_mbps,
+            upload_max_mbps: self.upload_max_mbps,
+            comment: self.comment.clone(),
+            ..Default::default()
+        };
+        self.devices.iter().map(|d| d.to_device(&circuit)).collect()
+    }
+}
+
+/// A circuit or device named by an edit that doesn't exist, which is
+/// answered with a 404 rather than a 400.
+#[derive(Debug)]
+struct NotFound(String);
+
+impl std::fmt::Display for NotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+fn circuit_not_found(circuit_id: &str) -> anyhow::Error {
+    NotFound(format!("Circuit {circuit_id} does not exist")).into()
+}
+
+fn device_not_found(device_id: &str) -> anyhow::Error {
+    NotFound(format!("Device {device_id} does not exist")).into()
+}
+
+/// Applies `change` to a copy of the shaped devices and saves it to
+/// `ShapedDevices.csv`. The copy replaces the cached devices only if
+/// both steps succeed. LibreQoS must then be reloaded to rebuild the
+/// queues.
+///
+/// The cached devices are only locked to copy and then replace them;
+/// the file is written on a blocking thread, so readers (and the async
+/// runtime) don't wait on the disk.
+async fn edit<F: FnOnce(&mut ConfigShapedDevices) -> anyhow::Result<Vec<ShapedDevice>>>(change: F) -> EditResult {
+    let _editing = EDITING.lock().await;
+    let mut devices = ConfigShapedDevices::from_devices(SHAPED_DEVICES.read().devices.clone());
+    let result = change(&mut devices).map_err(|e| match e.downcast_ref::<NotFound>() {
+        Some(_) => ApiError::not_found(e),
+        None => ApiError::bad_request(e),
+    })?;
+    let devices = spawn_blocking(move || devices.save().map(|_| devices))
+        .await
+        .map_err(ApiError::internal)?
+        .map_err(|e| ApiError::internal(format!("Unable to save ShapedDevices.csv: {e}")))?;
+    *SHAPED_DEVICES.write() = devices;
+    *RELOAD_REQUIRED.write() = true;
+    Ok(NoCache::new(Json(result)))
+}
+
+fn circuit_exists(devices: &ConfigShapedDevices, circuit_id: &str) -> anyhow::Result<()> {
+    match devices.devices.iter().any(|d| d.circuit_id == circuit_id) {
+        true => Ok(()),
+        false => Err(circuit_not_found(circuit_id)),
+    }
+}
+
+fn circuit_devices(devices: &ConfigShapedDevices, circuit_id: &str) -> Vec<ShapedDevice> {
+    devices.devices.iter().filter(|d| d.circuit_id == circuit_id).cloned().collect()
+}
+
+/// Creates a circuit. Fails if the circuit already exists.
+#[post("/api/circuits", data = "<circuit>")]
+pub async fn add_circuit(admin: AdminUser, circuit: Json<CircuitForm>) -> EditResult {
+    let result = edit(|devices| {
+        let circuit_id = circuit.circuit_id.trim();
+        if devices.devices.iter().any(|d| d.circuit_id == circuit_id) {
+            return Err(anyhow::Error::msg(format!("Circuit {circuit_id} already exists")));
+        }
+        devices.add_devices(circuit.to_devices()?)?;
+        Ok(circuit_devices(devices, circuit_id))
+    }).await;
+    record_change(&admin.0, "AddCircuit", circuit.summary().to_string(), result).await
+}
+
+/// Replaces a circuit and all of its devices.
+#[put("/api/circuits/<circuit_id>", data = "<circuit>")]
+pub async fn replace_circuit(admin: AdminUser, circuit_id: String, circuit: Json<CircuitForm>) -> EditResult {
+    let result = edit(|devices| {
+        circuit_exists(devices, &circuit_id)?;
+        devices.replace_circuit(&circuit_id, circuit.to_devices()?)?;
+        Ok(circuit_devices(devices, circuit.circuit_id.trim()))
+    }).await;
+    let parameters = json!({ "circuit_id": circuit_id, "circuit": circuit.summary() }).to_string();
+    record_change(&admin.0, "ReplaceCircuit", parameters, result).await
+}
+
+/// Deletes a circuit and all of its devices, returning them.
+#[delete("/api/circuits/<circuit_id>")]
+pub async fn delete_circuit(admin: AdminUser, circuit_id: String) -> EditResult {
+    let result = edit(|devices| {
+        circuit_exists(devices, &circuit_id)?;
+        devices.remove_circuit(&circuit_id)
+    }).await;
+    record_change(&admin.0, "DeleteCircuit", json!({ "circuit_id": circuit_id }).to_string(), result).await
+}
+
+/// Adds a device to an existing circuit.
+#[post("/api/circuits/<circuit_id>/devices", data = "<device>")]
+pub async fn add_device(admin: AdminUser, circuit_id: String, device: Json<DeviceForm>) -> EditResult {
+    let result = edit(|devices| {
+        let circuit = devices
+            .devices
+            .iter()
+            .find(|d| d.circuit_id == circuit_id)
+            .ok_or_else(|| circuit_not_found(&circuit_id))?;
+        let device = device.to_device(circuit)?;
+        devices.add_devices(vec![device])?;
+        Ok(circuit_devices(devices, &circuit_id))
+    }).await;
+    let parameters = json!({ "circuit_id": circuit_id, "device": *device }).to_string();
+    record_change(&admin.0, "AddDevice", parameters, result).await
+}
+
+/// Replaces a device, keeping it in the same circuit.
+#[put("/api/devices/<device_id>", data = "<device>")]
+pub async fn replace_device(admin: AdminUser, device_id: String, device: Json<DeviceForm>) -> EditResult {
+    let result = edit(|devices| {
+        let existing = devices
+            .devices
+            .iter()
+            .find(|d| d.device_id == device_id)
+            .ok_or_else(|| device_not_found(&device_id))?;
+        let circuit_id = existing.circuit_id.clone();
+        let device = device.to_device(existing)?;
+        devices.replace_device(&device_id, device)?;
+        Ok(circuit_devices(devices, &circuit_id))
+    }).await;
+    let parameters = json!({ "device_id": device_id, "device": *device }).to_string();
+    record_change(&admin.0, "ReplaceDevice", parameters, result).await
+}
+
+/// Deletes a device, returning it.
+#[delete("/api/devices/<device_id>")]
+pub async fn delete_device(admin: AdminUser, device_id: String) -> EditResult {
+    let result = edit(|devices| {
+        if !devices.devices.iter().any(|d| d.device_id == device_id) {
+            return Err(device_not_found(&device_id));
+        }
+        Ok(vec![devices.remove_device(&device_id)?])
+    }).await;
+    record_change(&admin.0, "DeleteDevice", json!({ "device_id": device_id }).to_string(), result).await
+}
+
 #[get("/api/reload_required")]
-pub fn reload_required() -> NoCache<Json<bool>> {
+pub fn reload_required(_user: AuthenticatedUser) -> NoCache<Json<bool>> {
     NoCache::new(Json(*RELOAD_REQUIRED.read()))
 }
 
-#[get("/api/reload_libreqos")]
-pub async fn reload_libreqos() -> NoCache<Json<String>> {
+#[post("/api/reload_libreqos")]
+pub async fn reload_libreqos(admin: AdminUser) -> ApiResult<String> {
     // Send request to lqosd
-    let mut stream = TcpStream::connect(BUS_BIND_ADDRESS).await.unwrap();
-    let test = BusSession {
-        auth_cookie: 1234,
-        requests: vec![
-            BusRequest::ReloadLibreQoS,
-        ],
-    };
-    let msg = encode_request(&test).unwrap();
-    stream.write(&msg).await.unwrap();
-
-    // Receive reply
-    let mut buf = Vec::new();
-    let _ = stream.read_to_end(&mut buf).await.unwrap();
-    let reply = decode_response(&buf).unwrap();
-
-    let result = match &reply.responses[0] {
-        BusResponse::ReloadLibreQoS(msg) => msg.clone(),
-        _ => "Unable to reload LibreQoS".to_string(),
+    let result = match bus_request_one(admin.0.bus_client(), BusRequest::ReloadLibreQoS).await? {
+        BusResponse::ReloadLibreQoS(msg) => msg,
+        other => return Err(unexpected(other)),
     };
 
     *RELOAD_REQUIRED.write() = false;
-    NoCache::new(Json(result))
-}
\ No newline at end of file
+    Ok(NoCache::new(Json(result)))
+}
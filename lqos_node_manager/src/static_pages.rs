@@ -1,41 +1,48 @@
 use rocket::fs::NamedFile;
+use crate::auth_guard::AuthenticatedUser;
 use crate::cache_control::{LongCache, NoCache};
 
 // Note that NoCache can be replaced with a cache option
 // once the design work is complete.
 #[get("/")]
-pub async fn index<'a>() -> NoCache<Option<NamedFile>> {
+pub async fn index<'a>(_user: AuthenticatedUser) -> NoCache<Option<NamedFile>> {
     NoCache::new(NamedFile::open("static/main.html").await.ok())
 }
 
 // Note that NoCache can be replaced with a cache option
 // once the design work is complete.
 #[get("/shaped")]
-pub async fn shaped_devices_csv_page<'a>() -> NoCache<Option<NamedFile>> {
+pub async fn shaped_devices_csv_page<'a>(_user: AuthenticatedUser) -> NoCache<Option<NamedFile>> {
     NoCache::new(NamedFile::open("static/shaped.html").await.ok())
 }
 
 // Note that NoCache can be replaced with a cache option
 // once the design work is complete.
 #[get("/circuit_queue")]
-pub async fn circuit_queue<'a>() -> NoCache<Option<NamedFile>> {
+pub async fn circuit_queue<'a>(_user: AuthenticatedUser) -> NoCache<Option<NamedFile>> {
     NoCache::new(NamedFile::open("static/circuit_queue.html").await.ok())
 }
 
 // Note that NoCache can be replaced with a cache option
 // once the design work is complete.
 #[get("/unknown")]
-pub async fn unknown_devices_page<'a>() -> NoCache<Option<NamedFile>> {
+pub async fn unknown_devices_page<'a>(_user: AuthenticatedUser) -> NoCache<Option<NamedFile>> {
     NoCache::new(NamedFile::open("static/unknown-ip.html").await.ok())
 }
 
 // Note that NoCache can be replaced with a cache option
 // once the design work is complete.
 #[get("/shaped-add")]
-pub async fn shaped_devices_add_page<'a>() -> NoCache<Option<NamedFile>> {
+pub async fn shaped_devices_add_page<'a>(_user: AuthenticatedUser) -> NoCache<Option<NamedFile>> {
     NoCache::new(NamedFile::open("static/shaped-add.html").await.ok())
 }
 
+// The login page is the one page that doesn't need a login.
+#[get("/login")]
+pub async fn login_page<'a>() -> NoCache<Option<NamedFile>> {
+    NoCache::new(NamedFile::open("static/login.html").await.ok())
+}
+
 #[get("/vendor/bootstrap.min.css")]
 pub async fn bootsrap_css<'a>() -> LongCache<Option<NamedFile>> {
     LongCache::new(NamedFile::open("static/vendor/bootstrap.min.css").await.ok())
@@ -1,3 +1,4 @@
+use std::time::SystemTime;
 use lazy_static::*;
 use lqos_bus::IpStats;
 use parking_lot::RwLock;
@@ -17,3 +18,9 @@ lazy_static! {
 lazy_static! {
     pub static ref HOST_COUNTS : RwLock<(u32, u32)> = RwLock::new((0, 0));
 }
+
+lazy_static! {
+    /// When `update_tracking` last got a full set of statistics from
+    /// `lqosd`.
+    pub static ref LAST_SUCCESSFUL_POLL : RwLock<Option<SystemTime>> = RwLock::new(None);
+}
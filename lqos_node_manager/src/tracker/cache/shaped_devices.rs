@@ -7,7 +7,28 @@ lazy_static! {
     /// Global storage of the shaped devices csv data.
     /// Updated by the file system watcher whenever
     /// the underlying file changes.
-    pub static ref SHAPED_DEVICES : RwLock<ConfigShapedDevices> = RwLock::new(ConfigShapedDevices::load().unwrap());
+    pub static ref SHAPED_DEVICES : RwLock<ConfigShapedDevices> = RwLock::new(load_shaped_devices().unwrap_or_default());
+}
+
+lazy_static! {
+    /// Why `ShapedDevices.csv` last failed to load, or `None` if
+    /// the last load succeeded.
+    pub static ref SHAPED_DEVICES_ERROR : RwLock<Option<String>> = RwLock::new(None);
+}
+
+/// Loads `ShapedDevices.csv`, recording whether it loaded.
+pub fn load_shaped_devices() -> Option<ConfigShapedDevices> {
+    match ConfigShapedDevices::load() {
+        Ok(devices) => {
+            *SHAPED_DEVICES_ERROR.write() = None;
+            Some(devices)
+        }
+        Err(e) => {
+            println!("Unable to load ShapedDevices.csv: {e}");
+            *SHAPED_DEVICES_ERROR.write() = Some(e.to_string());
+            None
+        }
+    }
 }
 
 lazy_static! {
@@ -15,4 +36,4 @@ lazy_static! {
     /// Updated by the file system watcher whenever
     /// the underlying file changes.
     pub static ref UNKNOWN_DEVICES : RwLock<Vec<IpStats>> = RwLock::new(Vec::new());
-}
\ No newline at end of file
+}
@@ -1,12 +1,14 @@
 //! The Cache mod stores data that is periodically updated
 //! on the server-side, to avoid re-requesting repeatedly
 //! when there are multiple clients.
-use std::{time::Duration, net::IpAddr};
+use std::time::{Duration, SystemTime};
 use anyhow::Result;
-use lqos_bus::{BUS_BIND_ADDRESS, BusSession, BusRequest, encode_request, BusResponse, decode_response, IpStats};
+use lqos_bus::{BusRequest, BusResponse};
 use lqos_config::ConfigShapedDevices;
-use rocket::tokio::{task::spawn_blocking, net::TcpStream, io::{AsyncWriteExt, AsyncReadExt}};
+use rocket::tokio::task::spawn_blocking;
+use crate::bus::bus_request;
 use super::cache::*;
+use super::live::publish_snapshot;
 
 /// Once per second, update CPU and RAM usage and ask
 /// `lqosd` for updated system statistics.
@@ -38,7 +40,11 @@ pub async fn update_tracking() {
             mem_use[0] = sys.used_memory();
             mem_use[1] = sys.total_memory();
         }
-        let _ = get_data_from_server().await; // Ignoring errors to keep running
+        // Ignoring errors to keep running. Dashboards only hear about
+        // fresh statistics.
+        if get_data_from_server().await.is_ok() {
+            publish_snapshot();
+        }
         rocket::tokio::time::sleep(Duration::from_secs(1)).await;
     }
 }
@@ -55,7 +61,7 @@ fn watch_for_shaped_devices_changing() -> Result<()> {
     watcher.watch(&ConfigShapedDevices::path()?, RecursiveMode::NonRecursive)?;
     loop {
         let _ = rx.recv();
-        if let Ok(new_file) = ConfigShapedDevices::load() {
+        if let Some(new_file) = load_shaped_devices() {
             println!("ShapedDevices.csv changed");
             *SHAPED_DEVICES.write() = new_file;
         }
@@ -66,27 +72,16 @@ fn watch_for_shaped_devices_changing() -> Result<()> {
 /// caches.
 async fn get_data_from_server() -> Result<()> {
     // Send request to lqosd
-    let mut stream = TcpStream::connect(BUS_BIND_ADDRESS).await?;
-    let test = BusSession {
-        auth_cookie: 1234,
-        requests: vec![
-            BusRequest::GetCurrentThroughput,
-            BusRequest::GetTopNDownloaders(10),
-            BusRequest::GetWorstRtt(10),
-            BusRequest::RttHistogram,
-            BusRequest::AllUnknownIps,
-        ],
-    };
-    let msg = encode_request(&test)?;
-    stream.write(&msg).await?;
-
-    // Receive reply
-    let mut buf = Vec::new();
-    let _ = stream.read_to_end(&mut buf).await.unwrap();
-    let reply = decode_response(&buf)?;
+    let responses = bus_request(None, vec![
+        BusRequest::GetCurrentThroughput,
+        BusRequest::GetTopNDownloaders(10),
+        BusRequest::GetWorstRtt(10),
+        BusRequest::RttHistogram,
+        BusRequest::AllUnknownIps,
+    ]).await?;
 
     // Process the reply
-    for r in reply.responses.iter() {
+    for r in responses.iter() {
         match r {
             BusResponse::CurrentThroughput {
                 bits_per_second,
@@ -97,6 +92,7 @@ async fn get_data_from_server() -> Result<()> {
                     let mut lock = CURRENT_THROUGHPUT.write();
                     lock.bits_per_second = *bits_per_second;
                     lock.packets_per_second = *packets_per_second;
+                    lock.shaped_bits_per_second = *shaped_bits_per_second;
                 } // Lock scope
                 {
                     let mut lock = THROUGHPUT_BUFFER.write();
@@ -117,26 +113,15 @@ async fn get_data_from_server() -> Result<()> {
                 *RTT_HISTOGRAM.write() = stats.clone();
             }
             BusResponse::AllUnknownIps(unknowns) => {
+                // lqosd already excludes addresses found in ShapedDevices.csv
                 *HOST_COUNTS.write() = (unknowns.len() as u32, 0);
-                let cfg = SHAPED_DEVICES.read();
-                let really_unknown: Vec<IpStats> = unknowns.iter().filter(|ip| {
-                    if let Ok(ip) = ip.ip_address.parse::<IpAddr>() {
-                        let lookup = match ip {
-                            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
-                            IpAddr::V6(ip) => ip,
-                        };
-                        cfg.trie.longest_match(lookup).is_none()
-                    } else {
-                        false
-                    }
-                }).cloned().collect();
-                *HOST_COUNTS.write() = (really_unknown.len() as u32, 0);
-                *UNKNOWN_DEVICES.write() = really_unknown;
+                *UNKNOWN_DEVICES.write() = unknowns.clone();
             }
             // Default
             _ => {}
         }
     }
 
+    *LAST_SUCCESSFUL_POLL.write() = Some(SystemTime::now());
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,94 @@
+//! Pushes the dashboard's statistics to browsers as server-sent events,
+//! so each open dashboard gets one update per polling tick instead of
+//! polling half a dozen APIs itself.
+
+use std::{sync::Arc, time::UNIX_EPOCH};
+use lazy_static::*;
+use rocket::response::stream::{Event, EventStream};
+use rocket::serde::{json, Serialize};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::{self, error::RecvError};
+use rocket::Shutdown;
+use crate::auth_guard::AuthenticatedUser;
+use super::cache::*;
+use super::{host_counts_now, IpStatsWithPlan};
+
+/// Slow clients may fall this many snapshots behind before they start
+/// missing some.
+const CHANNEL_CAPACITY: usize = 8;
+
+lazy_static! {
+    /// Each tick's snapshot, already serialized, for every connected
+    /// dashboard.
+    static ref DASHBOARD_UPDATES: broadcast::Sender<Arc<String>> = broadcast::channel(CHANNEL_CAPACITY).0;
+}
+
+/// Everything on the dashboard that `update_tracking` refreshes.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DashboardSnapshot {
+    /// When these statistics were fetched from `lqosd`, in milliseconds
+    /// since the UNIX epoch. A client that has already seen this poll
+    /// (e.g. after reconnecting) shouldn't chart it again.
+    pub last_poll: u64,
+    pub current_throughput: ThroughputPerSecond,
+    pub cpu: Vec<f32>,
+    /// Used and total memory, in bytes.
+    pub ram: Vec<u64>,
+    pub top_10_downloaders: Vec<IpStatsWithPlan>,
+    pub worst_10_rtt: Vec<IpStatsWithPlan>,
+    pub rtt_histogram: Vec<u32>,
+    /// Shaped devices and unknown IPs.
+    pub host_counts: (u32, u32),
+}
+
+/// The current statistics, or `None` if `lqosd` hasn't answered yet.
+fn snapshot_json() -> Option<String> {
+    let last_poll = (*LAST_SUCCESSFUL_POLL.read())?.duration_since(UNIX_EPOCH).ok()?;
+    let snapshot = DashboardSnapshot {
+        last_poll: last_poll.as_millis() as u64,
+        current_throughput: *CURRENT_THROUGHPUT.read(),
+        cpu: CPU_USAGE.read().clone(),
+        ram: MEMORY_USAGE.read().clone(),
+        top_10_downloaders: TOP_10_DOWNLOADERS.read().iter().map(|tt| tt.into()).collect(),
+        worst_10_rtt: WORST_10_RTT.read().iter().map(|tt| tt.into()).collect(),
+        rtt_histogram: RTT_HISTOGRAM.read().clone(),
+        host_counts: host_counts_now(),
+    };
+    json::to_string(&snapshot).ok()
+}
+
+/// Sends the current statistics to every connected dashboard. Called by
+/// `update_tracking` after each successful poll.
+pub fn publish_snapshot() {
+    if DASHBOARD_UPDATES.receiver_count() > 0 {
+        if let Some(snapshot) = snapshot_json() {
+            // Only fails if everyone disconnected in the meantime.
+            let _ = DASHBOARD_UPDATES.send(Arc::new(snapshot));
+        }
+    }
+}
+
+/// A stream of `DashboardSnapshot`s: the latest straight away (if
+/// `lqosd` has answered yet), then one per successful poll until the
+/// client disconnects or the server shuts down.
+#[get("/api/dashboard_stream")]
+pub fn dashboard_stream(_user: AuthenticatedUser, mut shutdown: Shutdown) -> EventStream![] {
+    let mut updates = DASHBOARD_UPDATES.subscribe();
+    EventStream! {
+        if let Some(snapshot) = snapshot_json() {
+            yield Event::data(snapshot);
+        }
+        loop {
+            let snapshot = select! {
+                update = updates.recv() => match update {
+                    Ok(snapshot) => snapshot,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+                _ = &mut shutdown => break,
+            };
+            yield Event::data(snapshot.as_str().to_string());
+        }
+    }
+}
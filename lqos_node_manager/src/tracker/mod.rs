@@ -1,10 +1,13 @@
 mod cache_manager;
 mod cache;
-pub use cache::{SHAPED_DEVICES, UNKNOWN_DEVICES};
+mod live;
+pub use cache::{SHAPED_DEVICES, SHAPED_DEVICES_ERROR, UNKNOWN_DEVICES, LAST_SUCCESSFUL_POLL};
 pub use cache_manager::update_tracking;
+pub use live::dashboard_stream;
 use std::net::IpAddr;
 use lqos_bus::{IpStats, TcHandle};
 use rocket::serde::{json::Json, Serialize, Deserialize};
+use crate::auth_guard::AuthenticatedUser;
 use crate::tracker::cache::ThroughputPerSecond;
 use self::cache::{CURRENT_THROUGHPUT, THROUGHPUT_BUFFER, CPU_USAGE, MEMORY_USAGE, TOP_10_DOWNLOADERS, WORST_10_RTT, RTT_HISTOGRAM, HOST_COUNTS};
 
@@ -49,53 +52,58 @@ impl From<&IpStats> for IpStatsWithPlan {
 }
 
 #[get("/api/current_throughput")]
-pub fn current_throughput() -> Json<ThroughputPerSecond> {
+pub fn current_throughput(_user: AuthenticatedUser) -> Json<ThroughputPerSecond> {
     let result = CURRENT_THROUGHPUT.read().clone();
     Json(result)
 }
 
 #[get("/api/throughput_ring")]
-pub fn throughput_ring() -> Json<Vec<ThroughputPerSecond>> {
+pub fn throughput_ring(_user: AuthenticatedUser) -> Json<Vec<ThroughputPerSecond>> {
     let result = THROUGHPUT_BUFFER.read().get_result();
     Json(result)
 }
 
 #[get("/api/cpu")]
-pub fn cpu_usage() -> Json<Vec<f32>> {
+pub fn cpu_usage(_user: AuthenticatedUser) -> Json<Vec<f32>> {
     let cpu_usage = CPU_USAGE.read().clone();
 
     Json(cpu_usage)
 }
 
 #[get("/api/ram")]
-pub fn ram_usage() -> Json<Vec<u64>> {
+pub fn ram_usage(_user: AuthenticatedUser) -> Json<Vec<u64>> {
     let ram_usage = MEMORY_USAGE.read().clone();
     Json(ram_usage)
 }
 
 #[get("/api/top_10_downloaders")]
-pub fn top_10_downloaders() -> Json<Vec<IpStatsWithPlan>> {
+pub fn top_10_downloaders(_user: AuthenticatedUser) -> Json<Vec<IpStatsWithPlan>> {
     let tt : Vec<IpStatsWithPlan> = TOP_10_DOWNLOADERS.read().iter().map(|tt| tt.into()).collect();
     Json(tt)
 }
 
 #[get("/api/worst_10_rtt")]
-pub fn worst_10_rtt() -> Json<Vec<IpStatsWithPlan>> {
+pub fn worst_10_rtt(_user: AuthenticatedUser) -> Json<Vec<IpStatsWithPlan>> {
     let tt : Vec<IpStatsWithPlan> = WORST_10_RTT.read().iter().map(|tt| tt.into()).collect();
     Json(tt)
 }
 
 
 #[get("/api/rtt_histogram")]
-pub fn rtt_histogram() -> Json<Vec<u32>> {
+pub fn rtt_histogram(_user: AuthenticatedUser) -> Json<Vec<u32>> {
     Json(RTT_HISTOGRAM.read().clone())
 }
 
 #[get("/api/host_counts")]
-pub fn host_counts() -> Json<(u32, u32)> {
+pub fn host_counts(_user: AuthenticatedUser) -> Json<(u32, u32)> {
+    Json(host_counts_now())
+}
+
+/// The number of shaped devices, and of unknown IPs.
+fn host_counts_now() -> (u32, u32) {
     let shaped_reader = SHAPED_DEVICES.read();
     let n_devices = shaped_reader.devices.len();
     let host_counts = HOST_COUNTS.read();
     let unknown = host_counts.0 - host_counts.1;
-    Json((n_devices as u32, unknown))
+    (n_devices as u32, unknown)
 }
@@ -1,20 +1,41 @@
-use lqos_bus::IpStats;
+use lqos_bus::{IpStats, UnknownIp, ShapedDevice, BusResponse, BusRequest};
 use rocket::serde::json::Json;
+use crate::api_error::{ApiError, ApiResult};
+use crate::auth_guard::AuthenticatedUser;
+use crate::bus::{bus_request, bus_request_one, unexpected};
 use crate::{cache_control::NoCache, tracker::UNKNOWN_DEVICES};
 
 #[get("/api/all_unknown_devices")]
-pub fn all_unknown_devices() -> NoCache<Json<Vec<IpStats>>> {
+pub fn all_unknown_devices(_user: AuthenticatedUser) -> NoCache<Json<Vec<IpStats>>> {
     NoCache::new(Json(UNKNOWN_DEVICES.read().clone()))
 }
 
 #[get("/api/unknown_devices_count")]
-pub fn unknown_devices_count() -> NoCache<Json<usize>> {
+pub fn unknown_devices_count(_user: AuthenticatedUser) -> NoCache<Json<usize>> {
     NoCache::new(Json(UNKNOWN_DEVICES.read().len()))
 }
 
 #[get("/api/unknown_devices_range/<start>/<end>")]
-pub fn unknown_devices_range(start: usize, end: usize) -> NoCache<Json<Vec<IpStats>>> {
+pub fn unknown_devices_range(_user: AuthenticatedUser, start: usize, end: usize) -> NoCache<Json<Vec<IpStats>>> {
     let reader = UNKNOWN_DEVICES.read();
-    let result: Vec<IpStats> = reader.iter().skip(start).take(end).cloned().collect();
+    let result: Vec<IpStats> = reader.iter().skip(start).take(end.saturating_sub(start)).cloned().collect();
     NoCache::new(Json(result))
 }
+
+#[get("/api/unknown_devices_detail")]
+pub async fn unknown_devices_detail(_user: AuthenticatedUser) -> ApiResult<Vec<UnknownIp>> {
+    match bus_request_one(None, BusRequest::UnknownIpDetails).await? {
+        BusResponse::UnknownIpDetails(details) => Ok(NoCache::new(Json(details))),
+        other => Err(unexpected(other)),
+    }
+}
+
+#[get("/api/suggest_device/<ip>")]
+pub async fn suggest_device(_user: AuthenticatedUser, ip: String) -> ApiResult<ShapedDevice> {
+    let mut responses = bus_request(None, vec![BusRequest::SuggestShapedDevice(ip)]).await?;
+    match responses.remove(0) {
+        BusResponse::SuggestedDevice(device) => Ok(NoCache::new(Json(device))),
+        BusResponse::Fail(e) => Err(ApiError::bad_request(e)),
+        other => Err(unexpected(other)),
+    }
+}
@@ -52,14 +52,49 @@ function updateHostCounts() {
     });
 }
 
+function getCookie(name) {
+    let match = document.cookie.split("; ").find((c) => c.startsWith(name + "="));
+    return match ? decodeURIComponent(match.split("=")[1]) : "";
+}
+
+// The message from a failed API call's {"error": "..."} body.
+function apiErrorMessage(xhr) {
+    if (xhr.responseJSON && xhr.responseJSON.error) {
+        return xhr.responseJSON.error;
+    }
+    return xhr.responseText || xhr.statusText;
+}
+
+// Sends the CSRF token with every request that changes something,
+// returns to the login page when the session ends, and adds a
+// "Log out" link to the navigation bar.
+function setupSession() {
+    $.ajaxSetup({
+        beforeSend: (xhr, settings) => {
+            if (settings.type != "GET") {
+                xhr.setRequestHeader("X-CSRF-Token", getCookie("lqos_csrf"));
+            }
+        },
+        statusCode: {
+            401: () => { window.location.href = "/login"; },
+        },
+    });
+    $("#btnReload").parent().after('<li class="nav-item"><a class="nav-link" href="#" id="btnLogout"><i class="fa fa-sign-out"></i> Log out</a></li>');
+    $("#btnLogout").on('click', () => {
+        $.post("/api/logout", () => { window.location.href = "/login"; });
+    });
+}
+
 function colorReloadButton() {
     $("body").append(reloadModal);
     $("#btnReload").on('click', () => {
-        $.get("/api/reload_libreqos", (result) => {
+        const showResult = (result) => {
             const myModal = new bootstrap.Modal(document.getElementById('reloadModal'), {focus: true});
             $("#reloadLibreResult").text(result);
             myModal.show();    
-        });
+        };
+        $.post("/api/reload_libreqos", showResult)
+            .fail((xhr) => showResult(apiErrorMessage(xhr)));
     });
     $.get("/api/reload_required", (req) => {
         if (req) {
@@ -84,6 +119,35 @@ function scaleNumber(n) {
     return n;
 }
 
+// Escapes text from ShapedDevices.csv (names, MACs and so on) before it
+// is put into HTML.
+function escapeHtml(text) {
+    return String(text)
+        .replace(/&/g, "&amp;")
+        .replace(/</g, "&lt;")
+        .replace(/>/g, "&gt;")
+        .replace(/"/g, "&quot;")
+        .replace(/'/g, "&#39;");
+}
+
+// Renders a protocol breakdown (from /api/protocols or
+// /api/circuit_protocols) as a table.
+function protocolTable(stats) {
+    let html = "<table class='table'>";
+    html += "<thead><th>Application</th><th>DL ⬇️</th><th>UL ⬆️</th><th>Total DL</th><th>Total UL</th></thead>";
+    for (let i = 0; i < stats.length; i++) {
+        html += "<tr>";
+        html += "<td>" + stats[i].bucket + "</td>";
+        html += "<td>" + scaleNumber(stats[i].bits_per_second[0]) + "</td>";
+        html += "<td>" + scaleNumber(stats[i].bits_per_second[1]) + "</td>";
+        html += "<td>" + scaleNumber(stats[i].total_bytes[0]) + "B</td>";
+        html += "<td>" + scaleNumber(stats[i].total_bytes[1]) + "B</td>";
+        html += "</tr>";
+    }
+    html += "</table>";
+    return html;
+}
+
 const reloadModal = `
 <div class='modal fade' id='reloadModal' tabindex='-1' aria-labelledby='reloadModalLabel' aria-hidden='true'>
     <div class='modal-dialog modal-fullscreen'>
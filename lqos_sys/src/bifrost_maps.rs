@@ -1,7 +1,7 @@
 use anyhow::Result;
 use lqos_config::{BridgeInterface, BridgeVlan};
 
-use crate::{bpf_map::BpfMap, lqos_kernel::interface_name_to_index};
+use crate::{bpf_map::BpfMap, lqos_kernel::interface_name_to_index, pinned_maps::map_path};
 
 #[repr(C)]
 #[derive(Default, Clone, Debug)]
@@ -16,13 +16,13 @@ struct BifrostVlan {
     redirect_to: u32,
 }
 
-const INTERFACE_PATH: &str = "/sys/fs/bpf/bifrost_interface_map";
-const VLAN_PATH: &str = "/sys/fs/bpf/bifrost_vlan_map";
+const INTERFACE_MAP: &str = "bifrost_interface_map";
+const VLAN_MAP: &str = "bifrost_vlan_map";
 
-pub(crate) fn clear_bifrost() -> Result<()> {
+pub(crate) fn clear_bifrost(pin_root: &str) -> Result<()> {
     println!("Clearing bifrost maps");
-    let mut interface_map = BpfMap::<u32, BifrostInterface>::from_path(INTERFACE_PATH)?;
-    let mut vlan_map = BpfMap::<u32, BifrostVlan>::from_path(VLAN_PATH)?;
+    let mut interface_map = BpfMap::<u32, BifrostInterface>::from_path(&map_path(pin_root, INTERFACE_MAP))?;
+    let mut vlan_map = BpfMap::<u32, BifrostVlan>::from_path(&map_path(pin_root, VLAN_MAP))?;
     println!("Clearing VLANs");
     vlan_map.clear_no_repeat()?;
     println!("Clearing Interfaces");
@@ -31,9 +31,9 @@ pub(crate) fn clear_bifrost() -> Result<()> {
     Ok(())
 }
 
-pub(crate) fn map_interfaces(mappings: &[BridgeInterface]) -> Result<()> {
+pub(crate) fn map_interfaces(pin_root: &str, mappings: &[BridgeInterface]) -> Result<()> {
     println!("Interface maps");
-    let mut interface_map = BpfMap::<u32, BifrostInterface>::from_path(INTERFACE_PATH)?;
+    let mut interface_map = BpfMap::<u32, BifrostInterface>::from_path(&map_path(pin_root, INTERFACE_MAP))?;
     for mapping in mappings.iter() {
         // Key is the parent interface
         let mut from = interface_name_to_index(&mapping.name)?;
@@ -51,9 +51,9 @@ pub(crate) fn map_interfaces(mappings: &[BridgeInterface]) -> Result<()> {
     Ok(())
 }
 
-pub(crate) fn map_vlans(mappings: &[BridgeVlan]) -> Result<()> {
+pub(crate) fn map_vlans(pin_root: &str, mappings: &[BridgeVlan]) -> Result<()> {
     println!("VLAN maps");
-    let mut vlan_map = BpfMap::<u32, BifrostVlan>::from_path(VLAN_PATH)?;
+    let mut vlan_map = BpfMap::<u32, BifrostVlan>::from_path(&map_path(pin_root, VLAN_MAP))?;
     for mapping in mappings.iter() {
         let mut key: u32 = (interface_name_to_index(&mapping.parent)? << 16) | mapping.tag;
         let mut val = BifrostVlan {
@@ -25,8 +25,10 @@ struct {
 	__uint(pinning, LIBBPF_PIN_BY_NAME);
 } cpu_map SEC(".maps");
 
+/* CPUs that may shape traffic. A hash, so that a CPU that isn't listed
+ * (never mapped, or excluded) has no entry rather than a zeroed one. */
 struct {
-	__uint(type, BPF_MAP_TYPE_ARRAY);
+	__uint(type, BPF_MAP_TYPE_HASH);
 	__uint(max_entries, MAX_CPUS);
 	__type(key, __u32);
 	__type(value, __u32);
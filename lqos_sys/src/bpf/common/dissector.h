@@ -4,6 +4,7 @@
 #include <bpf/bpf_helpers.h>
 #include <bpf/bpf_endian.h>
 #include <linux/if_ether.h>
+#include <linux/in.h>
 #include <stdbool.h>
 #include "../common/skb_safety.h"
 #include "../common/debug.h"
@@ -37,6 +38,19 @@ struct dissector_t
     // Current VLAN tag. If there are multiple tags, it will be
     // the INNER tag.
     __be16 current_vlan;
+    // IP protocol (e.g. IPPROTO_TCP), once the IP header is found
+    __u8 ip_protocol;
+    // TCP/UDP source port (host byte order), 0 if not found
+    __u16 src_port;
+    // TCP/UDP destination port (host byte order), 0 if not found
+    __u16 dst_port;
+};
+
+// The first 4 bytes of both TCP and UDP headers.
+struct l4_ports
+{
+    __be16 source;
+    __be16 dest;
 };
 
 // Representation of the VLAN header type.
@@ -224,6 +238,7 @@ static __always_inline bool dissector_find_ip_header(
             return false;
         encode_ipv4(dissector->ip_header.iph->saddr, &dissector->src_ip);
         encode_ipv4(dissector->ip_header.iph->daddr, &dissector->dst_ip);
+        dissector->ip_protocol = dissector->ip_header.iph->protocol;
         return true;
     }
     break;
@@ -238,10 +253,52 @@ static __always_inline bool dissector_find_ip_header(
             return false;
         encode_ipv6(&dissector->ip_header.ip6h->saddr, &dissector->src_ip);
         encode_ipv6(&dissector->ip_header.ip6h->daddr, &dissector->dst_ip);
+        dissector->ip_protocol = dissector->ip_header.ip6h->nexthdr;
         return true;
     }
     break;
     default:
         return false;
     }
-}
\ No newline at end of file
+}
+
+// Locates TCP/UDP source and destination ports, once the IP header has
+// been found. IPv6 extension headers are not followed. Returns FALSE
+// if the packet isn't TCP/UDP, or is truncated.
+static __always_inline bool dissector_find_l4_ports(
+    struct dissector_t *dissector
+) {
+    if (dissector->ip_protocol != IPPROTO_TCP && 
+        dissector->ip_protocol != IPPROTO_UDP) {
+            return false;
+    }
+    void * l4 = NULL;
+    switch (dissector->eth_type)
+    {
+    case ETH_P_IP:
+    {
+        if (dissector->ip_header.iph + 1 > dissector->end)
+            return false;
+        __u32 ihl = dissector->ip_header.iph->ihl * 4;
+        if (ihl < sizeof(struct iphdr))
+            return false;
+        l4 = (void *)dissector->ip_header.iph + ihl;
+    }
+    break;
+    case ETH_P_IPV6:
+    {
+        if (dissector->ip_header.ip6h + 1 > dissector->end)
+            return false;
+        l4 = (void *)(dissector->ip_header.ip6h + 1);
+    }
+    break;
+    default:
+        return false;
+    }
+    struct l4_ports * ports = (struct l4_ports *)l4;
+    if (ports + 1 > dissector->end)
+        return false;
+    dissector->src_port = bpf_ntohs(ports->source);
+    dissector->dst_port = bpf_ntohs(ports->dest);
+    return true;
+}
@@ -0,0 +1,78 @@
+#pragma once
+
+#include <linux/bpf.h>
+#include <bpf/bpf_helpers.h>
+#include <linux/in6.h>
+#include <stdbool.h>
+#include "maximums.h"
+#include "debug.h"
+
+// Key for per-flow tracking. Flows are directional: each direction of
+// a conversation has its own entry.
+struct flow_key {
+    struct in6_addr src; // Encoded by `ip_hash.h`
+    struct in6_addr dst; // Encoded by `ip_hash.h`
+    __u16 src_port;      // Host byte order, 0 if not TCP/UDP
+    __u16 dst_port;      // Host byte order, 0 if not TCP/UDP
+    __u8 protocol;       // IP protocol number
+    __u8 pad[3];
+};
+
+// Counters for each flow
+struct flow_counter {
+    __u64 last_seen;     // bpf_ktime_get_ns() of the most recent packet
+    __u64 bytes;
+    __u64 packets;
+    __u32 tc_handle;
+    __u32 last_rtt;      // Most recent TCP RTT, in 0.01ms units (0 = none)
+};
+
+// Pinned map storing counters per flow. It's an LRU structure, so if
+// it fills up the least recently seen flows are evicted.
+struct
+{
+	__uint(type, BPF_MAP_TYPE_LRU_HASH);
+	__type(key, struct flow_key);
+	__type(value, struct flow_counter);
+    __uint(max_entries, MAX_TRACKED_FLOWS);
+	__uint(pinning, LIBBPF_PIN_BY_NAME);
+} flow_tracker SEC(".maps");
+
+static __always_inline void track_flow(
+    struct flow_key * key,
+    __u32 size,
+    __u32 tc_handle
+) {
+    __u64 now = bpf_ktime_get_ns();
+    struct flow_counter * counter = 
+        (struct flow_counter *)bpf_map_lookup_elem(&flow_tracker, key);
+    if (counter) {
+        // Not per-CPU, so use atomic adds for the counters
+        __sync_fetch_and_add(&counter->bytes, size);
+        __sync_fetch_and_add(&counter->packets, 1);
+        counter->last_seen = now;
+        counter->tc_handle = tc_handle;
+    } else {
+        struct flow_counter new_flow = {0};
+        new_flow.last_seen = now;
+        new_flow.bytes = size;
+        new_flow.packets = 1;
+        new_flow.tc_handle = tc_handle;
+        if (bpf_map_update_elem(&flow_tracker, key, &new_flow, BPF_NOEXIST) != 0) {
+            bpf_debug("Failed to insert tracked flow");
+        }
+    }
+}
+
+// Records an RTT sample against a flow, if that flow is being tracked.
+// Never creates a flow entry.
+static __always_inline void flow_record_rtt(
+    struct flow_key * key,
+    __u32 rtt
+) {
+    struct flow_counter * counter = 
+        (struct flow_counter *)bpf_map_lookup_elem(&flow_tracker, key);
+    if (counter) {
+        counter->last_rtt = rtt;
+    }
+}
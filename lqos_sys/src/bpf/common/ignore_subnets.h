@@ -0,0 +1,26 @@
+#pragma once
+
+#include <linux/bpf.h>
+#include <bpf/bpf_helpers.h>
+#include <stdbool.h>
+#include "maximums.h"
+#include "lpm.h"
+
+// Subnets for which traffic should not be tracked in map_traffic.
+// Filled from userspace; if it is empty, everything is tracked.
+struct {
+	__uint(type, BPF_MAP_TYPE_LPM_TRIE);
+	__uint(max_entries, IGNORE_SUBNETS_MAX);
+	__type(key, struct ip_hash_key);
+	__type(value, __u32);
+	__uint(pinning, LIBBPF_PIN_BY_NAME);
+	__uint(map_flags, BPF_F_NO_PREALLOC);
+} map_ignore_subnets SEC(".maps");
+
+// Returns true if the address in `lookup_key` falls inside an
+// ignored subnet. `lookup_key` must have its prefix length set.
+static __always_inline bool is_ignored_subnet(struct ip_hash_key * lookup_key)
+{
+    __u32 * ignored = bpf_map_lookup_elem(&map_ignore_subnets, lookup_key);
+    return ignored != NULL;
+}
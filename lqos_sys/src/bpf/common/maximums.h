@@ -6,6 +6,12 @@
 // Maximum number of TC class mappings to support
 #define IP_HASH_ENTRIES_MAX	128000
 
+// Maximum number of subnets to exclude from traffic tracking
+#define IGNORE_SUBNETS_MAX 1024
+
+// Maximum number of 5-tuple flows to track (if flow tracking is enabled)
+#define MAX_TRACKED_FLOWS 65536
+
 // Maximum number of supported CPUs
 #define MAX_CPUS 1024
 
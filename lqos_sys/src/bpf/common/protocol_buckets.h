@@ -0,0 +1,103 @@
+#pragma once
+
+#include <linux/bpf.h>
+#include <bpf/bpf_helpers.h>
+#include <linux/in.h>
+#include <stdbool.h>
+#include "maximums.h"
+#include "debug.h"
+
+// Coarse application buckets, decided by L4 protocol and well-known
+// port. These must match `ProtocolBucket` in `lqos_bus`.
+#define BUCKET_OTHER 0
+#define BUCKET_WEB 1
+#define BUCKET_VIDEO 2
+#define BUCKET_GAMING 3
+#define BUCKET_VOIP 4
+#define BUCKET_DNS 5
+#define PROTOCOL_BUCKETS 6
+
+// Byte counters for each bucket, per TC handle (circuit)
+struct protocol_counters {
+    __u64 download_bytes[PROTOCOL_BUCKETS];
+    __u64 upload_bytes[PROTOCOL_BUCKETS];
+};
+
+// Pinned map storing bucket counters per TC handle. Unmapped traffic
+// is counted against handle 0, which every CPU would be fighting over
+// if the map were shared - so it is per-CPU, and userspace adds up the
+// CPUs' counters.
+struct
+{
+	__uint(type, BPF_MAP_TYPE_LRU_PERCPU_HASH);
+	__type(key, __u32);
+	__type(value, struct protocol_counters);
+    __uint(max_entries, IP_HASH_ENTRIES_MAX);
+	__uint(pinning, LIBBPF_PIN_BY_NAME);
+} map_protocol_buckets SEC(".maps");
+
+// Classifies a single port. Returns BUCKET_OTHER if it isn't
+// recognized.
+static __always_inline __u32 port_bucket(__u8 protocol, __u16 port)
+{
+    // DNS, including DNS-over-TLS
+    if (port == 53 || (protocol == IPPROTO_TCP && port == 853))
+        return BUCKET_DNS;
+    // HTTP, HTTPS and QUIC
+    if (port == 443 || (protocol == IPPROTO_TCP && (port == 80 || port == 8080)))
+        return BUCKET_WEB;
+    // RTMP and RTSP
+    if (port == 1935 || port == 554 || port == 8554)
+        return BUCKET_VIDEO;
+    // SIP, IAX2 and RTP
+    if (port == 5060 || port == 5061 || port == 4569 || port == 5004 || port == 5005)
+        return BUCKET_VOIP;
+    // Xbox Live, PSN, Steam and Minecraft
+    if (port == 3074 || (port >= 3478 && port <= 3480) || 
+        (port >= 27015 && port <= 27050) || port == 25565)
+        return BUCKET_GAMING;
+    return BUCKET_OTHER;
+}
+
+// Classifies a packet by whichever of its ports is recognized.
+static __always_inline __u32 classify_bucket(
+    __u8 protocol,
+    __u16 src_port,
+    __u16 dst_port
+) {
+    if (protocol != IPPROTO_TCP && protocol != IPPROTO_UDP)
+        return BUCKET_OTHER;
+    __u32 bucket = port_bucket(protocol, dst_port);
+    if (bucket == BUCKET_OTHER)
+        bucket = port_bucket(protocol, src_port);
+    return bucket;
+}
+
+static __always_inline void track_protocol_bucket(
+    int direction,
+    __u32 tc_handle,
+    __u32 bucket,
+    __u32 size
+) {
+    if (bucket >= PROTOCOL_BUCKETS) return; // Keep the verifier happy
+    struct protocol_counters * counters = 
+        (struct protocol_counters *)bpf_map_lookup_elem(&map_protocol_buckets, &tc_handle);
+    if (counters) {
+        // Only this CPU's copy, so no atomics are needed
+        if (direction == 1) {
+            counters->download_bytes[bucket] += size;
+        } else {
+            counters->upload_bytes[bucket] += size;
+        }
+    } else {
+        struct protocol_counters new_counters = {0};
+        if (direction == 1) {
+            new_counters.download_bytes[bucket] = size;
+        } else {
+            new_counters.upload_bytes[bucket] = size;
+        }
+        if (bpf_map_update_elem(&map_protocol_buckets, &tc_handle, &new_counters, BPF_NOEXIST) != 0) {
+            bpf_debug("Failed to insert protocol counters");
+        }
+    }
+}
@@ -36,6 +36,7 @@ My modifications are Copyright 2022, Herbert Wolverson
 #include "debug.h"
 #include "ip_hash.h"
 #include "dissector_tc.h"
+#include "flows.h"
 
 #define MAX_MEMCMP_SIZE 128
 
@@ -619,6 +620,23 @@ static __always_inline void pping_match_packet(struct flow_state *f_state,
         __sync_fetch_and_add(&f_state->outstanding_timestamps, -1);
     }
 
+    // Record the RTT against both directions of the flow, if they are
+    // in the flow tracker. Note that pping stores the ports swapped.
+    {
+        struct flow_key flow = {0};
+        flow.protocol = IPPROTO_TCP;
+        flow.src = p_info->pid.flow.saddr.ip;
+        flow.dst = p_info->pid.flow.daddr.ip;
+        flow.src_port = p_info->pid.flow.daddr.port;
+        flow.dst_port = p_info->pid.flow.saddr.port;
+        flow_record_rtt(&flow, rtt);
+        flow.src = p_info->pid.flow.daddr.ip;
+        flow.dst = p_info->pid.flow.saddr.ip;
+        flow.src_port = p_info->pid.flow.saddr.port;
+        flow.dst_port = p_info->pid.flow.daddr.port;
+        flow_record_rtt(&flow, rtt);
+    }
+
     // Update the most performance map to include this data
     struct rotating_performance *perf = 
         (struct rotating_performance *)bpf_map_lookup_elem(
@@ -14,6 +14,9 @@
 #include "common/maximums.h"
 #include "common/throughput.h"
 #include "common/lpm.h"
+#include "common/ignore_subnets.h"
+#include "common/flows.h"
+#include "common/protocol_buckets.h"
 #include "common/cpu_map.h"
 #include "common/tcp_rtt.h"
 #include "common/bifrost.h"
@@ -26,7 +29,9 @@
       * If VLAN redirection is enabled, change VLAN tags
       * to swap ingress/egress VLANs.
   * Perform LPM lookup to determine CPU destination
-  * Track traffic totals
+  * Track traffic totals (unless the address is in an ignored subnet)
+  * Track per-circuit totals by protocol bucket (web, video, etc.)
+  * If enabled, track per-flow (5-tuple) totals
   * Perform CPU redirection
 3. TC (ingress) starts
   * If interface redirection is enabled, bypass the bridge
@@ -35,7 +40,8 @@
     there is a VLAN tag to avoid STP loops.
 4. TC (egress) starts on the outbound interface
   * LPM lookup to find TC handle
-  * If TCP, track RTT via ringbuffer and sampling
+  * If TCP, track RTT via ringbuffer and sampling (and per-flow, if
+    flow tracking is enabled)
   * Send TC redirect to track at the appropriate handle.
 */
 
@@ -51,6 +57,14 @@ int direction = 255;
 __be16 internet_vlan = 0; // Note: turn these into big-endian
 __be16 isp_vlan = 0;
 
+// Also configured during loading. If non-zero, every packet is also
+// counted in the per-flow (5-tuple) `flow_tracker` map.
+__u32 flow_tracking = 0;
+
+// Also configured during loading. If non-zero, bytes are counted per
+// circuit in the `map_protocol_buckets` application breakdown.
+__u32 protocol_tracking = 0;
+
 // XDP Entry Point
 SEC("xdp")
 int xdp_prog(struct xdp_md *ctx)
@@ -120,13 +134,44 @@ int xdp_prog(struct xdp_md *ctx)
         tc_handle = ip_info->tc_handle;
         cpu = ip_info->cpu;
     }
-    // Update the traffic tracking buffers
-    track_traffic(
-        effective_direction, 
-        &lookup_key.address, 
-        ctx->data_end - ctx->data, // end - data = length
-        tc_handle
-    );
+    // Update the traffic tracking buffers, unless the address
+    // is in an ignored subnet
+    if (!is_ignored_subnet(&lookup_key)) {
+        track_traffic(
+            effective_direction, 
+            &lookup_key.address, 
+            ctx->data_end - ctx->data, // end - data = length
+            tc_handle
+        );
+
+        // Optional per-circuit application/protocol breakdown
+        bool have_ports = dissector_find_l4_ports(&dissector);
+        if (protocol_tracking) {
+            track_protocol_bucket(
+                effective_direction,
+                tc_handle,
+                classify_bucket(
+                    dissector.ip_protocol, 
+                    dissector.src_port, 
+                    dissector.dst_port
+                ),
+                ctx->data_end - ctx->data
+            );
+        }
+
+        // Optional per-flow tracking
+        if (flow_tracking) {
+            struct flow_key flow = {0};
+            flow.src = dissector.src_ip;
+            flow.dst = dissector.dst_ip;
+            flow.protocol = dissector.ip_protocol;
+            if (have_ports) {
+                flow.src_port = dissector.src_port;
+                flow.dst_port = dissector.dst_port;
+            }
+            track_flow(&flow, ctx->data_end - ctx->data, tc_handle);
+        }
+    }
 
     // Send on its way
     if (tc_handle != 0) {
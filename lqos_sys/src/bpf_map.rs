@@ -67,6 +67,27 @@ where
         result
     }
 
+    /// Looks up a single entry in the underlying BPF map.
+    ///
+    /// ## Arguments
+    ///
+    /// * `key` - the key to find.
+    ///
+    /// Returns `None` if the key isn't present.
+    pub(crate) fn lookup(&self, key: &mut K) -> Option<V> {
+        let key_ptr: *mut K = key;
+        let mut value = V::default();
+        let value_ptr: *mut V = &mut value;
+        let err = unsafe {
+            bpf_map_lookup_elem(self.fd, key_ptr as *mut c_void, value_ptr as *mut c_void)
+        };
+        if err != 0 {
+            None
+        } else {
+            Some(value)
+        }
+    }
+
     /// Inserts an entry into a BPF map.
     /// Use this sparingly, because it briefly pauses XDP access to the
     /// underlying map (through internal locking we can't reach from
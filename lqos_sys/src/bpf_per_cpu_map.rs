@@ -2,7 +2,6 @@ use anyhow::{Error, Result};
 use libbpf_sys::{
     bpf_map_get_next_key, bpf_map_lookup_elem, bpf_obj_get, libbpf_num_possible_cpus,
 };
-use std::fmt::Debug;
 use std::{
     ffi::{c_void, CString},
     marker::PhantomData,
@@ -23,7 +22,7 @@ pub(crate) struct BpfPerCpuMap<K, V> {
 impl<K, V> BpfPerCpuMap<K, V>
 where
     K: Default + Clone,
-    V: Default + Clone + Debug,
+    V: Default + Clone,
 {
     /// Connect to a PER-CPU BPF map via a filename. Connects the internal
     /// file descriptor, which is held until the structure is
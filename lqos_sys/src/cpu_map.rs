@@ -1,10 +1,13 @@
 use std::{ffi::CString, os::raw::c_void};
 use anyhow::{Error, Result};
-use libbpf_sys::{bpf_map_update_elem, bpf_obj_get, libbpf_num_possible_cpus};
+use libbpf_sys::{bpf_map_delete_elem, bpf_map_update_elem, bpf_obj_get, libbpf_num_possible_cpus};
+use crate::pinned_maps::map_path;
 
 //* Provides an interface for querying the number of CPUs eBPF can
-//* see, and marking CPUs as available. Currently marks ALL eBPF
-//* usable CPUs as available.
+//* see, and marking CPUs as available. Marks every eBPF usable CPU
+//* as available, except those excluded in `/etc/lqos`. Excluded CPUs
+//* are removed from the (pinned, and so possibly older) maps, so XDP
+//* passes their traffic up the stack instead of redirecting it.
 
 pub(crate) struct CpuMapping {
     fd_cpu_map: i32,
@@ -23,21 +26,30 @@ fn get_map_fd(filename: &str) -> Result<i32> {
 }
 
 impl CpuMapping {
-    pub(crate) fn new() -> Result<Self> {
+    pub(crate) fn new(pin_root: &str) -> Result<Self> {
         Ok(Self {
-            fd_cpu_map: get_map_fd("/sys/fs/bpf/cpu_map")?,
-            fd_cpu_available: get_map_fd("/sys/fs/bpf/cpus_available")?,
-            fd_txq_config: get_map_fd("/sys/fs/bpf/map_txq_config")?,
+            fd_cpu_map: get_map_fd(&map_path(pin_root, "cpu_map"))?,
+            fd_cpu_available: get_map_fd(&map_path(pin_root, "cpus_available"))?,
+            fd_txq_config: get_map_fd(&map_path(pin_root, "map_txq_config"))?,
         })
     }
 
-    pub(crate) fn mark_cpus_available(&self) -> Result<()> {
+    pub(crate) fn mark_cpus_available(&self, queue_size: u32, excluded_cpus: &[u32]) -> Result<()> {
         let cpu_count = unsafe { libbpf_num_possible_cpus() } as u32;
 
-        let queue_size = 2048u32;
+        for cpu in (0..cpu_count).filter(|cpu| excluded_cpus.contains(cpu)) {
+            println!("Excluding core #{cpu}");
+            let cpu_ptr: *const u32 = &cpu;
+            // Fails if the CPU wasn't mapped, which is fine.
+            unsafe {
+                bpf_map_delete_elem(self.fd_cpu_available, cpu_ptr as *const c_void);
+                bpf_map_delete_elem(self.fd_cpu_map, cpu_ptr as *const c_void);
+            }
+        }
+
         let val_ptr: *const u32 = &queue_size;
-        for cpu in 0..cpu_count {
-            println!("Mapping core #{cpu}");
+        for cpu in (0..cpu_count).filter(|cpu| !excluded_cpus.contains(cpu)) {
+            println!("Mapping core #{cpu} (queue size {queue_size})");
             // Insert into the cpu map
             let cpu_ptr: *const u32 = &cpu;
             let error = unsafe {
@@ -89,43 +101,3 @@ impl Drop for CpuMapping {
         let _ = nix::unistd::close(self.fd_txq_config);
     }
 }
-
-/// Emulates xd_setup from cpumap
-pub(crate) fn xps_setup_default_disable(interface: &str) -> Result<()> {
-    use std::io::Write;
-    println!("xps_setup");
-    let queues = sorted_txq_xps_cpus(interface)?;
-    for (cpu, xps_cpu) in queues.iter().enumerate() {
-        let mask = cpu_to_mask_disabled(cpu);
-        let mut f = std::fs::OpenOptions::new().write(true).open(xps_cpu)?;
-        f.write_all(&mask.to_string().as_bytes())?;
-        f.flush()?;
-        println!("Mapped TX queue for CPU {cpu}");
-    }
-
-    Ok(())
-}
-
-fn sorted_txq_xps_cpus(interface: &str) -> Result<Vec<String>> {
-    let mut result = Vec::new();
-    let paths = std::fs::read_dir(&format!("/sys/class/net/{interface}/queues/"))?;
-    for path in paths {
-        if let Ok(path) = &path {
-            if path.path().is_dir() {
-                if let Some(filename) = path.path().file_name() {
-                    let base_fn = format!("/sys/class/net/{interface}/queues/{}/xps_cpus", filename.to_str().unwrap());
-                    if std::path::Path::new(&base_fn).exists() {
-                        result.push(base_fn);
-                    }
-                }
-            }
-        }
-    }
-    result.sort();
-
-    Ok(result)
-}
-
-fn cpu_to_mask_disabled(_cpu: usize) -> usize {
-    0
-}
\ No newline at end of file
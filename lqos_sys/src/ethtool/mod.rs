@@ -0,0 +1,327 @@
+mod netlink;
+use anyhow::{Error, Result};
+use netlink::{find, find_string, find_u32, parse_attributes, Attributes, GenlSocket};
+
+//* Reads and changes NIC features, interrupt coalescing and ring sizes
+//* through the kernel's ethtool netlink API (Linux 5.6+), rather than
+//* running `/sbin/ethtool`. Every setter returns the state the driver
+//* reports afterwards, so callers can log exactly what changed.
+
+const ETHTOOL_GENL_NAME: &str = "ethtool";
+const ETHTOOL_GENL_VERSION: u8 = 1;
+
+const ETHTOOL_MSG_FEATURES_GET: u8 = 11;
+const ETHTOOL_MSG_FEATURES_SET: u8 = 12;
+const ETHTOOL_MSG_RINGS_GET: u8 = 15;
+const ETHTOOL_MSG_RINGS_SET: u8 = 16;
+const ETHTOOL_MSG_COALESCE_GET: u8 = 19;
+const ETHTOOL_MSG_COALESCE_SET: u8 = 20;
+
+const ETHTOOL_A_HEADER_DEV_NAME: u16 = 2;
+
+const ETHTOOL_A_BITSET_NOMASK: u16 = 1;
+const ETHTOOL_A_BITSET_BITS: u16 = 3;
+const ETHTOOL_A_BITSET_BITS_BIT: u16 = 1;
+const ETHTOOL_A_BITSET_BIT_NAME: u16 = 2;
+const ETHTOOL_A_BITSET_BIT_VALUE: u16 = 3;
+
+const ETHTOOL_A_FEATURES_HEADER: u16 = 1;
+const ETHTOOL_A_FEATURES_HW: u16 = 2;
+const ETHTOOL_A_FEATURES_WANTED: u16 = 3;
+const ETHTOOL_A_FEATURES_ACTIVE: u16 = 4;
+
+const ETHTOOL_A_RINGS_HEADER: u16 = 1;
+const ETHTOOL_A_RINGS_RX_MAX: u16 = 2;
+const ETHTOOL_A_RINGS_TX_MAX: u16 = 5;
+const ETHTOOL_A_RINGS_RX: u16 = 6;
+const ETHTOOL_A_RINGS_TX: u16 = 9;
+
+const ETHTOOL_A_COALESCE_HEADER: u16 = 1;
+const ETHTOOL_A_COALESCE_RX_USECS: u16 = 2;
+const ETHTOOL_A_COALESCE_TX_USECS: u16 = 6;
+
+/// Maps the short names accepted by `ethtool -K` (e.g. `gro`) to the
+/// kernel's feature names. Anything else is assumed to already be a
+/// kernel feature name (e.g. `rx-gro-hw`).
+fn kernel_feature_names(feature: &str) -> Vec<&str> {
+    match feature {
+        "sg" => vec!["tx-scatter-gather"],
+        "tso" => vec![
+            "tx-tcp-segmentation",
+            "tx-tcp-ecn-segmentation",
+            "tx-tcp-mangleid-segmentation",
+            "tx-tcp6-segmentation",
+        ],
+        "gso" => vec!["tx-generic-segmentation"],
+        "gro" => vec!["rx-gro"],
+        "lro" => vec!["rx-lro"],
+        "rx" => vec!["rx-checksum"],
+        "tx" => vec![
+            "tx-checksum-ipv4",
+            "tx-checksum-ip-generic",
+            "tx-checksum-ipv6",
+            "tx-checksum-fcoe-crc",
+            "tx-checksum-sctp",
+        ],
+        "rxvlan" => vec!["rx-vlan-hw-parse"],
+        "txvlan" => vec!["tx-vlan-hw-insert"],
+        "ntuple" => vec!["rx-ntuple-filter"],
+        "rxhash" => vec!["rx-hashing"],
+        _ => vec![feature],
+    }
+}
+
+/// An interface's offload features, by kernel name.
+#[derive(Clone, Debug, Default)]
+pub struct Features {
+    /// Features that are currently on.
+    pub active: Vec<String>,
+    /// Features the driver allows to be turned on or off.
+    pub changeable: Vec<String>,
+}
+
+impl Features {
+    /// Returns true if any part of `feature` (a short `ethtool -K` name
+    /// or a kernel name) is on.
+    pub fn is_active(&self, feature: &str) -> bool {
+        kernel_feature_names(feature)
+            .iter()
+            .any(|name| self.active.iter().any(|a| a == name))
+    }
+
+    /// The kernel features making up `feature` (a short `ethtool -K`
+    /// or kernel name) that the driver allows to be changed. Saving
+    /// and restoring these individually puts back exactly the bits
+    /// that were on, rather than every part of a short name.
+    pub fn changeable_parts(&self, feature: &str) -> Vec<String> {
+        kernel_feature_names(feature)
+            .into_iter()
+            .filter(|name| self.changeable.iter().any(|c| c == name))
+            .map(|name| name.to_string())
+            .collect()
+    }
+}
+
+/// Interrupt coalescing settings. `None` if the driver doesn't report
+/// (or, when setting, shouldn't change) a value.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Coalesce {
+    /// Microseconds to wait after receiving a packet before interrupting.
+    pub rx_usecs: Option<u32>,
+    /// Microseconds to wait after sending a packet before interrupting.
+    pub tx_usecs: Option<u32>,
+}
+
+/// Ring (descriptor queue) sizes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Rings {
+    /// RX ring size, in descriptors.
+    pub rx: Option<u32>,
+    /// TX ring size, in descriptors.
+    pub tx: Option<u32>,
+    /// Largest supported RX ring size; ignored when setting.
+    pub rx_max: Option<u32>,
+    /// Largest supported TX ring size; ignored when setting.
+    pub tx_max: Option<u32>,
+}
+
+/// Names of the bits that are set in a (verbose) ethtool bitset.
+fn bitset_names(data: &[u8]) -> Vec<String> {
+    let attributes = parse_attributes(data);
+    let no_mask = find(&attributes, ETHTOOL_A_BITSET_NOMASK).is_some();
+    let Some(bits) = find(&attributes, ETHTOOL_A_BITSET_BITS) else { return Vec::new() };
+    parse_attributes(bits)
+        .iter()
+        .filter(|(kind, _)| *kind == ETHTOOL_A_BITSET_BITS_BIT)
+        .filter_map(|(_, bit)| {
+            let bit = parse_attributes(bit);
+            let set = no_mask || find(&bit, ETHTOOL_A_BITSET_BIT_VALUE).is_some();
+            if set {
+                find_string(&bit, ETHTOOL_A_BITSET_BIT_NAME)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// A connection to the kernel's ethtool netlink interface. Requires
+/// `CAP_NET_ADMIN` to change anything.
+pub struct Ethtool {
+    socket: GenlSocket,
+}
+
+impl Ethtool {
+    /// Connects to the ethtool netlink family.
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            socket: GenlSocket::open(ETHTOOL_GENL_NAME)?,
+        })
+    }
+
+    fn header(attributes: &mut Attributes, kind: u16, interface: &str) {
+        attributes
+            .begin_nest(kind)
+            .string(ETHTOOL_A_HEADER_DEV_NAME, interface)
+            .end_nest();
+    }
+
+    fn get(&mut self, command: u8, header: u16, interface: &str) -> Result<Vec<u8>> {
+        let mut request = Attributes::new();
+        Self::header(&mut request, header, interface);
+        self.socket
+            .request(command, ETHTOOL_GENL_VERSION, &request)
+            .map_err(|e| Error::msg(format!("{interface}: {e}")))?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::msg(format!("{interface}: no reply from ethtool netlink")))
+    }
+
+    fn set(&mut self, command: u8, request: &Attributes, interface: &str) -> Result<()> {
+        self.socket
+            .request(command, ETHTOOL_GENL_VERSION, request)
+            .map_err(|e| Error::msg(format!("{interface}: {e}")))?;
+        Ok(())
+    }
+
+    /// Reads an interface's offload features.
+    pub fn features(&mut self, interface: &str) -> Result<Features> {
+        let reply = self.get(ETHTOOL_MSG_FEATURES_GET, ETHTOOL_A_FEATURES_HEADER, interface)?;
+        let attributes = parse_attributes(&reply);
+        Ok(Features {
+            active: find(&attributes, ETHTOOL_A_FEATURES_ACTIVE).map(bitset_names).unwrap_or_default(),
+            changeable: find(&attributes, ETHTOOL_A_FEATURES_HW).map(bitset_names).unwrap_or_default(),
+        })
+    }
+
+    /// Turns features (short `ethtool -K` or kernel names) on or off.
+    /// Fails without changing anything if a feature can't be changed
+    /// on this interface. Returns the resulting features.
+    pub fn set_features(&mut self, interface: &str, changes: &[(&str, bool)]) -> Result<Features> {
+        let current = self.features(interface)?;
+        let mut request = Attributes::new();
+        Self::header(&mut request, ETHTOOL_A_FEATURES_HEADER, interface);
+        request.begin_nest(ETHTOOL_A_FEATURES_WANTED).begin_nest(ETHTOOL_A_BITSET_BITS);
+        for (feature, on) in changes.iter() {
+            let names: Vec<&str> = kernel_feature_names(feature)
+                .into_iter()
+                .filter(|name| current.changeable.iter().any(|c| c == name))
+                .collect();
+            if names.is_empty() {
+                return Err(Error::msg(format!("{interface}: {feature} can't be changed")));
+            }
+            for name in names {
+                request.begin_nest(ETHTOOL_A_BITSET_BITS_BIT).string(ETHTOOL_A_BITSET_BIT_NAME, name);
+                if *on {
+                    request.flag(ETHTOOL_A_BITSET_BIT_VALUE);
+                }
+                request.end_nest();
+            }
+        }
+        request.end_nest().end_nest();
+        self.set(ETHTOOL_MSG_FEATURES_SET, &request, interface)?;
+        self.features(interface)
+    }
+
+    /// Reads an interface's interrupt coalescing settings.
+    pub fn coalesce(&mut self, interface: &str) -> Result<Coalesce> {
+        let reply = self.get(ETHTOOL_MSG_COALESCE_GET, ETHTOOL_A_COALESCE_HEADER, interface)?;
+        let attributes = parse_attributes(&reply);
+        Ok(Coalesce {
+            rx_usecs: find_u32(&attributes, ETHTOOL_A_COALESCE_RX_USECS),
+            tx_usecs: find_u32(&attributes, ETHTOOL_A_COALESCE_TX_USECS),
+        })
+    }
+
+    /// Changes the coalescing settings that are `Some`. Returns the
+    /// resulting settings.
+    pub fn set_coalesce(&mut self, interface: &str, coalesce: &Coalesce) -> Result<Coalesce> {
+        let mut request = Attributes::new();
+        Self::header(&mut request, ETHTOOL_A_COALESCE_HEADER, interface);
+        if let Some(usecs) = coalesce.rx_usecs {
+            request.u32(ETHTOOL_A_COALESCE_RX_USECS, usecs);
+        }
+        if let Some(usecs) = coalesce.tx_usecs {
+            request.u32(ETHTOOL_A_COALESCE_TX_USECS, usecs);
+        }
+        self.set(ETHTOOL_MSG_COALESCE_SET, &request, interface)?;
+        self.coalesce(interface)
+    }
+
+    /// Reads an interface's ring sizes.
+    pub fn rings(&mut self, interface: &str) -> Result<Rings> {
+        let reply = self.get(ETHTOOL_MSG_RINGS_GET, ETHTOOL_A_RINGS_HEADER, interface)?;
+        let attributes = parse_attributes(&reply);
+        Ok(Rings {
+            rx: find_u32(&attributes, ETHTOOL_A_RINGS_RX),
+            tx: find_u32(&attributes, ETHTOOL_A_RINGS_TX),
+            rx_max: find_u32(&attributes, ETHTOOL_A_RINGS_RX_MAX),
+            tx_max: find_u32(&attributes, ETHTOOL_A_RINGS_TX_MAX),
+        })
+    }
+
+    /// Changes the ring sizes that are `Some`. Returns the resulting
+    /// sizes.
+    pub fn set_rings(&mut self, interface: &str, rings: &Rings) -> Result<Rings> {
+        let mut request = Attributes::new();
+        Self::header(&mut request, ETHTOOL_A_RINGS_HEADER, interface);
+        if let Some(size) = rings.rx {
+            request.u32(ETHTOOL_A_RINGS_RX, size);
+        }
+        if let Some(size) = rings.tx {
+            request.u32(ETHTOOL_A_RINGS_TX, size);
+        }
+        self.set(ETHTOOL_MSG_RINGS_SET, &request, interface)?;
+        self.rings(interface)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bitset(no_mask: bool, bits: &[(&str, bool)]) -> Vec<u8> {
+        let mut attributes = Attributes::new();
+        if no_mask {
+            attributes.flag(ETHTOOL_A_BITSET_NOMASK);
+        }
+        attributes.begin_nest(ETHTOOL_A_BITSET_BITS);
+        for (name, value) in bits {
+            attributes.begin_nest(ETHTOOL_A_BITSET_BITS_BIT).string(ETHTOOL_A_BITSET_BIT_NAME, name);
+            if *value {
+                attributes.flag(ETHTOOL_A_BITSET_BIT_VALUE);
+            }
+            attributes.end_nest();
+        }
+        attributes.end_nest();
+        attributes.bytes().to_vec()
+    }
+
+    #[test]
+    fn bitsets() {
+        assert_eq!(bitset_names(&bitset(true, &[("rx-gro", false), ("rx-lro", false)])), vec!["rx-gro", "rx-lro"]);
+        assert_eq!(bitset_names(&bitset(false, &[("rx-gro", true), ("rx-lro", false)])), vec!["rx-gro"]);
+    }
+
+    #[test]
+    fn short_feature_names() {
+        let features = Features {
+            active: vec!["tx-tcp6-segmentation".to_string(), "rx-gro-hw".to_string()],
+            changeable: Vec::new(),
+        };
+        assert!(features.is_active("tso"));
+        assert!(features.is_active("rx-gro-hw"));
+        assert!(!features.is_active("gro"));
+    }
+
+    #[test]
+    fn changeable_parts() {
+        let features = Features {
+            active: Vec::new(),
+            changeable: vec!["tx-tcp-segmentation".to_string(), "tx-tcp6-segmentation".to_string(), "rx-gro".to_string()],
+        };
+        assert_eq!(features.changeable_parts("tso"), vec!["tx-tcp-segmentation", "tx-tcp6-segmentation"]);
+        assert_eq!(features.changeable_parts("rx-gro"), vec!["rx-gro"]);
+        assert!(features.changeable_parts("lro").is_empty());
+    }
+}
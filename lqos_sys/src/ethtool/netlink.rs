@@ -0,0 +1,231 @@
+use anyhow::{Error, Result};
+use nix::{errno::Errno, libc};
+
+//* Just enough generic netlink to talk to the kernel's `ethtool`
+//* family: building requests with (nested) attributes, sending them,
+//* and splitting the replies back into attributes.
+
+const NLMSG_HEADER_LEN: usize = 16;
+const GENL_HEADER_LEN: usize = 4;
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_ACK: u16 = 0x4;
+const NLMSG_ERROR: u16 = 0x2;
+const NLMSG_DONE: u16 = 0x3;
+const NLA_F_NESTED: u16 = 0x8000;
+const NLA_TYPE_MASK: u16 = 0x3FFF;
+
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Builds the attribute payload of a request.
+#[derive(Default)]
+pub(crate) struct Attributes {
+    buffer: Vec<u8>,
+    nests: Vec<usize>,
+}
+
+impl Attributes {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    fn put(&mut self, kind: u16, data: &[u8]) {
+        self.buffer.extend_from_slice(&((4 + data.len()) as u16).to_ne_bytes());
+        self.buffer.extend_from_slice(&kind.to_ne_bytes());
+        self.buffer.extend_from_slice(data);
+        self.buffer.resize(align(self.buffer.len()), 0);
+    }
+
+    pub(crate) fn u32(&mut self, kind: u16, value: u32) -> &mut Self {
+        self.put(kind, &value.to_ne_bytes());
+        self
+    }
+
+    pub(crate) fn string(&mut self, kind: u16, value: &str) -> &mut Self {
+        let mut data = value.as_bytes().to_vec();
+        data.push(0);
+        self.put(kind, &data);
+        self
+    }
+
+    pub(crate) fn flag(&mut self, kind: u16) -> &mut Self {
+        self.put(kind, &[]);
+        self
+    }
+
+    pub(crate) fn begin_nest(&mut self, kind: u16) -> &mut Self {
+        self.nests.push(self.buffer.len());
+        self.put(kind | NLA_F_NESTED, &[]);
+        self
+    }
+
+    pub(crate) fn end_nest(&mut self) -> &mut Self {
+        if let Some(start) = self.nests.pop() {
+            let len = (self.buffer.len() - start) as u16;
+            self.buffer[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+        }
+        self
+    }
+
+    pub(crate) fn bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+/// Splits an attribute payload into (type, data) pairs. Nested
+/// attributes are returned as-is, to be parsed in turn.
+pub(crate) fn parse_attributes(mut data: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut result = Vec::new();
+    while data.len() >= 4 {
+        let len = u16::from_ne_bytes([data[0], data[1]]) as usize;
+        let kind = u16::from_ne_bytes([data[2], data[3]]) & NLA_TYPE_MASK;
+        if len < 4 || len > data.len() {
+            break;
+        }
+        result.push((kind, &data[4..len]));
+        data = &data[usize::min(align(len), data.len())..];
+    }
+    result
+}
+
+pub(crate) fn find<'a>(attributes: &[(u16, &'a [u8])], kind: u16) -> Option<&'a [u8]> {
+    attributes.iter().find(|(k, _)| *k == kind).map(|(_, data)| *data)
+}
+
+pub(crate) fn find_u32(attributes: &[(u16, &[u8])], kind: u16) -> Option<u32> {
+    find(attributes, kind)
+        .filter(|data| data.len() >= 4)
+        .map(|data| u32::from_ne_bytes([data[0], data[1], data[2], data[3]]))
+}
+
+pub(crate) fn find_string(attributes: &[(u16, &[u8])], kind: u16) -> Option<String> {
+    find(attributes, kind).map(|data| {
+        String::from_utf8_lossy(data)
+            .trim_end_matches('\0')
+            .to_string()
+    })
+}
+
+/// A generic netlink socket, bound to one family.
+pub(crate) struct GenlSocket {
+    fd: i32,
+    family: u16,
+    sequence: u32,
+}
+
+impl GenlSocket {
+    /// Opens a socket and looks up the id of the named family.
+    pub(crate) fn open(family_name: &str) -> Result<Self> {
+        let fd = unsafe {
+            libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_GENERIC)
+        };
+        if fd < 0 {
+            return Err(Error::msg(format!("Unable to open netlink socket: {}", Errno::last().desc())));
+        }
+        let mut socket = Self { fd, family: GENL_ID_CTRL, sequence: 0 };
+        let mut request = Attributes::new();
+        request.string(CTRL_ATTR_FAMILY_NAME, family_name);
+        let replies = socket.request(CTRL_CMD_GETFAMILY, 1, &request)?;
+        socket.family = replies
+            .iter()
+            .find_map(|reply| {
+                find(&parse_attributes(reply), CTRL_ATTR_FAMILY_ID)
+                    .filter(|id| id.len() >= 2)
+                    .map(|id| u16::from_ne_bytes([id[0], id[1]]))
+            })
+            .ok_or_else(|| Error::msg(format!("Netlink family {family_name} is not available")))?;
+        Ok(socket)
+    }
+
+    /// Sends a command and waits for the kernel to acknowledge it.
+    /// Returns the attribute payload of each reply received first.
+    pub(crate) fn request(&mut self, command: u8, version: u8, attributes: &Attributes) -> Result<Vec<Vec<u8>>> {
+        self.sequence += 1;
+        let payload = attributes.bytes();
+        let mut message = Vec::with_capacity(NLMSG_HEADER_LEN + GENL_HEADER_LEN + payload.len());
+        message.extend_from_slice(&((NLMSG_HEADER_LEN + GENL_HEADER_LEN + payload.len()) as u32).to_ne_bytes());
+        message.extend_from_slice(&self.family.to_ne_bytes());
+        message.extend_from_slice(&(NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+        message.extend_from_slice(&self.sequence.to_ne_bytes());
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        message.extend_from_slice(&[command, version, 0, 0]);
+        message.extend_from_slice(payload);
+
+        let sent = unsafe { libc::send(self.fd, message.as_ptr() as *const libc::c_void, message.len(), 0) };
+        if sent < 0 {
+            return Err(Error::msg(format!("Unable to send netlink request: {}", Errno::last().desc())));
+        }
+
+        let mut replies = Vec::new();
+        let mut buffer = vec![0u8; 32768];
+        loop {
+            let received = unsafe { libc::recv(self.fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len(), 0) };
+            if received < 0 {
+                return Err(Error::msg(format!("Unable to receive netlink reply: {}", Errno::last().desc())));
+            }
+            let mut data = &buffer[..received as usize];
+            while data.len() >= NLMSG_HEADER_LEN {
+                let len = u32::from_ne_bytes([data[0], data[1], data[2], data[3]]) as usize;
+                let kind = u16::from_ne_bytes([data[4], data[5]]);
+                let sequence = u32::from_ne_bytes([data[8], data[9], data[10], data[11]]);
+                if len < NLMSG_HEADER_LEN || len > data.len() {
+                    break;
+                }
+                let body = &data[NLMSG_HEADER_LEN..len];
+                data = &data[usize::min(align(len), data.len())..];
+                if sequence != self.sequence {
+                    continue;
+                }
+                match kind {
+                    NLMSG_ERROR if body.len() >= 4 => {
+                        let error = i32::from_ne_bytes([body[0], body[1], body[2], body[3]]);
+                        return if error == 0 {
+                            Ok(replies)
+                        } else {
+                            Err(Error::msg(Errno::from_i32(-error).desc()))
+                        };
+                    }
+                    NLMSG_DONE => return Ok(replies),
+                    k if k == self.family && body.len() >= GENL_HEADER_LEN => {
+                        replies.push(body[GENL_HEADER_LEN..].to_vec());
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+impl Drop for GenlSocket {
+    fn drop(&mut self) {
+        let _ = nix::unistd::close(self.fd);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn nested_attributes_round_trip() {
+        let mut attributes = Attributes::new();
+        attributes
+            .begin_nest(1)
+            .string(2, "eth1")
+            .end_nest()
+            .u32(3, 42)
+            .flag(4);
+        let parsed = parse_attributes(attributes.bytes());
+        assert_eq!(parsed.len(), 3);
+        let header = parse_attributes(find(&parsed, 1).unwrap());
+        assert_eq!(find_string(&header, 2), Some("eth1".to_string()));
+        assert_eq!(find_u32(&parsed, 3), Some(42));
+        assert_eq!(find(&parsed, 4), Some(&[][..]));
+    }
+}
@@ -0,0 +1,75 @@
+use std::time::Duration;
+use anyhow::{Error, Result};
+use nix::time::{clock_gettime, ClockId};
+use crate::{MapBackend, XdpIpAddress};
+
+/// Key of the XDP `flow_tracker` map. Flows are directional: each
+/// direction of a conversation has its own entry.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct FlowKey {
+    /// Source address
+    pub src: XdpIpAddress,
+
+    /// Destination address
+    pub dst: XdpIpAddress,
+
+    /// TCP/UDP source port, 0 for other protocols.
+    pub src_port: u16,
+
+    /// TCP/UDP destination port, 0 for other protocols.
+    pub dst_port: u16,
+
+    /// IP protocol number (6 = TCP, 17 = UDP, etc.)
+    pub protocol: u8,
+
+    pad: [u8; 3],
+}
+
+/// Counters from the XDP `flow_tracker` map.
+#[repr(C)]
+#[derive(Debug, Clone, Default)]
+pub struct FlowCounter {
+    /// Kernel monotonic time (in nanoseconds) at which the flow was
+    /// last seen. Compare with `kernel_now`.
+    pub last_seen: u64,
+
+    /// Bytes counter (keeps incrementing)
+    pub bytes: u64,
+
+    /// Packets counter (keeps incrementing)
+    pub packets: u64,
+
+    /// Mapped TC handle, 0 if there isn't one.
+    pub tc_handle: u32,
+
+    /// Most recent TCP round-trip time. Convert to an `f32` and divide by `100.0` for milliseconds. 0 if there isn't one.
+    pub last_rtt: u32,
+}
+
+/// Queries the underlying `flow_tracker` eBPF pinned map, and returns
+/// every entry. The map will be empty unless flow tracking is enabled
+/// in `/etc/lqos`.
+pub fn get_flow_map(maps: &impl MapBackend) -> Result<Vec<(FlowKey, FlowCounter)>> {
+    maps.dump("flow_tracker")
+}
+
+/// Returns the kernel's monotonic clock, in the same units as
+/// `FlowCounter::last_seen`.
+pub fn kernel_now() -> Result<Duration> {
+    let now = clock_gettime(ClockId::CLOCK_MONOTONIC)
+        .map_err(|e| Error::msg(format!("Unable to read monotonic clock: {e}")))?;
+    Ok(Duration::from(now))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sizes_match_c_structs() {
+        // struct flow_key and struct flow_counter in flows.h
+        assert_eq!(std::mem::size_of::<FlowKey>(), 40);
+        assert_eq!(std::mem::size_of::<FlowCounter>(), 32);
+    }
+}
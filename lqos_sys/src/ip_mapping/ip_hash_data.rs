@@ -1,5 +1,5 @@
 #[repr(C)]
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct IpHashData {
     pub cpu: u32,
     pub tc_handle: u32,
@@ -1,5 +1,5 @@
 #[repr(C)]
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct IpHashKey {
     pub prefixlen: u32,
     pub address: [u8; 16],
@@ -1,7 +1,7 @@
 use anyhow::Result;
 use lqos_bus::TcHandle;
 use std::net::IpAddr;
-use crate::{bpf_map::BpfMap, XdpIpAddress};
+use crate::{MapBackend, XdpIpAddress};
 mod ip_to_map;
 mod ip_hash_data;
 mod ip_hash_key;
@@ -9,33 +9,38 @@ use ip_to_map::IpToMap;
 use ip_hash_data::IpHashData;
 use ip_hash_key::IpHashKey;
 
+const IP_TO_CPU_MAP: &str = "map_ip_to_cpu_and_tc";
+const IP_TO_CPU_MAP_RECIP: &str = "map_ip_to_cpu_and_tc_recip";
+const IGNORE_SUBNETS_MAP: &str = "map_ignore_subnets";
+
+fn ip_map_name(upload: bool) -> &'static str {
+    if upload {
+        IP_TO_CPU_MAP_RECIP
+    } else {
+        IP_TO_CPU_MAP
+    }
+}
+
 /// Adds an IP address to the underlying TC map.
 /// 
 /// ## Arguments
 /// 
+/// * `maps` - the map backend to use.
 /// * `address` - a string containing an IPv4 or IPv6 address, with or without a prefix-length.
 /// * `tc_handle` - the TC classifier handle to associate with the IP address, in (major,minor) format.
 /// * `cpu` - the CPU index on which the TC class should be handled.
-pub fn add_ip_to_tc(address: &str, tc_handle: TcHandle, cpu: u32, upload: bool) -> Result<()> {
-    let bpf_path = if upload {
-        "/sys/fs/bpf/map_ip_to_cpu_and_tc_recip"
-    } else {
-        "/sys/fs/bpf/map_ip_to_cpu_and_tc"
-    };
-
+pub fn add_ip_to_tc(maps: &impl MapBackend, address: &str, tc_handle: TcHandle, cpu: u32, upload: bool) -> Result<()> {
     let ip_to_add = IpToMap::new(address, tc_handle, cpu)?;
-    let mut bpf_map =
-        BpfMap::<IpHashKey, IpHashData>::from_path(bpf_path)?;
     let address = XdpIpAddress::from_ip(ip_to_add.subnet);
-    let mut key = IpHashKey {
+    let key = IpHashKey {
         prefixlen: ip_to_add.prefix,
         address: address.0,
     };
-    let mut value = IpHashData {
+    let value = IpHashData {
         cpu: ip_to_add.cpu,
         tc_handle: ip_to_add.handle(),
     };
-    bpf_map.insert(&mut key, &mut value)?;
+    maps.insert(ip_map_name(upload), &key, &value)?;
     Ok(())
 }
 
@@ -43,47 +48,92 @@ pub fn add_ip_to_tc(address: &str, tc_handle: TcHandle, cpu: u32, upload: bool)
 /// 
 /// ## Arguments
 /// 
+/// * `maps` - the map backend to use.
 /// * `address` - the IP address to remove. If no prefix (e.g. `/24`) is provided, the longest prefix to match a single IP address will be assumed.
-pub fn del_ip_from_tc(address: &str, upload: bool) -> Result<()> {
-    let bpf_path = if upload {
-        "/sys/fs/bpf/map_ip_to_cpu_and_tc_recip"
-    } else {
-        "/sys/fs/bpf/map_ip_to_cpu_and_tc"
-    };
+pub fn del_ip_from_tc(maps: &impl MapBackend, address: &str, upload: bool) -> Result<()> {
     let ip_to_add = IpToMap::new(address, TcHandle::from_string("0:0")?, 0)?;
-    let mut bpf_map =
-        BpfMap::<IpHashKey, IpHashData>::from_path(bpf_path)?;
     let ip = address.parse::<IpAddr>()?;
     let ip = XdpIpAddress::from_ip(ip);
-    let mut key = IpHashKey {
+    let key = IpHashKey {
         prefixlen: ip_to_add.prefix,
         address: ip.0,
     };
-    bpf_map.delete(&mut key)?;
+    maps.delete::<IpHashKey, IpHashData>(ip_map_name(upload), &key)?;
     Ok(())
 }
 
 /// Remove all IP addresses from the underlying TC map.
-pub fn clear_ips_from_tc() -> Result<()> {
-    let mut bpf_map =
-        BpfMap::<IpHashKey, IpHashData>::from_path("/sys/fs/bpf/map_ip_to_cpu_and_tc")?;
-    bpf_map.clear()?;
-
-    let mut bpf_map =
-        BpfMap::<IpHashKey, IpHashData>::from_path("/sys/fs/bpf/map_ip_to_cpu_and_tc_recip")?;
-    bpf_map.clear()?;
+pub fn clear_ips_from_tc(maps: &impl MapBackend) -> Result<()> {
+    maps.clear::<IpHashKey, IpHashData>(IP_TO_CPU_MAP)?;
+    maps.clear::<IpHashKey, IpHashData>(IP_TO_CPU_MAP_RECIP)?;
 
     Ok(())
 }
 
 /// Query the underlying IP address to TC map and return the currently active dataset.
-pub fn list_mapped_ips() -> Result<Vec<(IpHashKey, IpHashData)>> {
-    let bpf_map = BpfMap::<IpHashKey, IpHashData>::from_path("/sys/fs/bpf/map_ip_to_cpu_and_tc")?;
-    let mut raw = bpf_map.dump_vec();
-
-    let bpf_map2 = BpfMap::<IpHashKey, IpHashData>::from_path("/sys/fs/bpf/map_ip_to_cpu_and_tc_recip")?;
-    let raw2 = bpf_map2.dump_vec();
+pub fn list_mapped_ips(maps: &impl MapBackend) -> Result<Vec<(IpHashKey, IpHashData)>> {
+    let mut raw = maps.dump(IP_TO_CPU_MAP)?;
+    let raw2 = maps.dump(IP_TO_CPU_MAP_RECIP)?;
     raw.extend_from_slice(&raw2);
 
     Ok(raw)
 }
+
+/// Replaces the list of subnets that the XDP program will not track in
+/// `map_traffic`. Traffic to/from these subnets is still shaped.
+/// Passing an empty list re-enables tracking for everything.
+///
+/// ## Arguments
+///
+/// * `maps` - the map backend to use.
+/// * `subnets` - IPv4 or IPv6 subnets, with or without a prefix-length.
+pub fn set_ignored_subnets(maps: &impl MapBackend, subnets: &[String]) -> Result<()> {
+    maps.clear::<IpHashKey, u32>(IGNORE_SUBNETS_MAP)?;
+    for subnet in subnets.iter() {
+        let to_ignore = IpToMap::new(subnet, TcHandle::from_string("0:0")?, 0)?;
+        let address = XdpIpAddress::from_ip(to_ignore.subnet);
+        let key = IpHashKey {
+            prefixlen: to_ignore.prefix,
+            address: address.0,
+        };
+        maps.insert(IGNORE_SUBNETS_MAP, &key, &1u32)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::InMemoryMaps;
+
+    #[test]
+    fn add_list_and_delete() {
+        let maps = InMemoryMaps::new();
+        let handle = TcHandle::from_string("1:5").unwrap();
+        add_ip_to_tc(&maps, "192.168.1.1", handle, 2, false).unwrap();
+        add_ip_to_tc(&maps, "10.0.0.0/24", handle, 2, true).unwrap();
+        assert!(add_ip_to_tc(&maps, "192.168.1.1", handle, 2, false).is_err());
+
+        let mapped = list_mapped_ips(&maps).unwrap();
+        assert_eq!(mapped.len(), 2);
+        assert_eq!(mapped[0].0.prefixlen, 128);
+        assert_eq!(XdpIpAddress(mapped[0].0.address).as_ip().to_string(), "192.168.1.1");
+        assert_eq!(mapped[0].1.tc_handle, handle.as_u32());
+        assert_eq!(mapped[0].1.cpu, 2);
+        assert_eq!(mapped[1].0.prefixlen, 120);
+
+        del_ip_from_tc(&maps, "192.168.1.1", false).unwrap();
+        assert_eq!(list_mapped_ips(&maps).unwrap().len(), 1);
+        clear_ips_from_tc(&maps).unwrap();
+        assert!(list_mapped_ips(&maps).unwrap().is_empty());
+    }
+
+    #[test]
+    fn ignored_subnets_are_replaced() {
+        let maps = InMemoryMaps::new();
+        set_ignored_subnets(&maps, &["10.0.0.0/8".to_string(), "fd00::/8".to_string()]).unwrap();
+        assert_eq!(maps.dump::<IpHashKey, u32>(IGNORE_SUBNETS_MAP).unwrap().len(), 2);
+        set_ignored_subnets(&maps, &[]).unwrap();
+        assert!(maps.dump::<IpHashKey, u32>(IGNORE_SUBNETS_MAP).unwrap().is_empty());
+    }
+}
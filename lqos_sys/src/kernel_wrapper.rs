@@ -1,14 +1,47 @@
-use crate::lqos_kernel::{
-    attach_xdp_and_tc_to_interface, unload_xdp_from_interface, InterfaceDirection,
+use crate::{
+    cpu_map::CpuMapping,
+    nic_tuning::NicTuning,
+    lqos_kernel::{attach_xdp_and_tc_to_interface, unload_xdp_from_interface, InterfaceDirection},
+    pinned_maps::{pinned_map_ids, unpin_maps},
 };
+use lqos_bus::{InterfaceStatus, KernelStatus};
+use lqos_config::{EtcLqos, KernelConfig};
 
 /// A wrapper-type that stores the interfaces to which the XDP and TC programs should
 /// be attached. Performs the attachment process, and hooks "drop" to unattach the
 /// programs when the structure falls out of scope.
+///
+/// Maps are pinned under the `pin_root` from the `[kernel]` section of `/etc/lqos`,
+/// and removed on drop if `unpin_on_exit` is set. If `detach_on_exit` is `false`,
+/// nothing is detached on drop; programs left attached are replaced atomically
+/// (keeping their maps) the next time the kernels are attached.
+///
+/// XPS (and, if `stop_irq_balance` is set, queue IRQ affinity) is lined up with
+/// the CPU mapping on each interface, and put back the way it was on detach.
 pub struct LibreQoSKernels {
     to_internet: String,
     to_isp: String,
     on_a_stick: bool,
+    kernel_config: KernelConfig,
+    interfaces: Vec<InterfaceStatus>,
+    nic_tuning: NicTuning,
+}
+
+fn kernel_config() -> KernelConfig {
+    EtcLqos::load()
+        .ok()
+        .and_then(|etc| etc.kernel)
+        .unwrap_or_default()
+}
+
+/// IRQs are only pinned if irqbalance has been stopped; otherwise it
+/// would move them again.
+fn pin_irqs() -> bool {
+    EtcLqos::load()
+        .ok()
+        .and_then(|etc| etc.tuning)
+        .map(|tuning| tuning.stop_irq_balance)
+        .unwrap_or(false)
 }
 
 impl LibreQoSKernels {
@@ -23,13 +56,19 @@ impl LibreQoSKernels {
     /// * `to_internet` - the name of the Internet-facing interface (e.g. `eth1`).
     /// * `to_isp` - the name of the ISP-network facing interface (e.g. `eth2`).
     pub fn new<S: ToString>(to_internet: S, to_isp: S) -> anyhow::Result<Self> {
-        let kernel = Self {
+        let mut kernel = Self {
             to_internet: to_internet.to_string(),
             to_isp: to_isp.to_string(),
             on_a_stick: false,
+            kernel_config: kernel_config(),
+            interfaces: Vec::new(),
+            nic_tuning: NicTuning::default(),
         };
-        attach_xdp_and_tc_to_interface(&kernel.to_internet, InterfaceDirection::Internet)?;
-        attach_xdp_and_tc_to_interface(&kernel.to_isp, InterfaceDirection::IspNetwork)?;
+        let status = attach_xdp_and_tc_to_interface(&kernel.to_internet, InterfaceDirection::Internet, &kernel.kernel_config)?;
+        kernel.interfaces.push(status);
+        let status = attach_xdp_and_tc_to_interface(&kernel.to_isp, InterfaceDirection::IspNetwork, &kernel.kernel_config)?;
+        kernel.interfaces.push(status);
+        kernel.tune_nics();
         Ok(kernel)
     }
 
@@ -45,24 +84,76 @@ impl LibreQoSKernels {
     /// * `internet_vlan` - the VLAN ID facing the Internet. Endianness is fixed for you.
     /// * `isp_vlan` - the VLAN ID facing the ISP core router. Endianness is fixed for you.
     pub fn on_a_stick_mode<S:ToString>(stick_interface : S, internet_vlan: u16, isp_vlan: u16) -> anyhow::Result<Self> {
-        let kernel = Self {
+        let mut kernel = Self {
             to_internet: stick_interface.to_string(),
             to_isp: String::new(),
             on_a_stick: true,
+            kernel_config: kernel_config(),
+            interfaces: Vec::new(),
+            nic_tuning: NicTuning::default(),
         };
-        attach_xdp_and_tc_to_interface(&kernel.to_internet, InterfaceDirection::OnAStick(internet_vlan, isp_vlan))?;
+        let status = attach_xdp_and_tc_to_interface(
+            &kernel.to_internet,
+            InterfaceDirection::OnAStick(internet_vlan, isp_vlan),
+            &kernel.kernel_config,
+        )?;
+        kernel.interfaces.push(status);
+        kernel.tune_nics();
 
         Ok(kernel)
     }
 }
 
+impl LibreQoSKernels {
+    /// Failing to tune a NIC isn't fatal: traffic is still shaped, just
+    /// less efficiently.
+    fn tune_nics(&mut self) {
+        let cpu_count = unsafe { libbpf_sys::libbpf_num_possible_cpus() } as u32;
+        let pin_irqs = pin_irqs();
+        for status in self.interfaces.iter() {
+            if let Err(e) = self.nic_tuning.apply(&status.interface, cpu_count, pin_irqs) {
+                eprintln!("Unable to tune {}: {e:?}", status.interface);
+            }
+        }
+    }
+
+    /// Changes which CPUs may shape traffic without re-attaching the
+    /// programs: newly excluded CPUs are removed from the cpumap (their
+    /// traffic is passed up the stack unshaped), and the rest are mapped.
+    pub fn set_excluded_cpus(&mut self, excluded_cpus: &[u32]) -> anyhow::Result<()> {
+        let cpu_map = CpuMapping::new(&self.kernel_config.pin_root)?;
+        cpu_map.mark_cpus_available(self.kernel_config.cpumap_queue_size, excluded_cpus)?;
+        self.kernel_config.excluded_cpus = excluded_cpus.to_vec();
+        Ok(())
+    }
+
+    /// Returns how the programs were attached to each interface, and
+    /// the current ids of the pinned maps.
+    pub fn status(&self) -> KernelStatus {
+        KernelStatus {
+            pin_root: self.kernel_config.pin_root.clone(),
+            interfaces: self.interfaces.clone(),
+            maps: pinned_map_ids(&self.kernel_config.pin_root),
+        }
+    }
+}
+
 impl Drop for LibreQoSKernels {
     fn drop(&mut self) {
+        if !self.kernel_config.detach_on_exit {
+            // Leave the programs (and their maps) in place, to be
+            // hot-replaced when lqosd next starts.
+            return;
+        }
         if !self.on_a_stick {
             let _ = unload_xdp_from_interface(&self.to_internet);
             let _ = unload_xdp_from_interface(&self.to_isp);
         } else {
             let _ = unload_xdp_from_interface(&self.to_internet);
         }
+        let _ = self.nic_tuning.restore();
+        if self.kernel_config.unpin_on_exit {
+            let _ = unpin_maps(&self.kernel_config.pin_root);
+        }
     }
 }
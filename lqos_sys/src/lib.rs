@@ -9,18 +9,31 @@
 mod bpf_map;
 mod bpf_per_cpu_map;
 mod cpu_map;
+mod ethtool;
+mod flows;
 mod ip_mapping;
 mod kernel_wrapper;
 mod lqos_kernel;
+mod map_backend;
+mod nic_tuning;
+mod pinned_maps;
+mod protocol_buckets;
+mod tc;
 mod tcp_rtt;
 mod throughput;
 mod xdp_ip_address;
 mod bifrost_maps;
 
-pub use ip_mapping::{add_ip_to_tc, clear_ips_from_tc, del_ip_from_tc, list_mapped_ips};
+pub use ethtool::{Coalesce, Ethtool, Features, Rings};
+pub use ip_mapping::{add_ip_to_tc, clear_ips_from_tc, del_ip_from_tc, list_mapped_ips, set_ignored_subnets};
 pub use kernel_wrapper::LibreQoSKernels;
+pub use map_backend::{InMemoryMaps, LibbpfMaps, MapBackend};
+pub use nic_tuning::NicTuning;
+pub use pinned_maps::{pin_root, unpin_maps};
 pub use tcp_rtt::{get_tcp_round_trip_times, RttTrackingEntry};
 pub use throughput::{get_throughput_map, HostCounter};
+pub use flows::{get_flow_map, kernel_now, FlowKey, FlowCounter};
+pub use protocol_buckets::{get_protocol_map, ProtocolCounters, PROTOCOL_BUCKETS};
 pub use xdp_ip_address::XdpIpAddress;
 pub use lqos_kernel::max_tracked_ips;
 pub use libbpf_sys::libbpf_num_possible_cpus;
\ No newline at end of file
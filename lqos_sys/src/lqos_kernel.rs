@@ -3,13 +3,17 @@
 #![allow(non_snake_case)]
 #![allow(dead_code)]
 
-use crate::cpu_map::CpuMapping;
+use crate::{cpu_map::CpuMapping, tc::{attach_tc, remove_clsact, TcAttachPoint}};
 use anyhow::{Error, Result};
 use libbpf_sys::{
-    bpf_xdp_attach, libbpf_set_strict_mode, LIBBPF_STRICT_ALL, XDP_FLAGS_UPDATE_IF_NOEXIST, XDP_FLAGS_HW_MODE, XDP_FLAGS_DRV_MODE, XDP_FLAGS_SKB_MODE,
+    bpf_prog_get_fd_by_id, bpf_xdp_attach, bpf_xdp_attach_opts, bpf_xdp_query_id,
+    libbpf_set_strict_mode, LIBBPF_STRICT_ALL,
+    XDP_FLAGS_UPDATE_IF_NOEXIST, XDP_FLAGS_HW_MODE, XDP_FLAGS_DRV_MODE, XDP_FLAGS_SKB_MODE, XDP_FLAGS_REPLACE,
 };
+use lqos_bus::{InterfaceStatus, XdpAttachMode};
+use lqos_config::KernelConfig;
 use nix::libc::{geteuid, if_nametoindex};
-use std::{ffi::CString, process::Command};
+use std::ffi::CString;
 
 pub(crate) mod bpf {
     #![allow(warnings, unused)]
@@ -54,10 +58,8 @@ pub fn unload_xdp_from_interface(interface_name: &str) -> Result<()> {
             return Err(Error::msg("Unable to unload from interface."));
         }
 
-        let interface_c = CString::new(interface_name)?;
-        let _ = bpf::tc_detach_egress(interface_index as i32, true, true, interface_c.as_ptr());
-        let _ = bpf::tc_detach_ingress(interface_index as i32, true, true, interface_c.as_ptr());
     }
+    remove_clsact(interface_index as u32)?;
     Ok(())
 }
 
@@ -70,8 +72,10 @@ fn set_strict_mode() -> Result<()> {
     }
 }
 
-unsafe fn open_kernel() -> Result<*mut bpf::lqos_kern> {
-    let result = bpf::lqos_kern_open();
+unsafe fn open_kernel(pin_root: &str) -> Result<*mut bpf::lqos_kern> {
+    std::fs::create_dir_all(pin_root)?;
+    let pin_root_c = CString::new(pin_root)?;
+    let result = bpf::lqos_kern_open(pin_root_c.as_ptr());
     if result.is_null() {
         Err(Error::msg("Unable to open LibreQoS XDP/TC Kernel"))
     } else {
@@ -95,16 +99,31 @@ pub enum InterfaceDirection {
     OnAStick(u16, u16),
 }
 
+impl InterfaceDirection {
+    fn describe(&self) -> &'static str {
+        match self {
+            InterfaceDirection::Internet => "Internet",
+            InterfaceDirection::IspNetwork => "ISP",
+            InterfaceDirection::OnAStick(..) => "On a stick",
+        }
+    }
+}
+
 pub fn attach_xdp_and_tc_to_interface(
     interface_name: &str,
     direction: InterfaceDirection,
-) -> Result<()> {
+    kernel_config: &KernelConfig,
+) -> Result<InterfaceStatus> {
+    let pin_root = &kernel_config.pin_root;
+    let allow_generic = !kernel_config.require_native_xdp;
     check_root()?;
     // Check the interface is valid
     let interface_index = interface_name_to_index(interface_name)?;
     set_strict_mode()?;
+    let mut hot_replace = false;
+    crate::pinned_maps::unpin_outdated_maps(pin_root)?;
     let skeleton = unsafe {
-        let skeleton = open_kernel()?;
+        let skeleton = open_kernel(pin_root)?;
         (*(*skeleton).data).direction = match direction {
             InterfaceDirection::Internet => 1,
             InterfaceDirection::IspNetwork => 2,
@@ -114,45 +133,67 @@ pub fn attach_xdp_and_tc_to_interface(
             (*(*skeleton).bss).internet_vlan = internet.to_be();
             (*(*skeleton).bss).isp_vlan = isp.to_be();
         }
+        let tracking = tracking_config();
+        (*(*skeleton).bss).flow_tracking = match tracking.flow_tracking {
+            true => 1,
+            false => 0,
+        };
+        (*(*skeleton).bss).protocol_tracking = match tracking.protocol_tracking {
+            true => 1,
+            false => 0,
+        };
         load_kernel(skeleton)?;
-        let _ = unload_xdp_from_interface(interface_name); // Ignoring error, it's ok if there isn't one
         let prog_fd = bpf::bpf_program__fd((*skeleton).progs.xdp_prog);
-        attach_xdp_best_available(interface_index, prog_fd)?;
+        if let Some((mode, old_prog_id)) = existing_xdp_program(interface_index) {
+            // Swap the programs atomically, keeping the pinned maps, so
+            // that traffic keeps flowing (and being shaped) throughout.
+            replace_xdp(interface_index, prog_fd, mode, old_prog_id)?;
+            hot_replace = true;
+        } else {
+            let _ = unload_xdp_from_interface(interface_name); // Ignoring error, it's ok if there isn't one
+            attach_xdp_best_available(interface_index, prog_fd, allow_generic)?;
+        }
         skeleton
     };
 
+    // Check what we actually ended up with
+    let (xdp_mode, xdp_program_id) = match unsafe { existing_xdp_program(interface_index) } {
+        Some((mode, id)) => (attach_mode(mode), id),
+        None => (XdpAttachMode::Unknown, 0),
+    };
+    if xdp_mode == XdpAttachMode::Generic && !allow_generic {
+        let _ = unload_xdp_from_interface(interface_name);
+        return Err(Error::msg(format!(
+            "{interface_name} only supports generic (SKB) XDP, and require_native_xdp is set"
+        )));
+    }
+    let mut status = InterfaceStatus {
+        interface: interface_name.to_string(),
+        direction: direction.describe().to_string(),
+        xdp_mode,
+        xdp_program_id,
+        hot_replaced: hot_replace,
+        tc_egress_program_id: 0,
+        tc_ingress_program_id: 0,
+    };
+
     // Configure CPU Maps
     {
-        let cpu_map = CpuMapping::new()?;
-        crate::cpu_map::xps_setup_default_disable(interface_name)?;
-        cpu_map.mark_cpus_available()?;
+        let cpu_map = CpuMapping::new(pin_root)?;
+        cpu_map.mark_cpus_available(kernel_config.cpumap_queue_size, &kernel_config.excluded_cpus)?;
         cpu_map.setup_base_txq_config()?;
     } // Scope block to ensure the CPU maps are closed
 
-    // Attach the TC program
-    // extern int tc_attach_egress(int ifindex, bool verbose, struct lqos_kern *obj);
-    // extern int tc_detach_egress(int ifindex, bool verbose, bool flush_hook, char * ifname);
-    let interface_c = CString::new(interface_name)?;
-    let _ =
-        unsafe { bpf::tc_detach_egress(interface_index as i32, true, true, interface_c.as_ptr()) }; // Ignoring error, because it's ok to not have something to detach
-
-    // Remove any previous entry
-    let r = Command::new("tc")
-        .args(["qdisc", "del", "dev", interface_name, "clsact"])
-        .output()?;
-    println!("{}", String::from_utf8(r.stderr).unwrap());
-    
-    // Add the classifier
-    let r = Command::new("tc")
-        .args(["filter", "add", "dev", interface_name, "clsact"])
-        .output()?;
-    println!("{}", String::from_utf8(r.stderr).unwrap());
-
-    // Attach to the egress
-    let error = unsafe { bpf::tc_attach_egress(interface_index as i32, true, skeleton) };
-    if error != 0 {
-        return Err(Error::msg("Unable to attach TC to interface"));
+    // Attach the TC program. When hot-replacing, the existing clsact
+    // qdisc is kept and the filter is swapped in place.
+    if !hot_replace {
+        remove_clsact(interface_index)?;
     }
+    status.tc_egress_program_id = attach_tc(
+        interface_index,
+        unsafe { bpf::bpf_program__fd((*skeleton).progs.tc_iphash_to_cpu) },
+        TcAttachPoint::Egress,
+    )?;
 
     // Attach to the ingress IF it is configured
     if let Ok(etc) = lqos_config::EtcLqos::load() {
@@ -166,27 +207,38 @@ pub fn attach_xdp_and_tc_to_interface(
                 }
 
                 // Build the interface and vlan map entries
-                crate::bifrost_maps::clear_bifrost()?;
-                crate::bifrost_maps::map_interfaces(&bridge.interface_mapping)?;
-                crate::bifrost_maps::map_vlans(&bridge.vlan_mapping)?;
+                crate::bifrost_maps::clear_bifrost(pin_root)?;
+                crate::bifrost_maps::map_interfaces(pin_root, &bridge.interface_mapping)?;
+                crate::bifrost_maps::map_vlans(pin_root, &bridge.vlan_mapping)?;
 
                 // Actually attach the TC ingress program
-                let error = unsafe { bpf::tc_attach_ingress(interface_index as i32, true, skeleton) };
-                if error != 0 {
-                    return Err(Error::msg("Unable to attach TC Ingress to interface"));
-                }
+                status.tc_ingress_program_id = attach_tc(
+                    interface_index,
+                    unsafe { bpf::bpf_program__fd((*skeleton).progs.bifrost) },
+                    TcAttachPoint::Ingress,
+                )?;
             }
         }
     }
 
-    Ok(())
+    Ok(status)
+}
+
+fn tracking_config() -> lqos_config::TrackingConfig {
+    lqos_config::EtcLqos::load()
+        .ok()
+        .and_then(|etc| etc.tracking)
+        .unwrap_or_default()
 }
 
-unsafe fn attach_xdp_best_available(interface_index: u32, prog_fd: i32) -> Result<()> {
+unsafe fn attach_xdp_best_available(interface_index: u32, prog_fd: i32, allow_generic: bool) -> Result<()> {
     // Try hardware offload first
     if try_xdp_attach(interface_index, prog_fd, XDP_FLAGS_HW_MODE).is_err() {
         // Try driver attach
         if try_xdp_attach(interface_index, prog_fd, XDP_FLAGS_DRV_MODE).is_err() {
+            if !allow_generic {
+                return Err(Error::msg("Unable to attach XDP in hardware or driver mode, and require_native_xdp is set"));
+            }
             // Try SKB mode
             if try_xdp_attach(interface_index, prog_fd, XDP_FLAGS_SKB_MODE).is_err() {
                 // Try no flags
@@ -211,6 +263,51 @@ unsafe fn attach_xdp_best_available(interface_index: u32, prog_fd: i32) -> Resul
     Ok(())
 }
 
+/// Returns the attach mode and program id of the XDP program currently
+/// attached to an interface, if there is one.
+unsafe fn existing_xdp_program(interface_index: u32) -> Option<(u32, u32)> {
+    for mode in [XDP_FLAGS_HW_MODE, XDP_FLAGS_DRV_MODE, XDP_FLAGS_SKB_MODE] {
+        let mut prog_id = 0u32;
+        if bpf_xdp_query_id(interface_index as i32, mode as i32, &mut prog_id) == 0 && prog_id != 0 {
+            return Some((mode, prog_id));
+        }
+    }
+    None
+}
+
+fn attach_mode(flags: u32) -> XdpAttachMode {
+    match flags {
+        XDP_FLAGS_HW_MODE => XdpAttachMode::Hardware,
+        XDP_FLAGS_DRV_MODE => XdpAttachMode::Driver,
+        XDP_FLAGS_SKB_MODE => XdpAttachMode::Generic,
+        _ => XdpAttachMode::Unknown,
+    }
+}
+
+/// Atomically replaces the XDP program `old_prog_id` with `prog_fd`,
+/// in the same attach mode.
+unsafe fn replace_xdp(interface_index: u32, prog_fd: i32, mode: u32, old_prog_id: u32) -> Result<()> {
+    let old_prog_fd = bpf_prog_get_fd_by_id(old_prog_id);
+    if old_prog_fd < 0 {
+        return Err(Error::msg(format!("Unable to open existing XDP program {old_prog_id}")));
+    }
+    let mut opts: bpf_xdp_attach_opts = std::mem::zeroed();
+    opts.sz = std::mem::size_of::<bpf_xdp_attach_opts>() as _;
+    opts.old_prog_fd = old_prog_fd;
+    let error = bpf_xdp_attach(
+        interface_index.try_into()?,
+        prog_fd,
+        XDP_FLAGS_REPLACE | mode,
+        &opts,
+    );
+    let _ = nix::unistd::close(old_prog_fd);
+    if error != 0 {
+        return Err(Error::msg(format!("Unable to replace XDP program {old_prog_id} ({error})")));
+    }
+    println!("Replaced XDP program {old_prog_id} without detaching");
+    Ok(())
+}
+
 unsafe fn try_xdp_attach(interface_index: u32, prog_fd: i32, connect_mode: u32) -> Result<()> {
     let error = bpf_xdp_attach(
         interface_index.try_into().unwrap(),
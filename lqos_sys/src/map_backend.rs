@@ -0,0 +1,291 @@
+use crate::{bpf_map::BpfMap, bpf_per_cpu_map::BpfPerCpuMap, pinned_maps::{map_path, pin_root}};
+use lqos_config::DEFAULT_PIN_ROOT;
+use anyhow::{Error, Result};
+use parking_lot::RwLock;
+use std::{any::Any, collections::HashMap};
+
+/// Access to the eBPF maps shared with the XDP/TC programs, by map
+/// name (e.g. `map_traffic`).
+///
+/// `LibbpfMaps` talks to the real pinned maps. `InMemoryMaps` keeps
+/// everything in userspace, so code that reads and writes maps can be
+/// exercised in `cargo test` without root or a loaded XDP program.
+///
+/// `K` and `V` must exactly match the underlying C data types.
+pub trait MapBackend: Send + Sync {
+    /// Retrieves a single entry, or `None` if the key isn't present.
+    fn get<K, V>(&self, map: &str, key: &K) -> Result<Option<V>>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static;
+
+    /// Inserts an entry. Fails if the key is already present.
+    fn insert<K, V>(&self, map: &str, key: &K, value: &V) -> Result<()>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static;
+
+    /// Deletes an entry. Fails if the key isn't present.
+    fn delete<K, V>(&self, map: &str, key: &K) -> Result<()>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static;
+
+    /// Removes every entry from a map.
+    fn clear<K, V>(&self, map: &str) -> Result<()>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static;
+
+    /// Returns every entry in a shared (not per-CPU) map.
+    fn dump<K, V>(&self, map: &str) -> Result<Vec<(K, V)>>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static;
+
+    /// Returns every entry in a per-CPU map, with one value per CPU.
+    fn dump_per_cpu<K, V>(&self, map: &str) -> Result<Vec<(K, Vec<V>)>>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static;
+}
+
+/// Maps pinned to the BPF filesystem by the LibreQoS kernels.
+pub struct LibbpfMaps {
+    pin_root: String,
+}
+
+impl LibbpfMaps {
+    /// Connects to maps pinned under `pin_root` (normally `/sys/fs/bpf`).
+    pub fn new(pin_root: &str) -> Self {
+        Self {
+            pin_root: pin_root.to_string(),
+        }
+    }
+
+    /// Connects to maps pinned under the `pin_root` configured in
+    /// `/etc/lqos`.
+    pub fn from_config() -> Self {
+        Self::new(&pin_root())
+    }
+
+    fn path(&self, map: &str) -> String {
+        map_path(&self.pin_root, map)
+    }
+}
+
+impl Default for LibbpfMaps {
+    fn default() -> Self {
+        Self::new(DEFAULT_PIN_ROOT)
+    }
+}
+
+impl MapBackend for LibbpfMaps {
+    fn get<K, V>(&self, map: &str, key: &K) -> Result<Option<V>>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static,
+    {
+        Ok(BpfMap::<K, V>::from_path(&self.path(map))?.lookup(&mut key.clone()))
+    }
+
+    fn insert<K, V>(&self, map: &str, key: &K, value: &V) -> Result<()>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static,
+    {
+        BpfMap::<K, V>::from_path(&self.path(map))?.insert(&mut key.clone(), &mut value.clone())
+    }
+
+    fn delete<K, V>(&self, map: &str, key: &K) -> Result<()>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static,
+    {
+        BpfMap::<K, V>::from_path(&self.path(map))?.delete(&mut key.clone())
+    }
+
+    fn clear<K, V>(&self, map: &str) -> Result<()>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static,
+    {
+        BpfMap::<K, V>::from_path(&self.path(map))?.clear()
+    }
+
+    fn dump<K, V>(&self, map: &str) -> Result<Vec<(K, V)>>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static,
+    {
+        Ok(BpfMap::<K, V>::from_path(&self.path(map))?.dump_vec())
+    }
+
+    fn dump_per_cpu<K, V>(&self, map: &str) -> Result<Vec<(K, Vec<V>)>>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static,
+    {
+        Ok(BpfPerCpuMap::<K, V>::from_path(&self.path(map))?.dump_vec())
+    }
+}
+
+/// Userspace stand-in for the eBPF maps. Maps are created on first
+/// use; every entry holds one value per CPU, and shared-map operations
+/// use the first. Accessing a map with a different key or value type
+/// than it was created with is an error.
+#[derive(Default)]
+pub struct InMemoryMaps {
+    maps: RwLock<HashMap<String, Box<dyn Any + Send + Sync>>>,
+}
+
+type Entries<K, V> = Vec<(K, Vec<V>)>;
+
+impl InMemoryMaps {
+    /// Creates an empty set of maps.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the per-CPU values for a key, replacing any existing entry.
+    /// Useful for simulating the counters the XDP program maintains.
+    pub fn set_per_cpu<K, V>(&self, map: &str, key: &K, values: Vec<V>) -> Result<()>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static,
+    {
+        self.with_map::<K, V, _, _>(map, |entries| {
+            match entries.iter_mut().find(|(k, _)| k == key) {
+                Some((_, existing)) => *existing = values,
+                None => entries.push((key.clone(), values)),
+            }
+            Ok(())
+        })
+    }
+
+    fn with_map<K, V, F, R>(&self, map: &str, f: F) -> Result<R>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static,
+        F: FnOnce(&mut Entries<K, V>) -> Result<R>,
+    {
+        let mut maps = self.maps.write();
+        let entries = maps
+            .entry(map.to_string())
+            .or_insert_with(|| Box::new(Entries::<K, V>::new()))
+            .downcast_mut::<Entries<K, V>>()
+            .ok_or_else(|| Error::msg(format!("Map {map} holds a different key/value type")))?;
+        f(entries)
+    }
+}
+
+impl MapBackend for InMemoryMaps {
+    fn get<K, V>(&self, map: &str, key: &K) -> Result<Option<V>>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static,
+    {
+        self.with_map::<K, V, _, _>(map, |entries| {
+            Ok(entries
+                .iter()
+                .find(|(k, _)| k == key)
+                .and_then(|(_, values)| values.first().cloned()))
+        })
+    }
+
+    fn insert<K, V>(&self, map: &str, key: &K, value: &V) -> Result<()>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static,
+    {
+        self.with_map::<K, V, _, _>(map, |entries| {
+            if entries.iter().any(|(k, _)| k == key) {
+                return Err(Error::msg(format!("Unable to insert into map {map} (key exists)")));
+            }
+            entries.push((key.clone(), vec![value.clone()]));
+            Ok(())
+        })
+    }
+
+    fn delete<K, V>(&self, map: &str, key: &K) -> Result<()>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static,
+    {
+        self.with_map::<K, V, _, _>(map, |entries| {
+            let before = entries.len();
+            entries.retain(|(k, _)| k != key);
+            if entries.len() == before {
+                Err(Error::msg(format!("Unable to delete from map {map}")))
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    fn clear<K, V>(&self, map: &str) -> Result<()>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static,
+    {
+        self.with_map::<K, V, _, _>(map, |entries| {
+            entries.clear();
+            Ok(())
+        })
+    }
+
+    fn dump<K, V>(&self, map: &str) -> Result<Vec<(K, V)>>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static,
+    {
+        self.with_map::<K, V, _, _>(map, |entries| {
+            Ok(entries
+                .iter()
+                .map(|(k, values)| (k.clone(), values.first().cloned().unwrap_or_default()))
+                .collect())
+        })
+    }
+
+    fn dump_per_cpu<K, V>(&self, map: &str) -> Result<Vec<(K, Vec<V>)>>
+    where
+        K: Default + Clone + PartialEq + Send + Sync + 'static,
+        V: Default + Clone + Send + Sync + 'static,
+    {
+        self.with_map::<K, V, _, _>(map, |entries| Ok(entries.clone()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn insert_get_delete() {
+        let maps = InMemoryMaps::new();
+        maps.insert("test", &1u32, &10u64).unwrap();
+        assert_eq!(maps.get::<u32, u64>("test", &1).unwrap(), Some(10));
+        assert_eq!(maps.get::<u32, u64>("test", &2).unwrap(), None);
+        assert!(maps.insert("test", &1u32, &20u64).is_err());
+        maps.delete::<u32, u64>("test", &1).unwrap();
+        assert!(maps.delete::<u32, u64>("test", &1).is_err());
+        assert!(maps.dump::<u32, u64>("test").unwrap().is_empty());
+    }
+
+    #[test]
+    fn per_cpu_values() {
+        let maps = InMemoryMaps::new();
+        maps.set_per_cpu("test", &1u32, vec![1u64, 2, 3]).unwrap();
+        assert_eq!(maps.dump_per_cpu::<u32, u64>("test").unwrap(), vec![(1, vec![1, 2, 3])]);
+        assert_eq!(maps.dump::<u32, u64>("test").unwrap(), vec![(1, 1)]);
+        maps.clear::<u32, u64>("test").unwrap();
+        assert!(maps.dump_per_cpu::<u32, u64>("test").unwrap().is_empty());
+    }
+
+    #[test]
+    fn type_mismatch_is_an_error() {
+        let maps = InMemoryMaps::new();
+        maps.insert("test", &1u32, &10u64).unwrap();
+        assert!(maps.dump::<u32, u32>("test").is_err());
+    }
+}
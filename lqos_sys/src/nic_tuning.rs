@@ -0,0 +1,230 @@
+use anyhow::{Error, Result};
+use std::path::{Path, PathBuf};
+
+//* Lines NIC queues up with the CPUs that shape their traffic. The
+//* TC program sends CPU n's packets out of TX queue n (`map_txq_config`
+//* uses queue_mapping = cpu + 1), so XPS for `tx-n` is set to CPU n,
+//* and each queue's IRQ is pinned to the CPU with the same number.
+//*
+//* Every value is read before it is first overwritten, so that the
+//* original settings can be put back when the programs are detached.
+//* Paths are relative to a configurable root (normally `/`), so that
+//* a fake sysfs/procfs tree can be used in tests.
+
+/// Applies (and later restores) XPS and IRQ affinity settings.
+pub struct NicTuning {
+    root: PathBuf,
+    saved: Vec<(PathBuf, String)>,
+}
+
+impl Default for NicTuning {
+    fn default() -> Self {
+        Self::new("/")
+    }
+}
+
+impl NicTuning {
+    /// Operates on `sys/` and `proc/` beneath `root`.
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            saved: Vec::new(),
+        }
+    }
+
+    /// Sets XPS for every TX queue on `interface` and, if `pin_irqs` is
+    /// set, pins every queue IRQ to its matching CPU.
+    pub fn apply(&mut self, interface: &str, cpu_count: u32, pin_irqs: bool) -> Result<()> {
+        self.set_xps(interface, cpu_count)?;
+        if pin_irqs {
+            self.pin_irqs(interface, cpu_count)?;
+        }
+        Ok(())
+    }
+
+    /// Writes back every value changed since this was created, most
+    /// recent first. Keeps going if one fails, returning the first error.
+    pub fn restore(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        while let Some((path, value)) = self.saved.pop() {
+            if let Err(e) = std::fs::write(&path, &value) {
+                println!("Unable to restore {}: {e:?}", path.display());
+                if result.is_ok() {
+                    result = Err(Error::msg(format!("Unable to restore {}", path.display())));
+                }
+            }
+        }
+        result
+    }
+
+    /// Sets `tx-n` to transmit for CPU n. Queues beyond the CPU count
+    /// aren't used by the TC program, and have XPS disabled.
+    pub fn set_xps(&mut self, interface: &str, cpu_count: u32) -> Result<()> {
+        for queue in self.queues(interface, "tx-")? {
+            let path = self.queue_dir(interface).join(format!("tx-{queue}")).join("xps_cpus");
+            if !path.exists() {
+                continue;
+            }
+            let mask = if queue < cpu_count { cpu_mask(queue) } else { "0".to_string() };
+            self.write(&path, &mask)?;
+            println!("Set XPS for {interface} tx-{queue} to {mask}");
+        }
+        Ok(())
+    }
+
+    /// Pins the IRQ of each of `interface`'s queues to the CPU with the
+    /// same number (wrapping if there are more queues than CPUs).
+    pub fn pin_irqs(&mut self, interface: &str, cpu_count: u32) -> Result<()> {
+        if cpu_count == 0 {
+            return Err(Error::msg("No CPUs to pin IRQs to"));
+        }
+        for (irq, queue) in self.queue_irqs(interface)? {
+            let cpu = queue % cpu_count;
+            let path = self.root.join(format!("proc/irq/{irq}/smp_affinity_list"));
+            self.write(&path, &cpu.to_string())?;
+            println!("Pinned {interface} queue {queue} (IRQ {irq}) to CPU {cpu}");
+        }
+        Ok(())
+    }
+
+    /// Returns the queue numbers with the given prefix (`rx-` or `tx-`),
+    /// in numeric order.
+    pub fn queues(&self, interface: &str, prefix: &str) -> Result<Vec<u32>> {
+        let mut result: Vec<u32> = std::fs::read_dir(self.queue_dir(interface))?
+            .flatten()
+            .filter_map(|entry| {
+                entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| name.strip_prefix(prefix))
+                    .and_then(|n| n.parse().ok())
+            })
+            .collect();
+        result.sort();
+        Ok(result)
+    }
+
+    /// Finds the IRQs belonging to `interface`'s queues in
+    /// `/proc/interrupts`, as (irq, queue) pairs.
+    pub fn queue_irqs(&self, interface: &str) -> Result<Vec<(u32, u32)>> {
+        let interrupts = std::fs::read_to_string(self.root.join("proc/interrupts"))?;
+        Ok(parse_interrupts(&interrupts, interface))
+    }
+
+    fn queue_dir(&self, interface: &str) -> PathBuf {
+        self.root.join(format!("sys/class/net/{interface}/queues"))
+    }
+
+    fn write(&mut self, path: &Path, value: &str) -> Result<()> {
+        if !self.saved.iter().any(|(p, _)| p == path) {
+            let original = std::fs::read_to_string(path)?;
+            self.saved.push((path.to_path_buf(), original.trim().to_string()));
+        }
+        std::fs::write(path, value)
+            .map_err(|e| Error::msg(format!("Unable to write {}: {e}", path.display())))
+    }
+}
+
+/// Drivers name queue interrupts after the interface with the queue
+/// number last, e.g. `eth0-TxRx-3` or `ens1f0-3`. Interrupts named
+/// without the interface (such as mlx5's `mlx5_comp3@pci:...`) aren't
+/// recognised.
+fn parse_interrupts(interrupts: &str, interface: &str) -> Vec<(u32, u32)> {
+    interrupts
+        .lines()
+        .filter_map(|line| {
+            let (irq, rest) = line.trim_start().split_once(':')?;
+            let irq: u32 = irq.parse().ok()?;
+            let name = rest.split_whitespace().last()?;
+            let suffix = name.strip_prefix(interface)?;
+            if !suffix.starts_with(['-', '_']) {
+                return None;
+            }
+            let digits = suffix.len() - suffix.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+            if digits == 0 {
+                return None;
+            }
+            let queue = suffix[suffix.len() - digits..].parse().ok()?;
+            Some((irq, queue))
+        })
+        .collect()
+}
+
+/// Formats a single-CPU mask the way sysfs expects it: hex, in
+/// comma-separated 32-bit words.
+fn cpu_mask(cpu: u32) -> String {
+    let mut words = vec![format!("{:x}", 1u32 << (cpu % 32))];
+    for _ in 0..cpu / 32 {
+        words.push("00000000".to_string());
+    }
+    words.join(",")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const INTERRUPTS: &str = "           CPU0       CPU1
+  24:          0          0  IR-PCI-MSI 524288-edge      eth1
+  25:        120          3  IR-PCI-MSI 524289-edge      eth1-TxRx-0
+  26:          4        999  IR-PCI-MSI 524290-edge      eth1-TxRx-1
+  27:          1          0  IR-PCI-MSI 524291-edge      eth10-TxRx-0
+ NMI:          0          0   Non-maskable interrupts
+";
+
+    fn fake_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("lqos_nic_{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for queue in ["rx-0", "rx-1", "tx-0", "tx-1", "tx-2"] {
+            let dir = root.join("sys/class/net/eth1/queues").join(queue);
+            std::fs::create_dir_all(&dir).unwrap();
+            if queue.starts_with("tx") {
+                std::fs::write(dir.join("xps_cpus"), "3\n").unwrap();
+            }
+        }
+        for irq in [25, 26] {
+            let dir = root.join(format!("proc/irq/{irq}"));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("smp_affinity_list"), "0-1\n").unwrap();
+        }
+        std::fs::write(root.join("proc/interrupts"), INTERRUPTS).unwrap();
+        root
+    }
+
+    fn read(root: &Path, path: &str) -> String {
+        std::fs::read_to_string(root.join(path)).unwrap()
+    }
+
+    #[test]
+    fn masks() {
+        assert_eq!(cpu_mask(0), "1");
+        assert_eq!(cpu_mask(5), "20");
+        assert_eq!(cpu_mask(33), "2,00000000");
+    }
+
+    #[test]
+    fn interrupts_are_matched_to_queues() {
+        assert_eq!(parse_interrupts(INTERRUPTS, "eth1"), vec![(25, 0), (26, 1)]);
+        assert_eq!(parse_interrupts(INTERRUPTS, "eth10"), vec![(27, 0)]);
+    }
+
+    #[test]
+    fn apply_and_restore() {
+        let root = fake_root("apply");
+        let mut tuning = NicTuning::new(&root);
+        assert_eq!(tuning.queues("eth1", "tx-").unwrap(), vec![0, 1, 2]);
+        tuning.apply("eth1", 2, true).unwrap();
+        assert_eq!(read(&root, "sys/class/net/eth1/queues/tx-0/xps_cpus"), "1");
+        assert_eq!(read(&root, "sys/class/net/eth1/queues/tx-1/xps_cpus"), "2");
+        assert_eq!(read(&root, "sys/class/net/eth1/queues/tx-2/xps_cpus"), "0");
+        assert_eq!(read(&root, "proc/irq/25/smp_affinity_list"), "0");
+        assert_eq!(read(&root, "proc/irq/26/smp_affinity_list"), "1");
+
+        // Applying twice must not lose the original values
+        tuning.apply("eth1", 2, true).unwrap();
+        tuning.restore().unwrap();
+        assert_eq!(read(&root, "sys/class/net/eth1/queues/tx-1/xps_cpus"), "3");
+        assert_eq!(read(&root, "proc/irq/26/smp_affinity_list"), "0-1");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+use anyhow::Result;
+use libbpf_sys::{bpf_map_info, bpf_obj_get, bpf_obj_get_info_by_fd, BPF_MAP_TYPE_HASH};
+use lqos_bus::MapStatus;
+use lqos_config::{EtcLqos, DEFAULT_PIN_ROOT};
+use std::{ffi::{c_void, CString}, path::Path};
+
+/// Every map the XDP/TC kernels pin by name. Keep in sync with the
+/// `LIBBPF_PIN_BY_NAME` maps in `src/bpf/common`.
+const PINNED_MAPS: [&str; 14] = [
+    "map_traffic",
+    "map_ip_to_cpu_and_tc",
+    "map_ip_to_cpu_and_tc_recip",
+    "map_ignore_subnets",
+    "map_protocol_buckets",
+    "map_txq_config",
+    "cpu_map",
+    "cpus_available",
+    "packet_ts",
+    "flow_state",
+    "rtt_tracker",
+    "flow_tracker",
+    "bifrost_interface_map",
+    "bifrost_vlan_map",
+];
+
+/// Returns the directory in which maps are pinned, from the `[kernel]`
+/// section of `/etc/lqos`. Defaults to `/sys/fs/bpf`.
+pub fn pin_root() -> String {
+    EtcLqos::load()
+        .map(|etc| etc.pin_root())
+        .unwrap_or_else(|_| DEFAULT_PIN_ROOT.to_string())
+}
+
+/// Returns the full path of a pinned map.
+pub(crate) fn map_path(pin_root: &str, map: &str) -> String {
+    format!("{}/{map}", pin_root.trim_end_matches('/'))
+}
+
+/// Removes every LibreQoS map pinned under `pin_root`, replacing
+/// `remove_pinned_maps.sh`. The directory itself is removed too if it
+/// is empty afterwards, unless it is the default `/sys/fs/bpf`. Only use
+/// this once the XDP/TC programs have been detached.
+pub fn unpin_maps(pin_root: &str) -> Result<()> {
+    for map in PINNED_MAPS.iter() {
+        let path = map_path(pin_root, map);
+        if Path::new(&path).exists() {
+            std::fs::remove_file(&path)?;
+        }
+    }
+    if Path::new(pin_root) != Path::new(DEFAULT_PIN_ROOT) {
+        // Fails harmlessly if anything else is pinned there
+        let _ = std::fs::remove_dir(pin_root);
+    }
+    Ok(())
+}
+
+/// Returns the kernel id of every LibreQoS map pinned under `pin_root`
+/// (0 for maps that aren't pinned).
+pub(crate) fn pinned_map_ids(pin_root: &str) -> Vec<MapStatus> {
+    PINNED_MAPS
+        .iter()
+        .map(|map| MapStatus {
+            name: map.to_string(),
+            id: pinned_map_id(&map_path(pin_root, map)).unwrap_or(0),
+        })
+        .collect()
+}
+
+/// libbpf can't reuse a pinned map whose type has changed, so maps
+/// pinned by an older version are unpinned before the programs load.
+/// Programs still attached keep using the old map until they are
+/// replaced. `cpus_available` used to be an array.
+pub(crate) fn unpin_outdated_maps(pin_root: &str) -> Result<()> {
+    let path = map_path(pin_root, "cpus_available");
+    if let Some(info) = pinned_map_info(&path) {
+        if info.type_ != BPF_MAP_TYPE_HASH {
+            std::fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+fn pinned_map_id(path: &str) -> Option<u32> {
+    pinned_map_info(path).map(|info| info.id)
+}
+
+fn pinned_map_info(path: &str) -> Option<bpf_map_info> {
+    let path_c = CString::new(path).ok()?;
+    let fd = unsafe { bpf_obj_get(path_c.as_ptr()) };
+    if fd < 0 {
+        return None;
+    }
+    let mut info: bpf_map_info = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<bpf_map_info>() as u32;
+    let err = unsafe { bpf_obj_get_info_by_fd(fd, &mut info as *mut bpf_map_info as *mut c_void, &mut len) };
+    let _ = nix::unistd::close(fd);
+    if err != 0 {
+        None
+    } else {
+        Some(info)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn map_paths() {
+        assert_eq!(map_path("/sys/fs/bpf", "map_traffic"), "/sys/fs/bpf/map_traffic");
+        assert_eq!(map_path("/sys/fs/bpf/lqos/wan1/", "cpu_map"), "/sys/fs/bpf/lqos/wan1/cpu_map");
+    }
+
+    #[test]
+    fn unpin_removes_only_our_maps() {
+        let root = std::env::temp_dir().join(format!("lqos_unpin_test_{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        for map in ["map_traffic", "rtt_tracker", "not_ours"] {
+            std::fs::write(root.join(map), "").unwrap();
+        }
+        unpin_maps(root.to_str().unwrap()).unwrap();
+        assert!(!root.join("map_traffic").exists());
+        assert!(!root.join("rtt_tracker").exists());
+        assert!(root.join("not_ours").exists());
+
+        std::fs::remove_file(root.join("not_ours")).unwrap();
+        unpin_maps(root.to_str().unwrap()).unwrap();
+        assert!(!root.exists());
+    }
+}
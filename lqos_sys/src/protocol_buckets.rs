@@ -0,0 +1,62 @@
+use anyhow::Result;
+use crate::MapBackend;
+
+/// Number of protocol buckets tracked by the XDP program. Matches
+/// `PROTOCOL_BUCKETS` in `protocol_buckets.h`.
+pub const PROTOCOL_BUCKETS: usize = 6;
+
+/// Representation of the XDP map from `map_protocol_buckets`. Arrays
+/// are indexed by bucket number (see `ProtocolBucket` in `lqos_bus`).
+#[repr(C)]
+#[derive(Debug, Clone, Default)]
+pub struct ProtocolCounters {
+    /// Download bytes per bucket (keeps incrementing)
+    pub download_bytes: [u64; PROTOCOL_BUCKETS],
+
+    /// Upload bytes per bucket (keeps incrementing)
+    pub upload_bytes: [u64; PROTOCOL_BUCKETS],
+}
+
+/// Queries the underlying `map_protocol_buckets` eBPF pinned map, and
+/// returns every entry keyed by TC handle, summed across CPUs. Unmapped
+/// traffic is counted against TC handle 0.
+pub fn get_protocol_map(maps: &impl MapBackend) -> Result<Vec<(u32, ProtocolCounters)>> {
+    Ok(maps
+        .dump_per_cpu::<u32, ProtocolCounters>("map_protocol_buckets")?
+        .into_iter()
+        .map(|(tc_handle, per_cpu)| {
+            let mut total = ProtocolCounters::default();
+            for counters in per_cpu.iter() {
+                for i in 0..PROTOCOL_BUCKETS {
+                    total.download_bytes[i] += counters.download_bytes[i];
+                    total.upload_bytes[i] += counters.upload_bytes[i];
+                }
+            }
+            (tc_handle, total)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::InMemoryMaps;
+
+    #[test]
+    fn counters_are_summed_across_cpus() {
+        let maps = InMemoryMaps::new();
+        let mut cpu0 = ProtocolCounters::default();
+        cpu0.download_bytes[1] = 100;
+        let mut cpu1 = ProtocolCounters::default();
+        cpu1.download_bytes[1] = 50;
+        cpu1.upload_bytes[5] = 7;
+        maps.set_per_cpu("map_protocol_buckets", &0u32, vec![cpu0, cpu1]).unwrap();
+
+        let result = get_protocol_map(&maps).unwrap();
+        assert_eq!(result.len(), 1);
+        let (tc_handle, total) = &result[0];
+        assert_eq!(*tc_handle, 0);
+        assert_eq!(total.download_bytes[1], 150);
+        assert_eq!(total.upload_bytes[5], 7);
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::{Error, Result};
+use libbpf_sys::{
+    bpf_tc_attach, bpf_tc_attach_point, bpf_tc_hook, bpf_tc_hook_create, bpf_tc_hook_destroy, bpf_tc_opts,
+    BPF_TC_EGRESS, BPF_TC_F_REPLACE, BPF_TC_INGRESS,
+};
+use nix::errno::Errno;
+
+//* Attaches the TC-BPF programs through libbpf, rather than shelling
+//* out to `tc`. Filters live on a `clsact` qdisc, which is created on
+//* demand.
+
+/// Handle and priority of the LibreQoS filters. Replacing a filter
+/// requires the same pair.
+const TC_HANDLE: u32 = 0x1;
+const TC_PRIORITY: u32 = 0xC02F;
+
+/// Which side of the `clsact` qdisc to attach to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TcAttachPoint {
+    Ingress,
+    Egress,
+}
+
+impl TcAttachPoint {
+    fn as_libbpf(&self) -> bpf_tc_attach_point {
+        match self {
+            TcAttachPoint::Ingress => BPF_TC_INGRESS,
+            TcAttachPoint::Egress => BPF_TC_EGRESS,
+        }
+    }
+}
+
+fn libbpf_error(action: &str, interface_index: u32, err: i32) -> Error {
+    Error::msg(format!(
+        "{action} failed for interface #{interface_index}: {}",
+        Errno::from_i32(-err).desc()
+    ))
+}
+
+fn tc_hook(interface_index: u32, attach_point: bpf_tc_attach_point) -> bpf_tc_hook {
+    let mut hook: bpf_tc_hook = unsafe { std::mem::zeroed() };
+    hook.sz = std::mem::size_of::<bpf_tc_hook>() as _;
+    hook.ifindex = interface_index as i32;
+    hook.attach_point = attach_point;
+    hook
+}
+
+/// Removes the `clsact` qdisc from an interface, detaching every TC-BPF
+/// program on both sides. It isn't an error if there isn't one.
+pub(crate) fn remove_clsact(interface_index: u32) -> Result<()> {
+    let mut hook = tc_hook(interface_index, BPF_TC_INGRESS | BPF_TC_EGRESS);
+    let err = unsafe { bpf_tc_hook_destroy(&mut hook) };
+    match err {
+        0 => Ok(()),
+        e if e == -(Errno::ENOENT as i32) || e == -(Errno::EINVAL as i32) => Ok(()),
+        e => Err(libbpf_error("Removing clsact qdisc", interface_index, e)),
+    }
+}
+
+/// Attaches a TC-BPF program to an interface, creating the `clsact`
+/// qdisc if needed. An existing LibreQoS filter at the same attach
+/// point is replaced atomically. Returns the attached program's id.
+pub(crate) fn attach_tc(interface_index: u32, prog_fd: i32, attach_point: TcAttachPoint) -> Result<u32> {
+    if prog_fd < 0 {
+        return Err(Error::msg(format!("No TC program to attach ({attach_point:?})")));
+    }
+    let mut hook = tc_hook(interface_index, attach_point.as_libbpf());
+    let err = unsafe { bpf_tc_hook_create(&mut hook) };
+    if err != 0 && err != -(Errno::EEXIST as i32) {
+        return Err(libbpf_error("Creating clsact qdisc", interface_index, err));
+    }
+
+    let mut opts: bpf_tc_opts = unsafe { std::mem::zeroed() };
+    opts.sz = std::mem::size_of::<bpf_tc_opts>() as _;
+    opts.prog_fd = prog_fd;
+    opts.flags = BPF_TC_F_REPLACE;
+    opts.handle = TC_HANDLE;
+    opts.priority = TC_PRIORITY;
+    let err = unsafe { bpf_tc_attach(&hook, &mut opts) };
+    if err != 0 {
+        return Err(libbpf_error(&format!("Attaching TC {attach_point:?} program"), interface_index, err));
+    }
+    println!("Attached TC-BPF {attach_point:?} program id:{}", opts.prog_id);
+    Ok(opts.prog_id)
+}
@@ -1,6 +1,6 @@
 use anyhow::Result;
 
-use crate::bpf_map::BpfMap;
+use crate::MapBackend;
 
 /// Entry from the XDP rtt_tracker map.
 #[repr(C)]
@@ -40,8 +40,6 @@ impl Default for RttTrackingEntry {
 /// A vector containing:
 /// * `[u8; 16]` - a byte representation of the encoded IP address. See `XdpIpAddress` for details.
 /// * An `RttTrackingEntry` structure containing the current RTT results for the IP address.
-pub fn get_tcp_round_trip_times() -> Result<Vec<([u8; 16], RttTrackingEntry)>> {
-    let rtt_tracker = BpfMap::<[u8; 16], RttTrackingEntry>::from_path("/sys/fs/bpf/rtt_tracker")?;
-    let rtt_data = rtt_tracker.dump_vec();
-    Ok(rtt_data)
+pub fn get_tcp_round_trip_times(maps: &impl MapBackend) -> Result<Vec<([u8; 16], RttTrackingEntry)>> {
+    maps.dump("rtt_tracker")
 }
@@ -1,4 +1,4 @@
-use crate::{bpf_per_cpu_map::BpfPerCpuMap, XdpIpAddress};
+use crate::{MapBackend, XdpIpAddress};
 use anyhow::Result;
 
 /// Representation of the XDP map from map_traffic
@@ -34,8 +34,6 @@ impl Default for HostCounter {
 }
 
 /// Queries the underlying `map_traffic` eBPF pinned map, and returns every entry.
-pub fn get_throughput_map() -> Result<Vec<(XdpIpAddress, Vec<HostCounter>)>> {
-    Ok(BpfPerCpuMap::<XdpIpAddress, HostCounter>::from_path(
-        "/sys/fs/bpf/map_traffic",
-    )?.dump_vec())
+pub fn get_throughput_map(maps: &impl MapBackend) -> Result<Vec<(XdpIpAddress, Vec<HostCounter>)>> {
+    maps.dump_per_cpu("map_traffic")
 }
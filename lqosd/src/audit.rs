@@ -0,0 +1,206 @@
+//! The audit log: an append-only record of every bus request that
+//! changes something, and of the node manager's edits, in the file set
+//! by the `[audit]` section of `/etc/lqos`. Each line is one JSON
+//! `AuditEntry`. The log is rotated (`.1` is the newest old log) when it
+//! grows past `max_size_bytes`.
+
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use anyhow::Result;
+use lazy_static::*;
+use log::warn;
+use lqos_bus::{bounded_parameters, AuditEntry, BusRequest, BusResponse};
+use lqos_config::{AuditConfig, EtcLqos};
+use parking_lot::{Mutex, RwLock};
+
+lazy_static! {
+    static ref AUDIT_CONFIG: RwLock<AuditConfig> = RwLock::new(AuditConfig::default());
+    /// Serializes writers, so entries aren't interleaved or lost to a
+    /// rotation.
+    static ref WRITE_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Takes the log location and rotation settings from the configuration.
+pub(crate) fn set_audit_config(etc: &EtcLqos) {
+    *AUDIT_CONFIG.write() = etc.audit.clone().unwrap_or_default();
+}
+
+/// Records a mutating request and its outcome. `client` identifies who
+/// sent it. Failures are logged as well as returned.
+pub(crate) fn record(client: &str, request: &BusRequest, response: &BusResponse) -> Result<()> {
+    let entry = match request {
+        BusRequest::RecordConfigChange { change, parameters, result } => AuditEntry {
+            timestamp: now(),
+            client: client.to_string(),
+            request: change.clone(),
+            parameters: bounded_parameters(parameters.clone()),
+            result: result.clone(),
+        },
+        _ => {
+            let (kind, parameters) = describe(request);
+            AuditEntry {
+                timestamp: now(),
+                client: client.to_string(),
+                request: kind,
+                parameters,
+                result: outcome(response),
+            }
+        }
+    };
+    let config = AUDIT_CONFIG.read().clone();
+    append(&config, &entry).map_err(|e| {
+        warn!("Unable to write to the audit log {}: {:?}", config.log_file, e);
+        e
+    })
+}
+
+/// The `n` most recent entries, newest first.
+pub(crate) fn audit_log(n: u32) -> BusResponse {
+    let config = AUDIT_CONFIG.read().clone();
+    match recent(&config, n as usize) {
+        Ok(entries) => BusResponse::AuditLog(entries),
+        Err(e) => BusResponse::Fail(format!("Unable to read the audit log: {e}")),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Splits a request into its kind (the variant name) and its parameters
+/// as JSON (`null` if it has none). Long parameters, e.g. a whole
+/// `AssignCpus` plan, are cut short.
+fn describe(request: &BusRequest) -> (String, String) {
+    match serde_json::to_value(request) {
+        Ok(serde_json::Value::String(kind)) => (kind, "null".to_string()),
+        Ok(serde_json::Value::Object(map)) if map.len() == 1 => {
+            let (kind, parameters) = map.into_iter().next().unwrap();
+            (kind, bounded_parameters(parameters.to_string()))
+        }
+        _ => (format!("{request:?}"), "null".to_string()),
+    }
+}
+
+fn outcome(response: &BusResponse) -> String {
+    match response {
+        BusResponse::Fail(reason) => format!("failed: {reason}"),
+        BusResponse::Tuning(results) => {
+            let failed = results.iter().filter(|r| r.error.is_some()).count();
+            if failed == 0 {
+                "ok".to_string()
+            } else {
+                format!("failed: {failed} of {} settings", results.len())
+            }
+        }
+        _ => "ok".to_string(),
+    }
+}
+
+fn rotated(config: &AuditConfig, generation: u32) -> String {
+    format!("{}.{generation}", config.log_file)
+}
+
+/// Shifts each old log up one generation, dropping the oldest, and
+/// moves the current log to `.1`.
+fn rotate(config: &AuditConfig) -> Result<()> {
+    if config.keep == 0 {
+        std::fs::remove_file(&config.log_file)?;
+        return Ok(());
+    }
+    for generation in (1..config.keep).rev() {
+        let from = rotated(config, generation);
+        if Path::new(&from).exists() {
+            std::fs::rename(&from, rotated(config, generation + 1))?;
+        }
+    }
+    std::fs::rename(&config.log_file, rotated(config, 1))?;
+    Ok(())
+}
+
+fn append(config: &AuditConfig, entry: &AuditEntry) -> Result<()> {
+    let line = format!("{}\n", serde_json::to_string(entry)?);
+    let _lock = WRITE_LOCK.lock();
+    if let Ok(metadata) = std::fs::metadata(&config.log_file) {
+        if metadata.len() > 0 && metadata.len() + line.len() as u64 > config.max_size_bytes {
+            rotate(config)?;
+        }
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(&config.log_file)?;
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+fn recent(config: &AuditConfig, n: usize) -> Result<Vec<AuditEntry>> {
+    let files = std::iter::once(config.log_file.clone())
+        .chain((1..=config.keep).map(|generation| rotated(config, generation)));
+    let mut entries = Vec::new();
+    for file in files {
+        if entries.len() >= n {
+            break;
+        }
+        let Ok(raw) = std::fs::read_to_string(&file) else {
+            continue;
+        };
+        entries.extend(
+            raw.lines()
+                .rev()
+                .filter_map(|line| serde_json::from_str::<AuditEntry>(line).ok())
+                .take(n - entries.len()),
+        );
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lqos_bus::{CircuitPlan, MAX_AUDIT_PARAMETERS};
+
+    #[test]
+    fn append_rotate_and_read_back() {
+        let directory = std::env::temp_dir().join(format!("lqos_audit_test_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let config = AuditConfig {
+            log_file: directory.join("audit.log").to_string_lossy().to_string(),
+            max_size_bytes: 400,
+            keep: 2,
+        };
+        let request = BusRequest::DelIpFlow { ip_address: "100.64.0.1".to_string(), upload: false };
+        let (kind, parameters) = describe(&request);
+        assert_eq!(kind, "DelIpFlow");
+        assert_eq!(parameters, r#"{"ip_address":"100.64.0.1","upload":false}"#);
+        assert_eq!(describe(&BusRequest::ClearIpFlow), ("ClearIpFlow".to_string(), "null".to_string()));
+        let plans = (0..1000).map(|i| CircuitPlan { circuit_id: i.to_string(), download_mbps: 100, upload_mbps: 20 }).collect();
+        let (kind, long) = describe(&BusRequest::AssignCpus(plans));
+        assert_eq!(kind, "AssignCpus");
+        assert!(long.len() < MAX_AUDIT_PARAMETERS + 32 && long.ends_with(" bytes)"));
+
+        for i in 0..20 {
+            let entry = AuditEntry {
+                timestamp: i,
+                client: "127.0.0.1:1234".to_string(),
+                request: kind.clone(),
+                parameters: bounded_parameters(parameters.clone()),
+                result: outcome(&BusResponse::Ack),
+            };
+            append(&config, &entry).unwrap();
+        }
+        assert!(std::fs::metadata(&config.log_file).unwrap().len() <= 400);
+        assert!(Path::new(&rotated(&config, 2)).exists());
+        assert!(!Path::new(&rotated(&config, 3)).exists());
+
+        let entries = recent(&config, 5).unwrap();
+        let timestamps: Vec<u64> = entries.iter().map(|e| e.timestamp).collect();
+        assert_eq!(timestamps, vec![19, 18, 17, 16, 15]);
+        // Older entries come from the rotated logs, and the oldest were dropped.
+        let all = recent(&config, 100).unwrap();
+        assert!(all.len() > 5 && all.len() < 20);
+        assert!(all.windows(2).all(|w| w[0].timestamp == w[1].timestamp + 1));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,230 @@
+//! Finds circuits by ID, name, IP, MAC or TC handle, and reports
+//! everything `lqosd` knows about them in one reply. `lqosd` holds the
+//! authoritative copy of `ShapedDevices.csv`, so clients don't need to
+//! keep their own.
+
+use std::{collections::{HashMap, HashSet}, net::IpAddr};
+use anyhow::{Error, Result};
+use lqos_bus::{BusResponse, CircuitInfo, CircuitQuery, IpStats, TcHandle, MAX_CIRCUITS_FOUND};
+use lqos_config::{ConfigShapedDevices, ShapedDevice};
+use crate::{
+    libreqos_tracker::{NETWORK_JSON, QUEUE_STRUCTURE, SHAPED_DEVICES},
+    network_tree::median,
+    queue_tracker::CIRCUIT_TO_QUEUE,
+    throughput_tracker,
+};
+
+/// (circuit ID, download class, upload class) for every circuit in
+/// `queuingStructure.json`.
+type CircuitHandles = Vec<(String, TcHandle, TcHandle)>;
+
+fn circuit_handles() -> CircuitHandles {
+    match &*QUEUE_STRUCTURE.read() {
+        Ok(structure) => structure
+            .iter()
+            .filter_map(|c| c.circuit_id.as_ref().map(|id| (id.clone(), c.class_id, c.up_class_id)))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn normalize_mac(mac: &str) -> String {
+    mac.trim().to_lowercase().replace('-', ":")
+}
+
+/// Returns the IDs of the circuits matching `query`, in
+/// `ShapedDevices.csv` order and without duplicates.
+fn matching_circuits(devices: &ConfigShapedDevices, handles: &CircuitHandles, query: &CircuitQuery) -> Result<Vec<String>> {
+    let mut result: Vec<String> = Vec::new();
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut add = |circuit_id: &str| {
+        if seen.insert(circuit_id.to_string()) {
+            result.push(circuit_id.to_string());
+        }
+    };
+    match query {
+        CircuitQuery::Id(id) => devices
+            .devices
+            .iter()
+            .filter(|d| &d.circuit_id == id)
+            .for_each(|d| add(&d.circuit_id)),
+        CircuitQuery::Name(name) => {
+            let name = name.to_lowercase();
+            devices
+                .devices
+                .iter()
+                .filter(|d| d.circuit_name.to_lowercase().contains(&name))
+                .for_each(|d| add(&d.circuit_id))
+        }
+        CircuitQuery::Ip(ip) => {
+            let ip: IpAddr = ip
+                .trim()
+                .parse()
+                .map_err(|_| Error::msg(format!("{ip} is not an IP address")))?;
+            let lookup = match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            if let Some((_, id)) = devices.trie.longest_match(lookup) {
+                add(&devices.devices[*id].circuit_id);
+            }
+        }
+        CircuitQuery::Mac(mac) => {
+            let mac = normalize_mac(mac);
+            devices
+                .devices
+                .iter()
+                .filter(|d| !d.mac.is_empty() && normalize_mac(&d.mac) == mac)
+                .for_each(|d| add(&d.circuit_id))
+        }
+        CircuitQuery::TcHandle(handle) => handles
+            .iter()
+            .filter(|(_, down, up)| down.as_u32() == handle.as_u32() || up.as_u32() == handle.as_u32())
+            .for_each(|(id, _, _)| add(id)),
+    }
+    Ok(result)
+}
+
+/// Current traffic, grouped by TC handle. Read once per request, however
+/// many circuits match.
+struct HandleTraffic {
+    bits: HashMap<u32, (u64, u64)>,
+    rtts: HashMap<u32, Vec<f32>>,
+    hosts: HashMap<u32, Vec<IpStats>>,
+}
+
+impl HandleTraffic {
+    fn now() -> Self {
+        Self {
+            bits: throughput_tracker::bits_per_tc_handle(),
+            rtts: throughput_tracker::rtt_per_tc_handle(),
+            hosts: throughput_tracker::hosts_per_tc_handle(),
+        }
+    }
+}
+
+fn circuit_info(
+    circuit_id: &str,
+    circuit_devices: &[&ShapedDevice],
+    handles: &CircuitHandles,
+    traffic: &HandleTraffic,
+) -> Option<CircuitInfo> {
+    // A circuit found by TC handle may have since left ShapedDevices.csv.
+    let first = *circuit_devices.first()?;
+
+    let network = NETWORK_JSON.read();
+    let parents = network
+        .find(&first.parent_node)
+        .map(|index| network.nodes[index].parents.iter().map(|p| network.nodes[*p].name.clone()).collect())
+        .unwrap_or_default();
+
+    let tc_handles = handles
+        .iter()
+        .find(|(id, _, _)| id == circuit_id)
+        .map(|(_, down, up)| (*down, *up));
+    let (bits_per_second, median_rtt_ms) = match tc_handles {
+        Some((down, up)) => {
+            let mut rtts = traffic.rtts.get(&down.as_u32()).cloned().unwrap_or_default();
+            (
+                (
+                    traffic.bits.get(&down.as_u32()).map(|b| b.0).unwrap_or(0),
+                    traffic.bits.get(&up.as_u32()).map(|b| b.1).unwrap_or(0),
+                ),
+                median(&mut rtts),
+            )
+        }
+        None => ((0, 0), 0.0),
+    };
+    let hosts = match tc_handles {
+        Some((down, up)) => {
+            let mut hosts: Vec<IpStats> = traffic.hosts.get(&down.as_u32()).cloned().unwrap_or_default();
+            if up.as_u32() != down.as_u32() {
+                hosts.extend(traffic.hosts.get(&up.as_u32()).into_iter().flatten().cloned());
+                hosts.sort_by(|a, b| b.bits_per_second.0.cmp(&a.bits_per_second.0));
+            }
+            hosts
+        }
+        None => Vec::new(),
+    };
+
+    let queues = CIRCUIT_TO_QUEUE
+        .read()
+        .get(circuit_id)
+        .and_then(|(down, up)| Some((down.stats()?, up.stats()?)));
+
+    Some(CircuitInfo {
+        circuit_id: circuit_id.to_string(),
+        circuit_name: first.circuit_name.clone(),
+        parent_node: first.parent_node.clone(),
+        parents,
+        plan_min_mbps: (first.download_min_mbps, first.upload_min_mbps),
+        plan_max_mbps: (first.download_max_mbps, first.upload_max_mbps),
+        devices: circuit_devices.iter().map(|d| (*d).clone()).collect(),
+        tc_handles,
+        bits_per_second,
+        median_rtt_ms,
+        hosts,
+        queues,
+    })
+}
+
+pub(crate) fn find_circuits(query: &CircuitQuery) -> BusResponse {
+    // The throughput tracker is read before ShapedDevices is locked:
+    // the unknown IP reports lock them the other way round.
+    let traffic = HandleTraffic::now();
+    let handles = circuit_handles();
+    let devices = SHAPED_DEVICES.read();
+    match matching_circuits(&devices, &handles, query) {
+        Ok(ids) => {
+            let mut by_circuit: HashMap<&str, Vec<&ShapedDevice>> = HashMap::new();
+            for device in devices.devices.iter() {
+                by_circuit.entry(device.circuit_id.as_str()).or_default().push(device);
+            }
+            BusResponse::Circuits(
+                ids.iter()
+                    .filter_map(|id| {
+                        let circuit_devices = by_circuit.get(id.as_str())?;
+                        circuit_info(id, circuit_devices, &handles, &traffic)
+                    })
+                    .take(MAX_CIRCUITS_FOUND)
+                    .collect(),
+            )
+        }
+        Err(e) => BusResponse::Fail(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use lqos_config::ShapedDevice;
+    use super::*;
+
+    fn device(circuit_id: &str, circuit_name: &str, mac: &str, ipv4: &str, prefix: u32) -> ShapedDevice {
+        ShapedDevice {
+            circuit_id: circuit_id.to_string(),
+            circuit_name: circuit_name.to_string(),
+            mac: mac.to_string(),
+            ipv4: vec![(ipv4.parse().unwrap(), prefix)],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn find_by_each_key() {
+        let devices = ConfigShapedDevices::from_devices(vec![
+            device("1", "Smith Residence", "AA:BB:CC:00:11:22", "100.64.0.1", 32),
+            device("1", "Smith Residence", "", "100.64.0.2", 32),
+            device("2", "Blacksmith Ltd", "", "100.64.1.0", 24),
+        ]);
+        let handles = vec![("2".to_string(), TcHandle::from_string("1:6").unwrap(), TcHandle::from_string("3:6").unwrap())];
+        let find = |query| matching_circuits(&devices, &handles, &query).unwrap();
+
+        assert_eq!(find(CircuitQuery::Id("2".to_string())), vec!["2"]);
+        assert_eq!(find(CircuitQuery::Name("SMITH".to_string())), vec!["1", "2"]);
+        assert_eq!(find(CircuitQuery::Ip("100.64.1.77".to_string())), vec!["2"]);
+        assert!(find(CircuitQuery::Ip("192.168.0.1".to_string())).is_empty());
+        assert_eq!(find(CircuitQuery::Mac("aa-bb-cc-00-11-22".to_string())), vec!["1"]);
+        assert_eq!(find(CircuitQuery::TcHandle(TcHandle::from_string("3:6").unwrap())), vec!["2"]);
+        assert!(matching_circuits(&devices, &handles, &CircuitQuery::Ip("bad wolf".to_string())).is_err());
+    }
+}
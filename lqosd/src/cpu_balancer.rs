@@ -0,0 +1,219 @@
+//! Decides which CPU shapes each circuit, and reports per-CPU load.
+//!
+//! Each CPU has its own HTB tree (major `cpu + 1`) and TX queue, so a
+//! circuit's CPU must match the queues LibreQoS builds for it.
+//! `AssignCpus` spreads circuits over the CPUs that aren't excluded in
+//! `/etc/lqos`, weighting each by the larger of its plan rate and its
+//! measured rate. Each CPU starts with the traffic it is measured to be
+//! shaping for circuits that aren't being assigned, so a partial list
+//! (e.g. just new circuits) fills the least busy CPUs first.
+
+use std::collections::HashMap;
+use lazy_static::*;
+use lqos_bus::{BusResponse, CircuitPlan, CpuAssignment, CpuStats};
+use parking_lot::RwLock;
+use crate::{libreqos_tracker::QUEUE_STRUCTURE, throughput_tracker};
+
+const BITS_PER_MBPS: u64 = 1_000_000;
+
+lazy_static! {
+    static ref EXCLUDED_CPUS: RwLock<Vec<u32>> = RwLock::new(Vec::new());
+}
+
+/// Sets the CPUs that must not shape traffic (`excluded_cpus` in the
+/// `[kernel]` section of `/etc/lqos`).
+pub(crate) fn set_excluded_cpus(cpus: &[u32]) {
+    *EXCLUDED_CPUS.write() = cpus.to_vec();
+}
+
+/// Returns true if circuits must not be mapped to `cpu`.
+pub(crate) fn is_excluded(cpu: u32) -> bool {
+    EXCLUDED_CPUS.read().contains(&cpu)
+}
+
+fn cpu_count() -> u32 {
+    (unsafe { lqos_sys::libbpf_num_possible_cpus() }) as u32
+}
+
+fn available_cpus() -> Vec<u32> {
+    (0..cpu_count()).filter(|cpu| !is_excluded(*cpu)).collect()
+}
+
+/// Maps a TC handle to the CPU whose HTB tree it belongs to.
+fn cpu_for_tc_handle(tc_handle: u32, cpu_count: u32) -> Option<u32> {
+    let major = tc_handle >> 16;
+    if major == 0 || cpu_count == 0 {
+        None
+    } else {
+        Some((major - 1) % cpu_count)
+    }
+}
+
+pub(crate) fn cpu_stats() -> BusResponse {
+    let cpu_count = cpu_count();
+    let mut result: Vec<CpuStats> = (0..cpu_count)
+        .map(|cpu| CpuStats {
+            cpu,
+            excluded: is_excluded(cpu),
+            circuits: 0,
+            xdp_bits_per_second: (0, 0),
+            shaped_bits_per_second: (0, 0),
+        })
+        .collect();
+
+    for (cpu, bits) in throughput_tracker::xdp_bits_per_cpu().iter().enumerate() {
+        if let Some(stats) = result.get_mut(cpu) {
+            stats.xdp_bits_per_second = *bits;
+        }
+    }
+    for (tc_handle, bits) in throughput_tracker::bits_per_tc_handle() {
+        if let Some(cpu) = cpu_for_tc_handle(tc_handle, cpu_count) {
+            let stats = &mut result[cpu as usize];
+            stats.shaped_bits_per_second.0 += bits.0;
+            stats.shaped_bits_per_second.1 += bits.1;
+        }
+    }
+    if let Ok(structure) = &*QUEUE_STRUCTURE.read() {
+        for node in structure.iter().filter(|n| n.circuit_id.is_some()) {
+            if let Some(stats) = result.get_mut(node.cpu_num as usize) {
+                stats.circuits += 1;
+            }
+        }
+    }
+
+    BusResponse::CpuStats(result)
+}
+
+/// Measured bits per second for each circuit, as (CPU, bits) for its
+/// download and upload classes.
+fn measured_circuit_bits() -> HashMap<String, Vec<(u32, u64)>> {
+    let by_handle = throughput_tracker::bits_per_tc_handle();
+    let mut result: HashMap<String, Vec<(u32, u64)>> = HashMap::new();
+    if let Ok(structure) = &*QUEUE_STRUCTURE.read() {
+        for node in structure.iter() {
+            if let Some(circuit_id) = &node.circuit_id {
+                let down = by_handle.get(&node.class_id.as_u32()).map(|b| b.0).unwrap_or(0);
+                let up = by_handle.get(&node.up_class_id.as_u32()).map(|b| b.1).unwrap_or(0);
+                let entry = result.entry(circuit_id.clone()).or_default();
+                entry.push((node.cpu_num, down));
+                entry.push((node.up_cpu_num, up));
+            }
+        }
+    }
+    result
+}
+
+/// The measured load each CPU keeps whatever happens to `plans`: the
+/// traffic of the circuits that aren't in them.
+fn base_load(measured: &HashMap<String, Vec<(u32, u64)>>, plans: &[CircuitPlan]) -> HashMap<u32, u64> {
+    let mut result = HashMap::new();
+    for (circuit_id, loads) in measured.iter() {
+        if !plans.iter().any(|plan| &plan.circuit_id == circuit_id) {
+            for (cpu, bits) in loads {
+                *result.entry(*cpu).or_insert(0) += bits;
+            }
+        }
+    }
+    result
+}
+
+pub(crate) fn assign_cpus(plans: &[CircuitPlan]) -> BusResponse {
+    let cpus = available_cpus();
+    if cpus.is_empty() {
+        return BusResponse::Fail("Every CPU is excluded".to_string());
+    }
+    let measured = measured_circuit_bits();
+    let weights: Vec<(String, u64)> = plans
+        .iter()
+        .map(|plan| {
+            let planned = (plan.download_mbps + plan.upload_mbps) * BITS_PER_MBPS;
+            let measured = measured
+                .get(&plan.circuit_id)
+                .map(|loads| loads.iter().map(|(_, bits)| bits).sum())
+                .unwrap_or(0);
+            (plan.circuit_id.clone(), u64::max(planned, measured))
+        })
+        .collect();
+    BusResponse::CpuAssignments(balance(&weights, &cpus, &base_load(&measured, plans)))
+}
+
+/// Assigns the heaviest circuits first, each to the least-loaded CPU,
+/// starting from `base_load`. Ties go to the lowest-numbered CPU, so
+/// results are repeatable.
+fn balance(weights: &[(String, u64)], cpus: &[u32], base_load: &HashMap<u32, u64>) -> Vec<CpuAssignment> {
+    let mut order: Vec<&(String, u64)> = weights.iter().collect();
+    order.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    let mut load: Vec<(u32, u64)> = cpus
+        .iter()
+        .map(|cpu| (*cpu, base_load.get(cpu).copied().unwrap_or(0)))
+        .collect();
+    order
+        .iter()
+        .map(|(circuit_id, weight)| {
+            let target = load
+                .iter_mut()
+                .min_by_key(|(cpu, load)| (*load, *cpu))
+                .unwrap();
+            target.1 += weight;
+            CpuAssignment {
+                circuit_id: circuit_id.clone(),
+                cpu: target.0,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assigned(result: &[CpuAssignment], circuit_id: &str) -> u32 {
+        result.iter().find(|a| a.circuit_id == circuit_id).unwrap().cpu
+    }
+
+    #[test]
+    fn heavy_circuits_are_spread() {
+        let weights = vec![
+            ("a".to_string(), 100),
+            ("b".to_string(), 100),
+            ("c".to_string(), 10),
+            ("d".to_string(), 10),
+        ];
+        let result = balance(&weights, &[1, 2], &HashMap::new());
+        assert_eq!(result.len(), 4);
+        assert_ne!(assigned(&result, "a"), assigned(&result, "b"));
+        assert_ne!(assigned(&result, "c"), assigned(&result, "d"));
+    }
+
+    #[test]
+    fn only_available_cpus_are_used() {
+        let weights: Vec<(String, u64)> = (0..10).map(|i| (format!("c{i}"), 10)).collect();
+        let result = balance(&weights, &[2, 3], &HashMap::new());
+        assert!(result.iter().all(|a| a.cpu == 2 || a.cpu == 3));
+        assert_eq!(result.iter().filter(|a| a.cpu == 2).count(), 5);
+    }
+
+    #[test]
+    fn busy_cpus_get_fewer_circuits() {
+        let measured = HashMap::from([
+            ("existing".to_string(), vec![(1, 900), (1, 100)]),
+            ("moving".to_string(), vec![(1, 400), (2, 100)]),
+        ]);
+        let plans = vec![CircuitPlan { circuit_id: "moving".to_string(), download_mbps: 0, upload_mbps: 0 }];
+        let base = base_load(&measured, &plans);
+        assert_eq!(base.get(&1), Some(&1000));
+
+        let weights: Vec<(String, u64)> = (0..4).map(|i| (format!("c{i}"), 100)).collect();
+        let result = balance(&weights, &[1, 2], &base);
+        assert!(result.iter().all(|a| a.cpu == 2));
+    }
+
+    #[test]
+    fn tc_handles_map_to_cpus() {
+        assert_eq!(cpu_for_tc_handle(0x0001_0005, 4), Some(0));
+        assert_eq!(cpu_for_tc_handle(0x0004_0005, 4), Some(3));
+        assert_eq!(cpu_for_tc_handle(0x0005_0005, 4), Some(0)); // On a stick upload trees
+        assert_eq!(cpu_for_tc_handle(5, 4), None);
+    }
+}
@@ -0,0 +1,118 @@
+//! Mirrors the XDP `flow_tracker` map (if flow tracking is enabled in
+//! `/etc/lqos`), calculating per-flow rates once per second.
+
+use std::{cmp::Reverse, collections::HashMap, net::IpAddr};
+use lazy_static::*;
+use lqos_bus::{BusResponse, FlowStats, TcHandle};
+use lqos_sys::{FlowCounter, FlowKey};
+use parking_lot::RwLock;
+use crate::libreqos_tracker::SHAPED_DEVICES;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+#[derive(Default)]
+struct FlowEntry {
+    bytes: u64,
+    packets: u64,
+    prev_bytes: u64,
+    prev_packets: u64,
+    bytes_per_second: u64,
+    packets_per_second: u64,
+    last_seen: u64,
+    last_rtt: u32,
+    tc_handle: u32,
+}
+
+lazy_static! {
+    static ref FLOW_TRACKER: RwLock<HashMap<FlowKey, FlowEntry>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Reads the XDP flow map and updates per-flow rates. Flows that the
+/// kernel has evicted are dropped, so memory use is bounded by the size
+/// of the XDP map. Called once per second by the throughput monitor.
+pub(crate) fn tick(flows: &[(FlowKey, FlowCounter)]) {
+    let mut tracker = FLOW_TRACKER.write();
+    if flows.is_empty() {
+        tracker.clear();
+        return;
+    }
+
+    let mut next = HashMap::with_capacity(flows.len());
+    for (key, counter) in flows.iter() {
+        let mut entry = tracker.remove(key).unwrap_or_else(|| FlowEntry {
+            // New flows have no previous data, so start from the current
+            // counters rather than reporting a huge first-second spike.
+            prev_bytes: counter.bytes,
+            prev_packets: counter.packets,
+            ..Default::default()
+        });
+        entry.bytes = counter.bytes;
+        entry.packets = counter.packets;
+        entry.bytes_per_second = entry.bytes.saturating_sub(entry.prev_bytes);
+        entry.packets_per_second = entry.packets.saturating_sub(entry.prev_packets);
+        entry.prev_bytes = entry.bytes;
+        entry.prev_packets = entry.packets;
+        entry.last_seen = counter.last_seen;
+        entry.last_rtt = counter.last_rtt;
+        entry.tc_handle = counter.tc_handle;
+        next.insert(*key, entry);
+    }
+    *tracker = next;
+}
+
+/// Returns the current byte counter, packet counter and kernel
+/// last-seen time (in nanoseconds) of every tracked flow. Used for
+/// NetFlow export.
+pub(crate) fn flow_totals() -> Vec<(FlowKey, u64, u64, u64)> {
+    FLOW_TRACKER
+        .read()
+        .iter()
+        .map(|(key, entry)| (*key, entry.bytes, entry.packets, entry.last_seen))
+        .collect()
+}
+
+fn circuit_for_ip(ip: IpAddr) -> Option<String> {
+    let lookup = match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+    let devices = SHAPED_DEVICES.read();
+    devices
+        .trie
+        .longest_match(lookup)
+        .map(|(_, idx)| devices.devices[*idx].circuit_id.clone())
+}
+
+/// Returns the `n` busiest flows (by current bits per second) to or from
+/// devices belonging to `circuit_id`.
+pub(crate) fn top_flows(circuit_id: &str, n: u32) -> BusResponse {
+    let now = lqos_sys::kernel_now()
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    let tracker = FLOW_TRACKER.read();
+    let mut result: Vec<FlowStats> = tracker
+        .iter()
+        .filter(|(key, _)| {
+            circuit_for_ip(key.src.as_ip()).as_deref() == Some(circuit_id)
+                || circuit_for_ip(key.dst.as_ip()).as_deref() == Some(circuit_id)
+        })
+        .map(|(key, entry)| FlowStats {
+            src_ip: key.src.as_ip().to_string(),
+            dst_ip: key.dst.as_ip().to_string(),
+            src_port: key.src_port,
+            dst_port: key.dst_port,
+            protocol: key.protocol,
+            bits_per_second: entry.bytes_per_second * 8,
+            packets_per_second: entry.packets_per_second,
+            total_bytes: entry.bytes,
+            total_packets: entry.packets,
+            last_rtt: entry.last_rtt as f32 / 100.0,
+            last_seen_secs_ago: now.saturating_sub(entry.last_seen) / NANOS_PER_SECOND,
+            tc_handle: TcHandle::from_u32(entry.tc_handle),
+        })
+        .collect();
+    result.sort_by_key(|flow| Reverse(flow.bits_per_second));
+    result.truncate(n as usize);
+    BusResponse::TopFlows(result)
+}
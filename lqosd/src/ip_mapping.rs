@@ -1,6 +1,13 @@
 use anyhow::Result;
+use lazy_static::*;
 use lqos_bus::{BusResponse, IpMapping, TcHandle};
-use lqos_sys::XdpIpAddress;
+use lqos_sys::{LibbpfMaps, XdpIpAddress};
+use crate::cpu_balancer;
+
+lazy_static! {
+    /// The pinned eBPF maps shared with the XDP/TC kernels.
+    pub(crate) static ref BPF_MAPS: LibbpfMaps = LibbpfMaps::from_config();
+}
 
 fn expect_ack(result: Result<()>) -> BusResponse {
     if result.is_ok() {
@@ -16,8 +23,12 @@ pub(crate) fn map_ip_to_flow(
     cpu: u32,
     upload: bool,
 ) -> BusResponse {
+    if cpu_balancer::is_excluded(cpu) {
+        return BusResponse::Fail(format!("CPU {cpu} is excluded in /etc/lqos"));
+    }
     expect_ack(lqos_sys::add_ip_to_tc(
-        &ip_address,
+        &*BPF_MAPS,
+        ip_address,
         *tc_handle,
         cpu,
         upload,
@@ -25,15 +36,15 @@ pub(crate) fn map_ip_to_flow(
 }
 
 pub(crate) fn del_ip_flow(ip_address: &str, upload: bool) -> BusResponse {
-    expect_ack(lqos_sys::del_ip_from_tc(ip_address, upload))
+    expect_ack(lqos_sys::del_ip_from_tc(&*BPF_MAPS, ip_address, upload))
 }
 
 pub(crate) fn clear_ip_flows() -> BusResponse {
-    expect_ack(lqos_sys::clear_ips_from_tc())
+    expect_ack(lqos_sys::clear_ips_from_tc(&*BPF_MAPS))
 }
 
 pub(crate) fn list_mapped_ips() -> BusResponse {
-    if let Ok(raw) = lqos_sys::list_mapped_ips() {
+    if let Ok(raw) = lqos_sys::list_mapped_ips(&*BPF_MAPS) {
         let data = raw
             .iter()
             .map(|(ip_key, ip_data)| IpMapping {
@@ -0,0 +1,37 @@
+//! Remembers how the XDP/TC programs were attached, for the
+//! `KernelStatus` bus request.
+
+use lazy_static::*;
+use log::{info, warn};
+use lqos_bus::{BusResponse, KernelStatus, XdpAttachMode};
+use parking_lot::RwLock;
+
+lazy_static! {
+    static ref KERNEL_STATUS: RwLock<Option<KernelStatus>> = RwLock::new(None);
+}
+
+/// Logs and stores the status of the freshly attached kernels.
+pub(crate) fn set_kernel_status(status: KernelStatus) {
+    for interface in status.interfaces.iter() {
+        info!(
+            "{} ({}): XDP program {} attached in {:?} mode{}, TC egress program {}",
+            interface.interface,
+            interface.direction,
+            interface.xdp_program_id,
+            interface.xdp_mode,
+            if interface.hot_replaced { " (hot-replaced)" } else { "" },
+            interface.tc_egress_program_id,
+        );
+        if interface.xdp_mode == XdpAttachMode::Generic {
+            warn!("{} is using generic (SKB) XDP, which is much slower than driver mode", interface.interface);
+        }
+    }
+    *KERNEL_STATUS.write() = Some(status);
+}
+
+pub(crate) fn kernel_status() -> BusResponse {
+    match &*KERNEL_STATUS.read() {
+        Some(status) => BusResponse::KernelStatus(status.clone()),
+        None => BusResponse::Fail("Kernels are not attached".to_string()),
+    }
+}
@@ -1,7 +1,10 @@
-//mod shaped_devices;
+mod shaped_devices;
 mod queue_structure;
 mod queueing_structure;
+mod network_json;
 
-//pub(crate) use shaped_devices::spawn_shaped_devices_monitor;
-pub(crate) use queue_structure::spawn_queue_structure_monitor;
+pub(crate) use shaped_devices::{reload_shaped_devices, spawn_shaped_devices_monitor};
+pub(crate) use shaped_devices::SHAPED_DEVICES;
+pub(crate) use queue_structure::{reload_queue_structure, spawn_queue_structure_monitor};
 pub(crate) use queue_structure::QUEUE_STRUCTURE;
+pub(crate) use network_json::NETWORK_JSON;
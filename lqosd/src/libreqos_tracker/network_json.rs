@@ -0,0 +1,26 @@
+//! Holds the network hierarchy from `network.json`.
+
+use lazy_static::*;
+use lqos_config::NetworkJson;
+use parking_lot::RwLock;
+
+lazy_static! {
+    /// The site/AP hierarchy. Reloaded alongside `queuingStructure.json`,
+    /// which LibreQoS rebuilds from `network.json`.
+    pub(crate) static ref NETWORK_JSON : RwLock<NetworkJson> = RwLock::new(load());
+}
+
+fn load() -> NetworkJson {
+    match NetworkJson::load() {
+        Ok(network) => network,
+        Err(e) => {
+            log::warn!("Unable to load network.json: {:?}", e);
+            NetworkJson::default()
+        }
+    }
+}
+
+/// Re-reads `network.json`.
+pub(crate) fn reload_network_json() {
+    *NETWORK_JSON.write() = load();
+}
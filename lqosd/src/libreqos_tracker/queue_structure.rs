@@ -4,6 +4,7 @@ use lazy_static::*;
 use parking_lot::RwLock;
 use anyhow::Result;
 use tokio::task::spawn_blocking;
+use crate::libreqos_tracker::network_json::reload_network_json;
 use crate::libreqos_tracker::queueing_structure::{QueueNetwork, read_queueing_structure, QueueNode};
 
 lazy_static! {
@@ -30,8 +31,14 @@ fn watch_for_shaped_devices_changing() -> Result<()> {
     watcher.watch(&QueueNetwork::path()?, RecursiveMode::NonRecursive)?;
     loop {
         let _ = rx.recv();
-        let new_file = read_queueing_structure();
         log::info!("queuingStructure.csv changed");
-        *QUEUE_STRUCTURE.write() = new_file;
+        reload_queue_structure();
     }
+}
+
+/// Re-reads `queuingStructure.json`, and the `network.json` it was
+/// built from.
+pub(crate) fn reload_queue_structure() {
+    *QUEUE_STRUCTURE.write() = read_queueing_structure();
+    reload_network_json();
 }
\ No newline at end of file
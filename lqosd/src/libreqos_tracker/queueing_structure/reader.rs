@@ -1,43 +1,98 @@
-use std::path::{PathBuf, Path};
+use std::{path::{PathBuf, Path}, collections::BTreeMap};
 use anyhow::{Result, Error};
 use lqos_bus::TcHandle;
-use serde_json::Value;
+use serde::{Deserialize, Deserializer, de};
 use lqos_config::EtcLqos;
 
+//* queuingStructure.json is written by LibreQoS.py. Key names vary a
+//* little between versions, so some fields accept more than one name.
+//* Keys we don't use are ignored.
+
+#[derive(Deserialize)]
+struct QueueNetworkFile {
+    #[serde(rename = "Network")]
+    network: BTreeMap<String, QueueNode>,
+}
+
 pub struct QueueNetwork {
     cpu_node: Vec<QueueNode>,
 }
 
-#[derive(Default, Clone, Debug)]
+#[derive(Default, Clone, Debug, Deserialize)]
+#[serde(default)]
 pub struct QueueNode {
+    #[serde(rename = "downloadBandwidthMbps", alias = "maxDownload")]
     pub download_bandwidth_mbps: u64,
+    #[serde(rename = "uploadBandwidthMbps", alias = "maxUpload")]
     pub upload_bandwidth_mbps: u64,
+    #[serde(rename = "downloadBandwidthMbpsMin", alias = "minDownload")]
     pub download_bandwidth_mbps_min: u64,
+    #[serde(rename = "uploadBandwidthMbpsMin", alias = "minUpload")]
     pub upload_bandwidth_mbps_min: u64,
+    #[serde(rename = "classid", deserialize_with = "tc_handle")]
     pub class_id: TcHandle,
+    #[serde(rename = "up_classid", deserialize_with = "tc_handle")]
     pub up_class_id: TcHandle,
+    #[serde(rename = "parentClassID", deserialize_with = "tc_handle")]
     pub parent_class_id: TcHandle,
+    #[serde(rename = "up_parentClassID", deserialize_with = "tc_handle")]
     pub up_parent_class_id: TcHandle,
+    #[serde(rename = "classMajor", deserialize_with = "hex_u32")]
     pub class_major: u32,
+    #[serde(rename = "up_classMajor", deserialize_with = "hex_u32")]
     pub up_class_major: u32,
+    #[serde(rename = "classMinor", deserialize_with = "hex_u32")]
     pub class_minor: u32,
+    #[serde(rename = "cpuNum", deserialize_with = "hex_u32")]
     pub cpu_num: u32,
+    #[serde(rename = "up_cpuNum", deserialize_with = "hex_u32")]
     pub up_cpu_num: u32,
     pub circuits: Vec<QueueNode>,
+    #[serde(rename = "circuitId", alias = "circuitID")]
     pub circuit_id: Option<String>,
+    #[serde(rename = "circuitName")]
     pub circuit_name: Option<String>,
+    #[serde(rename = "parentNode", alias = "ParentNode")]
     pub parent_node: Option<String>,
     pub devices: Vec<QueueNode>,
     pub comment: String,
+    #[serde(rename = "deviceId", alias = "deviceID")]
     pub device_id: Option<String>,
+    #[serde(rename = "deviceName")]
     pub device_name: Option<String>,
     pub mac: Option<String>,
+    /// IPv4 addresses/subnets. In the flattened list, circuits also
+    /// carry their devices' addresses.
+    pub ipv4s: Vec<String>,
+    /// IPv6 addresses/subnets, as for `ipv4s`.
+    pub ipv6s: Vec<String>,
 }
 
 fn read_hex_string(s: &str) -> Result<u32> {
     Ok(u32::from_str_radix(&s.replace("0x", ""), 16)?)
 }
 
+/// Accepts a hex string (`"0x1f"`) or a plain number.
+fn hex_u32<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<u32, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum HexOrNumber {
+        Number(u32),
+        Hex(String),
+    }
+    match HexOrNumber::deserialize(deserializer)? {
+        HexOrNumber::Number(n) => Ok(n),
+        HexOrNumber::Hex(s) => read_hex_string(&s)
+            .map_err(|_| de::Error::custom(format!("{s} is not a hex number"))),
+    }
+}
+
+/// Accepts a TC handle string, such as `"0x3:0x5"`.
+fn tc_handle<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<TcHandle, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    TcHandle::from_string(&s).map_err(|_| de::Error::custom(format!("{s} is not a TC handle")))
+}
+
 impl QueueNetwork {
     pub fn path() -> Result<PathBuf> {
         let cfg = EtcLqos::load()?;
@@ -59,27 +114,18 @@ impl QueueNetwork {
             return Err(Error::msg("queueStructure.json does not exist yet. Try running LibreQoS?"));
         }
         let raw_string = std::fs::read_to_string(path)?;
-        let mut result = Self {
-            cpu_node: Vec::new(),
-        };
-        let json: Value = serde_json::from_str(&raw_string)?;
-        if let Value::Object(map) = &json {
-            if let Some(network) = map.get("Network") {
-                if let Value::Object(map) = network {
-                    for (key, value) in map.iter() {
-                        result.cpu_node.push(QueueNode::from_json(&key, value)?);
-                    }
-                } else {
-                    return Err(Error::msg("Unable to parse network object structure"));
-                }
-            } else {
-                return Err(Error::msg("Network entry not found"));
-            }
-        } else {
-            return Err(Error::msg("Unable to parse queueStructure.json"));
-        }
+        Self::from_str(&raw_string)
+    }
 
-        Ok(result)
+    /// Parses queuingStructure.json. Errors name the JSON path of the
+    /// offending value, e.g. `Network.CpueQueue0.circuits[3].classid`.
+    pub(crate) fn from_str(raw: &str) -> Result<Self> {
+        let deserializer = &mut serde_json::Deserializer::from_str(raw);
+        let file: QueueNetworkFile = serde_path_to_error::deserialize(deserializer)
+            .map_err(|e| Error::msg(format!("Unable to parse queuingStructure.json at {}: {}", e.path(), e.inner())))?;
+        Ok(Self {
+            cpu_node: file.network.into_values().collect(),
+        })
     }
 
     pub fn to_flat(&self) -> Vec<QueueNode> {
@@ -90,6 +136,10 @@ impl QueueNetwork {
             result.extend_from_slice(&children);
         }
         for c in result.iter_mut() {
+            for device in c.devices.iter() {
+                c.ipv4s.extend_from_slice(&device.ipv4s);
+                c.ipv6s.extend_from_slice(&device.ipv6s);
+            }
             c.circuits.clear();
             c.devices.clear();
         }
@@ -98,56 +148,6 @@ impl QueueNetwork {
 }
 
 impl QueueNode {
-    fn from_json(key: &str, value: &Value) -> Result<Self> {
-        let mut result = Self::default();
-        if let Value::Object(map) = value {
-            for (key, value) in map.iter() {
-                match key.as_str() {
-                    "downloadBandwidthMbps" | "maxDownload" => result.download_bandwidth_mbps = value.as_u64().unwrap(),
-                    "uploadBandwidthMbps" | "maxUpload" => result.upload_bandwidth_mbps = value.as_u64().unwrap(),
-                    "downloadBandwidthMbpsMin"| "minDownload" => result.download_bandwidth_mbps_min = value.as_u64().unwrap(),
-                    "uploadBandwidthMbpsMin" | "minUpload" => result.upload_bandwidth_mbps_min = value.as_u64().unwrap(),
-                    "classid" => result.class_id = TcHandle::from_string(&value.as_str().unwrap().to_string())?,
-                    "up_classid" => result.up_class_id = TcHandle::from_string(value.as_str().unwrap().to_string())?,
-                    "classMajor" => result.class_major = read_hex_string(value.as_str().unwrap())?,
-                    "up_classMajor" => result.up_class_major = read_hex_string(value.as_str().unwrap())?,
-                    "classMinor" => result.class_minor = read_hex_string(value.as_str().unwrap())?,
-                    "cpuNum" => result.cpu_num = read_hex_string(value.as_str().unwrap())?,
-                    "up_cpuNum" => result.up_cpu_num = read_hex_string(value.as_str().unwrap())?,
-                    "parentClassID" => result.parent_class_id = TcHandle::from_string(value.as_str().unwrap().to_string())?,
-                    "up_parentClassID" => result.up_parent_class_id = TcHandle::from_string(value.as_str().unwrap().to_string())?,
-                    "circuitId" | "circuitID" => result.circuit_id = Some(value.as_str().unwrap().to_string()),
-                    "circuitName" => result.circuit_name = Some(value.as_str().unwrap().to_string()),
-                    "parentNode" | "ParentNode" => result.parent_node = Some(value.as_str().unwrap().to_string()),
-                    "comment" => result.comment = value.as_str().unwrap().to_string(),
-                    "deviceId" | "deviceID" => result.device_id = Some(value.as_str().unwrap().to_string()),
-                    "deviceName" => result.device_name = Some(value.as_str().unwrap().to_string()),
-                    "mac" => result.mac = Some(value.as_str().unwrap().to_string()),
-                    "ipv4s" => {}, // Ignore
-                    "ipv6s" => {},
-                    "circuits" => {
-                        if let Value::Array(array) = value {
-                            for c in array.iter() {
-                                result.circuits.push(QueueNode::from_json(key, c)?);
-                            }
-                        }
-                    }
-                    "devices" => {
-                        if let Value::Array(array) = value {
-                            for c in array.iter() {
-                                result.devices.push(QueueNode::from_json(key, c)?);
-                            }
-                        }
-                    }
-                    _ => log::error!("I don't know how to parse key: [{key}]"),
-                }
-            }
-        } else {
-            return Err(Error::msg(format!("Unable to parse node structure for [{key}]")));
-        }
-        Ok(result)
-    }
-
     fn to_flat(&self) -> Vec<QueueNode> {
         let mut result = Vec::new();
         for c in self.circuits.iter() {
@@ -163,3 +163,68 @@ impl QueueNode {
         result
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const STRUCTURE: &str = r#"{
+        "Network": {
+            "CpueQueue0": {
+                "classid": "0x1:0x1",
+                "up_classid": "0x3:0x1",
+                "cpuNum": "0x0",
+                "up_cpuNum": "0x2",
+                "classMajor": "0x1",
+                "up_classMajor": "0x3",
+                "classMinor": "0x3",
+                "maxDownload": 1000,
+                "maxUpload": 1000,
+                "someNewKey": true,
+                "circuits": [
+                    {
+                        "circuitID": "1",
+                        "circuitName": "Circuit 1",
+                        "ParentNode": "AP_A",
+                        "classid": "0x1:0x5",
+                        "up_classid": "0x3:0x5",
+                        "downloadBandwidthMbps": 25,
+                        "uploadBandwidthMbps": 5,
+                        "devices": [
+                            { "deviceID": "d1", "deviceName": "Device 1", "mac": "", "ipv4s": ["100.64.0.1"], "ipv6s": [] },
+                            { "deviceID": "d2", "ipv4s": ["100.64.0.2"], "ipv6s": ["fdd7:b724:0:100::/56"] }
+                        ]
+                    }
+                ]
+            }
+        }
+    }"#;
+
+    #[test]
+    fn parse_with_key_variants() {
+        let flat = QueueNetwork::from_str(STRUCTURE).unwrap().to_flat();
+        assert_eq!(flat.len(), 4);
+        assert_eq!(flat[0].download_bandwidth_mbps, 1000);
+        assert_eq!(flat[0].up_cpu_num, 2);
+
+        let circuit = &flat[1];
+        assert_eq!(circuit.circuit_id.as_deref(), Some("1"));
+        assert_eq!(circuit.parent_node.as_deref(), Some("AP_A"));
+        assert_eq!(circuit.class_id.get_major_minor(), (1, 5));
+        assert_eq!(circuit.upload_bandwidth_mbps, 5);
+        assert_eq!(circuit.ipv4s, vec!["100.64.0.1", "100.64.0.2"]);
+        assert_eq!(circuit.ipv6s, vec!["fdd7:b724:0:100::/56"]);
+        assert_eq!(flat[2].device_id.as_deref(), Some("d1"));
+    }
+
+    #[test]
+    fn errors_name_the_json_path() {
+        let bad = STRUCTURE.replace(r#""classid": "0x1:0x5""#, r#""classid": 5"#);
+        let error = QueueNetwork::from_str(&bad).err().unwrap().to_string();
+        assert!(error.contains("Network.CpueQueue0.circuits[0].classid"), "{error}");
+
+        let bad = STRUCTURE.replace(r#""maxUpload": 1000"#, r#""maxUpload": "fast""#);
+        let error = QueueNetwork::from_str(&bad).err().unwrap().to_string();
+        assert!(error.contains("Network.CpueQueue0.maxUpload"), "{error}");
+    }
+}
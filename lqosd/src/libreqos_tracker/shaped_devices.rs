@@ -10,7 +10,7 @@ lazy_static! {
     /// Global storage of the shaped devices csv data.
     /// Updated by the file system watcher whenever
     /// the underlying file changes.
-    pub(crate) static ref SHAPED_DEVICES : RwLock<ConfigShapedDevices> = RwLock::new(ConfigShapedDevices::load().unwrap());
+    pub(crate) static ref SHAPED_DEVICES : RwLock<ConfigShapedDevices> = RwLock::new(ConfigShapedDevices::load().unwrap_or_default());
 }
 
 pub async fn spawn_shaped_devices_monitor() {
@@ -30,9 +30,15 @@ fn watch_for_shaped_devices_changing() -> Result<()> {
     watcher.watch(&ConfigShapedDevices::path()?, RecursiveMode::NonRecursive)?;
     loop {
         let _ = rx.recv();
-        if let Ok(new_file) = ConfigShapedDevices::load() {
-            println!("ShapedDevices.csv changed");
-            *SHAPED_DEVICES.write() = new_file;
-        }
+        println!("ShapedDevices.csv changed");
+        reload_shaped_devices();
+    }
+}
+
+/// Re-reads `ShapedDevices.csv`, keeping the current data if it can't
+/// be loaded.
+pub(crate) fn reload_shaped_devices() {
+    if let Ok(new_file) = ConfigShapedDevices::load() {
+        *SHAPED_DEVICES.write() = new_file;
     }
 }
\ No newline at end of file
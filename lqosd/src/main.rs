@@ -1,22 +1,34 @@
+mod audit;
+mod circuit_lookup;
+mod cpu_balancer;
 mod ip_mapping;
+mod kernel_status;
+mod flow_tracker;
 mod throughput_tracker;
 mod program_control;
+mod protocol_tracker;
 mod queue_tracker;
 mod libreqos_tracker;
 #[cfg(feature = "equinix_tests")]
 mod lqos_daht_test;
 mod offloads;
+mod service;
+mod netflow;
+mod network_tree;
+mod tracking_filter;
+mod unknown_ips;
 use crate::ip_mapping::{clear_ip_flows, del_ip_flow, list_mapped_ips, map_ip_to_flow};
 use anyhow::Result;
 use lqos_bus::{
-    cookie_value, decode_request, encode_response, BusReply, BusRequest, BUS_BIND_ADDRESS,
+    cookie_value, decode_request, encode_response, request_length, BusReply, BusRequest, BusSession,
+    BUS_BIND_ADDRESS, REQUEST_HEADER_SIZE,
 };
 use lqos_config::{LibreQoSConfig, EtcLqos};
 use lqos_sys::LibreQoSKernels;
-use signal_hook::{consts::SIGINT, iterator::Signals};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream}, join,
+    signal::unix::{signal, SignalKind},
 };
 use log::{info, warn};
 
@@ -27,94 +39,178 @@ async fn main() -> Result<()> {
     let config = LibreQoSConfig::load()?;
     let etc_lqos = EtcLqos::load()?;
 
+    // Listen for signals before changing anything, so that a signal
+    // received during start-up still leads to an orderly shutdown.
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sighup = signal(SignalKind::hangup())?;
+
     // Disable offloading
     if let Some(tuning) = &etc_lqos.tuning {
-        offloads::bpf_sysctls().await;
-        if tuning.stop_irq_balance {
-            offloads::stop_irq_balance().await;
-        }        
-        offloads::netdev_budget(tuning.netdev_budget_usecs, tuning.netdev_budget_packets).await;
-        offloads::ethtool_tweaks(&config.internet_interface, tuning).await;
-        offloads::ethtool_tweaks(&config.isp_interface, tuning).await;
+        offloads::apply_tuning(tuning, &[&config.internet_interface, &config.isp_interface]).await;
     }
 
     // Start the XDP/TC kernels
     let kernels = if config.on_a_stick_mode {
-        LibreQoSKernels::on_a_stick_mode(&config.internet_interface, config.stick_vlans.1, config.stick_vlans.0)?
+        LibreQoSKernels::on_a_stick_mode(&config.internet_interface, config.stick_vlans.1, config.stick_vlans.0)
     } else {
-        LibreQoSKernels::new(&config.internet_interface, &config.isp_interface)?
+        LibreQoSKernels::new(&config.internet_interface, &config.isp_interface)
+    };
+    let mut kernels = match kernels {
+        Ok(kernels) => kernels,
+        Err(e) => {
+            offloads::restore_tuning().await;
+            return Err(e);
+        }
     };
+    kernel_status::set_kernel_status(kernels.status());
+    cpu_balancer::set_excluded_cpus(&etc_lqos.kernel.clone().unwrap_or_default().excluded_cpus);
+    audit::set_audit_config(&etc_lqos);
+
+    // Decide which addresses are reported (and tracked at all)
+    tracking_filter::setup_tracking_filter(&etc_lqos, &config);
 
     // Spawn tracking sub-systems
     join!(
         throughput_tracker::spawn_throughput_monitor(),
         queue_tracker::spawn_queue_monitor(),
-        //libreqos_tracker::spawn_shaped_devices_monitor(),
+        libreqos_tracker::spawn_shaped_devices_monitor(),
         libreqos_tracker::spawn_queue_structure_monitor(),
+        netflow::spawn_netflow_exporter(),
     );
 
-    let mut signals = Signals::new(&[SIGINT])?;
-
-    std::thread::spawn(move || {
-        for sig in signals.forever() {
-            warn!("Received signal {:?}", sig);
+    // Main bus listen loop
+    let bus_address = etc_lqos.bus_bind_address.as_deref().unwrap_or(BUS_BIND_ADDRESS);
+    let listener = match TcpListener::bind(bus_address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            offloads::restore_tuning().await;
             std::mem::drop(kernels);
-            std::process::exit(0);
+            return Err(e.into());
         }
-    });
+    };
+    info!("Listening on: {}", bus_address);
 
-    // Main bus listen loop
-    let listener = TcpListener::bind(BUS_BIND_ADDRESS).await?;
-    info!("Listening on: {}", BUS_BIND_ADDRESS);
+    service::notify_ready();
+    service::spawn_watchdog();
     loop {
-        let (mut socket, _) = listener.accept().await?;
-        tokio::spawn(async move {
-            let mut buf = vec![0; 1024];
-
-            let _ = socket
-                .read(&mut buf)
-                .await
-                .expect("failed to read data from socket");
-
-            if let Ok(request) = decode_request(&buf) {
-                if request.auth_cookie == cookie_value() {
-                    let mut response = BusReply {
-                        auth_cookie: request.auth_cookie,
-                        responses: Vec::new(),
-                    };
-                    for req in request.requests.iter() {
-                        //println!("Request: {:?}", req);
-                        response.responses.push(match req {
-                            BusRequest::Ping => lqos_bus::BusResponse::Ack,
-                            BusRequest::GetCurrentThroughput => {
-                                throughput_tracker::current_throughput()
-                            }
-                            BusRequest::GetTopNDownloaders(n) => throughput_tracker::top_n(*n),
-                            BusRequest::GetWorstRtt(n) => throughput_tracker::worst_n(*n),
-                            BusRequest::MapIpToFlow {
-                                ip_address,
-                                tc_handle,
-                                cpu,
-                                upload,
-                            } => map_ip_to_flow(ip_address, tc_handle, *cpu, *upload),
-                            BusRequest::DelIpFlow { ip_address, upload } => del_ip_flow(&ip_address, *upload),
-                            BusRequest::ClearIpFlow => clear_ip_flows(),
-                            BusRequest::ListIpFlow => list_mapped_ips(),
-                            BusRequest::XdpPping => throughput_tracker::xdp_pping_compat(),
-                            BusRequest::RttHistogram => throughput_tracker::rtt_histogram(),
-                            BusRequest::HostCounts => throughput_tracker::host_counts(),
-                            BusRequest::AllUnknownIps => throughput_tracker::all_unknown_ips(),
-                            BusRequest::ReloadLibreQoS => program_control::reload_libre_qos(),
-                            BusRequest::GetRawQueueData(circuit_id) => queue_tracker::get_raw_circuit_data(&circuit_id),
-                            #[cfg(feature = "equinix_tests")]
-                            BusRequest::RequestLqosEquinixTest => lqos_daht_test::lqos_daht_test().await,
-                        });
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((socket, _)) => {
+                    tokio::spawn(serve_bus_connection(socket));
+                }
+                Err(e) => {
+                    // e.g. out of file descriptors; don't spin on it
+                    warn!("Unable to accept a bus connection: {e}");
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                }
+            },
+            _ = sigterm.recv() => { warn!("Received SIGTERM"); break; }
+            _ = sigint.recv() => { warn!("Received SIGINT"); break; }
+            _ = sighup.recv() => {
+                warn!("Received SIGHUP");
+                service::reload(&mut kernels).await;
+            }
+        }
+    }
+
+    // Orderly shutdown: put the system back as it was, and detach (or
+    // leave) the programs as configured. The file watchers block
+    // forever, so exit rather than waiting for the runtime to wind down.
+    service::notify_stopping();
+    offloads::restore_tuning().await;
+    std::mem::drop(kernels);
+    info!("LibreQoS Daemon Stopped");
+    std::process::exit(0);
+}
+
+/// Reads one length-prefixed request. The buffer grows as the request
+/// arrives, so a client can't make `lqosd` allocate the largest request
+/// size just by claiming it in the header.
+async fn read_request<R: AsyncRead + Unpin>(socket: &mut R) -> Result<BusSession> {
+    let mut header = [0; REQUEST_HEADER_SIZE];
+    socket.read_exact(&mut header).await?;
+    let length = request_length(header)?;
+    let mut buf = Vec::with_capacity(REQUEST_HEADER_SIZE + usize::min(length, 64 * 1024));
+    buf.extend_from_slice(&header);
+    socket.take(length as u64).read_to_end(&mut buf).await?;
+    decode_request(&buf)
+}
+
+async fn serve_bus_connection(mut socket: TcpStream) {
+    let request = match read_request(&mut socket).await {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("Unable to read a bus request: {e}");
+            return;
+        }
+    };
+
+    if request.auth_cookie == cookie_value() {
+        let mut response = BusReply {
+            auth_cookie: request.auth_cookie,
+            responses: Vec::new(),
+        };
+        let peer = socket
+            .peer_addr()
+            .map(|a| a.to_string())
+            .unwrap_or_else(|_| "unknown".to_string());
+        let client = match &request.client {
+            Some(client) => format!("{client}@{peer}"),
+            None => peer,
+        };
+        for req in request.requests.iter() {
+            //println!("Request: {:?}", req);
+            let mut reply = match req {
+                BusRequest::Ping => lqos_bus::BusResponse::Ack,
+                BusRequest::GetCurrentThroughput => {
+                    throughput_tracker::current_throughput()
+                }
+                BusRequest::GetTopNDownloaders(n) => throughput_tracker::top_n(*n),
+                BusRequest::GetWorstRtt(n) => throughput_tracker::worst_n(*n),
+                BusRequest::MapIpToFlow {
+                    ip_address,
+                    tc_handle,
+                    cpu,
+                    upload,
+                } => map_ip_to_flow(ip_address, tc_handle, *cpu, *upload),
+                BusRequest::DelIpFlow { ip_address, upload } => del_ip_flow(&ip_address, *upload),
+                BusRequest::ClearIpFlow => clear_ip_flows(),
+                BusRequest::ListIpFlow => list_mapped_ips(),
+                BusRequest::XdpPping => throughput_tracker::xdp_pping_compat(),
+                BusRequest::RttHistogram => throughput_tracker::rtt_histogram(),
+                BusRequest::HostCounts => throughput_tracker::host_counts(),
+                BusRequest::AllUnknownIps => throughput_tracker::all_unknown_ips(),
+                BusRequest::UnknownIpDetails => throughput_tracker::unknown_ip_details(),
+                BusRequest::SuggestShapedDevice(ip) => unknown_ips::suggest_shaped_device(ip),
+                BusRequest::ReloadLibreQoS => program_control::reload_libre_qos(),
+                BusRequest::GetRawQueueData(circuit_id) => queue_tracker::get_raw_circuit_data(&circuit_id),
+                BusRequest::GetTopFlows { circuit_id, n } => flow_tracker::top_flows(circuit_id, *n),
+                BusRequest::GetProtocolBreakdown(circuit_id) => protocol_tracker::protocol_breakdown(circuit_id),
+                BusRequest::KernelStatus => kernel_status::kernel_status(),
+                BusRequest::CpuStats => cpu_balancer::cpu_stats(),
+                BusRequest::AssignCpus(plans) => cpu_balancer::assign_cpus(plans),
+                BusRequest::TuningStatus => offloads::tuning_status(),
+                BusRequest::NetworkTree => network_tree::network_tree(),
+                BusRequest::FindCircuits(query) => circuit_lookup::find_circuits(query),
+                BusRequest::RecordConfigChange { .. } => lqos_bus::BusResponse::Ack,
+                BusRequest::AuditLog(n) => audit::audit_log(*n),
+                BusRequest::RestoreTuning => lqos_bus::BusResponse::Tuning(offloads::restore_tuning().await),
+                #[cfg(feature = "equinix_tests")]
+                BusRequest::RequestLqosEquinixTest => lqos_daht_test::lqos_daht_test().await,
+            };
+            if req.is_mutating() {
+                if let Err(e) = audit::record(&client, req, &reply) {
+                    // Recording is the whole point of this request
+                    if let BusRequest::RecordConfigChange { .. } = req {
+                        reply = lqos_bus::BusResponse::Fail(format!("Unable to write to the audit log: {e}"));
                     }
-                    //println!("{:?}", response);
-                    let _ = reply(&encode_response(&response).unwrap(), &mut socket).await;
                 }
             }
-        });
+            response.responses.push(reply);
+        }
+        //println!("{:?}", response);
+        let _ = reply(&encode_response(&response).unwrap(), &mut socket).await;
     }
 }
 
@@ -122,3 +218,44 @@ async fn reply(response: &[u8], socket: &mut TcpStream) -> Result<()> {
     socket.write_all(&response).await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lqos_bus::{encode_request, CircuitPlan};
+    use tokio::io::duplex;
+
+    #[tokio::test]
+    async fn reads_requests_larger_than_one_read() {
+        let plans = (0..500)
+            .map(|i| CircuitPlan { circuit_id: format!("circuit-{i}"), download_mbps: 100, upload_mbps: 20 })
+            .collect();
+        let session = BusSession { auth_cookie: cookie_value(), client: None, requests: vec![BusRequest::AssignCpus(plans)] };
+        let bytes = encode_request(&session).unwrap();
+        assert!(bytes.len() > 1024);
+
+        // A small pipe delivers the request in many pieces.
+        let (mut client, mut server) = duplex(64);
+        let writer = tokio::spawn(async move { client.write_all(&bytes).await.unwrap() });
+        let request = read_request(&mut server).await.unwrap();
+        writer.await.unwrap();
+        match &request.requests[0] {
+            BusRequest::AssignCpus(plans) => assert_eq!(plans.len(), 500),
+            _ => panic!("Unexpected request"),
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_short_and_oversized_requests() {
+        // Claims 10 MB, then hangs up
+        let (mut client, mut server) = duplex(64);
+        client.write_all(&(10_000_000u32).to_le_bytes()).await.unwrap();
+        client.write_all(&[0; 10]).await.unwrap();
+        std::mem::drop(client);
+        assert!(read_request(&mut server).await.is_err());
+
+        let (mut client, mut server) = duplex(64);
+        client.write_all(&u32::MAX.to_le_bytes()).await.unwrap();
+        assert!(read_request(&mut server).await.is_err());
+    }
+}
@@ -0,0 +1,366 @@
+//! Encodes flow records as NetFlow v9 (RFC 3954) or IPFIX (RFC 7011)
+//! packets. Each packet holds only complete records, and stays below
+//! `MAX_PACKET_SIZE` so it isn't fragmented.
+
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use lqos_config::NetflowProtocol;
+
+pub(crate) const MAX_PACKET_SIZE: usize = 1400;
+pub(crate) const TEMPLATE_ID_V4: u16 = 256;
+pub(crate) const TEMPLATE_ID_V6: u16 = 257;
+pub(crate) const TEMPLATE_ID_SAMPLING: u16 = 258;
+
+// Information element IDs, shared by v9 and IPFIX
+const IE_OCTET_DELTA_COUNT: u16 = 1;
+const IE_PACKET_DELTA_COUNT: u16 = 2;
+const IE_PROTOCOL: u16 = 4;
+const IE_SOURCE_PORT: u16 = 7;
+const IE_SOURCE_IPV4: u16 = 8;
+const IE_DESTINATION_PORT: u16 = 11;
+const IE_DESTINATION_IPV4: u16 = 12;
+const IE_LAST_SWITCHED: u16 = 21;
+const IE_FIRST_SWITCHED: u16 = 22;
+const IE_SOURCE_IPV6: u16 = 27;
+const IE_DESTINATION_IPV6: u16 = 28;
+const IE_FLOW_START_MILLISECONDS: u16 = 152;
+const IE_FLOW_END_MILLISECONDS: u16 = 153;
+
+// Sampling options. v9 uses SAMPLING_INTERVAL/SAMPLING_ALGORITHM, scoped
+// to the exporting system. IPFIX (RFC 5477) describes 1-in-N selection
+// as one sampled in every `interval + space`, scoped to the observation
+// domain.
+const V9_SCOPE_SYSTEM: u16 = 1;
+const IE_SAMPLING_INTERVAL: u16 = 34;
+const IE_SAMPLING_ALGORITHM: u16 = 35;
+const SAMPLING_ALGORITHM_RANDOM: u8 = 2;
+const IE_OBSERVATION_DOMAIN_ID: u16 = 149;
+const IE_SAMPLING_PACKET_INTERVAL: u16 = 305;
+const IE_SAMPLING_PACKET_SPACE: u16 = 306;
+
+/// A single exported flow. Per-host counters are exported as flows with
+/// an unspecified address on the far side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FlowRecord {
+    pub(crate) src: IpAddr,
+    pub(crate) dst: IpAddr,
+    pub(crate) src_port: u16,
+    pub(crate) dst_port: u16,
+    pub(crate) protocol: u8,
+    pub(crate) bytes: u64,
+    pub(crate) packets: u64,
+    /// Milliseconds since the exporter started
+    pub(crate) start_ms: u64,
+    /// Milliseconds since the exporter started
+    pub(crate) end_ms: u64,
+}
+
+impl FlowRecord {
+    fn is_v6(&self) -> bool {
+        self.src.is_ipv6() || self.dst.is_ipv6()
+    }
+
+    /// The fields that identify the flow, for sampling.
+    pub(crate) fn key(&self) -> (IpAddr, IpAddr, u16, u16, u8) {
+        (self.src, self.dst, self.src_port, self.dst_port, self.protocol)
+    }
+}
+
+/// Builds export packets, keeping track of sequence numbers and when
+/// templates were last sent.
+pub(crate) struct Encoder {
+    protocol: NetflowProtocol,
+    observation_domain: u32,
+    /// One in every `sampling_rate` records is exported. Advertised in
+    /// an options record when it is more than 1.
+    sampling_rate: u32,
+    template_refresh: Duration,
+    last_template: Option<Instant>,
+    /// Wall-clock time at which the exporter started, in ms since the epoch
+    boot_epoch_ms: u64,
+    /// v9: packets sent. IPFIX: data records sent.
+    sequence: u32,
+}
+
+impl Encoder {
+    pub(crate) fn new(
+        protocol: NetflowProtocol,
+        observation_domain: u32,
+        sampling_rate: u32,
+        template_refresh: Duration,
+        boot_epoch_ms: u64,
+    ) -> Self {
+        Self {
+            protocol,
+            observation_domain,
+            sampling_rate,
+            template_refresh,
+            last_template: None,
+            boot_epoch_ms,
+            sequence: 0,
+        }
+    }
+
+    /// Encodes `records` into as many packets as required. Templates
+    /// (and the sampling options record) are included in the first
+    /// packet if they are due to be resent.
+    pub(crate) fn encode(&mut self, records: &[FlowRecord], uptime_ms: u64) -> Vec<Vec<u8>> {
+        let mut packets = Vec::new();
+        let mut send_templates = match self.last_template {
+            None => true,
+            Some(last) => last.elapsed() >= self.template_refresh,
+        };
+        if send_templates {
+            self.last_template = Some(Instant::now());
+        }
+
+        let mut remaining = records;
+        while send_templates || !remaining.is_empty() {
+            let (packet, used) = self.encode_packet(remaining, send_templates, uptime_ms);
+            remaining = &remaining[used..];
+            send_templates = false;
+            packets.push(packet);
+        }
+        packets
+    }
+
+    fn header_len(&self) -> usize {
+        match self.protocol {
+            NetflowProtocol::V9 => 20,
+            NetflowProtocol::Ipfix => 16,
+        }
+    }
+
+    fn template_set_id(&self) -> u16 {
+        match self.protocol {
+            NetflowProtocol::V9 => 0,
+            NetflowProtocol::Ipfix => 2,
+        }
+    }
+
+    fn options_template_set_id(&self) -> u16 {
+        match self.protocol {
+            NetflowProtocol::V9 => 1,
+            NetflowProtocol::Ipfix => 3,
+        }
+    }
+
+    fn sampled(&self) -> bool {
+        self.sampling_rate > 1
+    }
+
+    /// The sampling options record's scope field, then its other fields.
+    fn sampling_fields(&self) -> ((u16, u16), Vec<(u16, u16)>) {
+        match self.protocol {
+            NetflowProtocol::V9 => (
+                (V9_SCOPE_SYSTEM, 4),
+                vec![(IE_SAMPLING_INTERVAL, 4), (IE_SAMPLING_ALGORITHM, 1)],
+            ),
+            NetflowProtocol::Ipfix => (
+                (IE_OBSERVATION_DOMAIN_ID, 4),
+                vec![(IE_SAMPLING_PACKET_INTERVAL, 4), (IE_SAMPLING_PACKET_SPACE, 4)],
+            ),
+        }
+    }
+
+    fn fields(&self, v6: bool) -> Vec<(u16, u16)> {
+        let mut fields = if v6 {
+            vec![(IE_SOURCE_IPV6, 16), (IE_DESTINATION_IPV6, 16)]
+        } else {
+            vec![(IE_SOURCE_IPV4, 4), (IE_DESTINATION_IPV4, 4)]
+        };
+        fields.extend_from_slice(&[
+            (IE_SOURCE_PORT, 2),
+            (IE_DESTINATION_PORT, 2),
+            (IE_PROTOCOL, 1),
+            (IE_OCTET_DELTA_COUNT, 8),
+            (IE_PACKET_DELTA_COUNT, 8),
+        ]);
+        match self.protocol {
+            NetflowProtocol::V9 => fields.extend_from_slice(&[(IE_FIRST_SWITCHED, 4), (IE_LAST_SWITCHED, 4)]),
+            NetflowProtocol::Ipfix => fields.extend_from_slice(&[
+                (IE_FLOW_START_MILLISECONDS, 8),
+                (IE_FLOW_END_MILLISECONDS, 8),
+            ]),
+        }
+        fields
+    }
+
+    fn record_len(&self, v6: bool) -> usize {
+        self.fields(v6).iter().map(|(_, len)| *len as usize).sum()
+    }
+
+    fn write_templates(&self, buf: &mut Vec<u8>) -> u16 {
+        let start = buf.len();
+        push_u16(buf, self.template_set_id());
+        push_u16(buf, 0); // Length, filled in below
+        for (id, v6) in [(TEMPLATE_ID_V4, false), (TEMPLATE_ID_V6, true)] {
+            let fields = self.fields(v6);
+            push_u16(buf, id);
+            push_u16(buf, fields.len() as u16);
+            for (field, len) in fields {
+                push_u16(buf, field);
+                push_u16(buf, len);
+            }
+        }
+        let len = (buf.len() - start) as u16;
+        buf[start + 2..start + 4].copy_from_slice(&len.to_be_bytes());
+        2
+    }
+
+    /// Writes the sampling options template, and a record of the sampling
+    /// rate. Returns the number of v9 records written.
+    fn write_sampling_options(&self, buf: &mut Vec<u8>) -> u16 {
+        let (scope, fields) = self.sampling_fields();
+        let start = buf.len();
+        push_u16(buf, self.options_template_set_id());
+        push_u16(buf, 0); // Length, filled in below
+        push_u16(buf, TEMPLATE_ID_SAMPLING);
+        match self.protocol {
+            NetflowProtocol::V9 => {
+                // Scope and option lengths, in bytes
+                push_u16(buf, 4);
+                push_u16(buf, fields.len() as u16 * 4);
+            }
+            NetflowProtocol::Ipfix => {
+                // Field count, then scope field count
+                push_u16(buf, fields.len() as u16 + 1);
+                push_u16(buf, 1);
+            }
+        }
+        for (field, len) in std::iter::once(scope).chain(fields) {
+            push_u16(buf, field);
+            push_u16(buf, len);
+        }
+        pad_set(buf, start);
+
+        let start = buf.len();
+        push_u16(buf, TEMPLATE_ID_SAMPLING);
+        push_u16(buf, 0); // Length, filled in below
+        buf.extend_from_slice(&self.observation_domain.to_be_bytes());
+        match self.protocol {
+            NetflowProtocol::V9 => {
+                buf.extend_from_slice(&self.sampling_rate.to_be_bytes());
+                buf.push(SAMPLING_ALGORITHM_RANDOM);
+            }
+            NetflowProtocol::Ipfix => {
+                buf.extend_from_slice(&1u32.to_be_bytes());
+                buf.extend_from_slice(&(self.sampling_rate - 1).to_be_bytes());
+            }
+        }
+        pad_set(buf, start);
+        2
+    }
+
+    fn write_record(&self, buf: &mut Vec<u8>, record: &FlowRecord) {
+        let v6 = record.is_v6();
+        write_address(buf, record.src, v6);
+        write_address(buf, record.dst, v6);
+        push_u16(buf, record.src_port);
+        push_u16(buf, record.dst_port);
+        buf.push(record.protocol);
+        buf.extend_from_slice(&record.bytes.to_be_bytes());
+        buf.extend_from_slice(&record.packets.to_be_bytes());
+        match self.protocol {
+            // Relative to sysUptime, which wraps along with them
+            NetflowProtocol::V9 => {
+                buf.extend_from_slice(&(record.start_ms as u32).to_be_bytes());
+                buf.extend_from_slice(&(record.end_ms as u32).to_be_bytes());
+            }
+            NetflowProtocol::Ipfix => {
+                buf.extend_from_slice(&(self.boot_epoch_ms + record.start_ms).to_be_bytes());
+                buf.extend_from_slice(&(self.boot_epoch_ms + record.end_ms).to_be_bytes());
+            }
+        }
+    }
+
+    /// Writes a data set for every leading record in `records` that shares
+    /// the address family of the first one, stopping when the packet is full.
+    /// Returns the number of records written.
+    fn write_data_set(&self, buf: &mut Vec<u8>, records: &[FlowRecord]) -> usize {
+        let v6 = records[0].is_v6();
+        let record_len = self.record_len(v6);
+        if buf.len() + 4 + record_len > MAX_PACKET_SIZE {
+            return 0;
+        }
+        let start = buf.len();
+        push_u16(buf, if v6 { TEMPLATE_ID_V6 } else { TEMPLATE_ID_V4 });
+        push_u16(buf, 0); // Length, filled in below
+        let mut count = 0;
+        for record in records.iter().take_while(|r| r.is_v6() == v6) {
+            if buf.len() + record_len > MAX_PACKET_SIZE {
+                break;
+            }
+            self.write_record(buf, record);
+            count += 1;
+        }
+        pad_set(buf, start);
+        count
+    }
+
+    fn encode_packet(&mut self, records: &[FlowRecord], templates: bool, uptime_ms: u64) -> (Vec<u8>, usize) {
+        let mut buf = vec![0; self.header_len()];
+        let mut v9_count = 0;
+        if templates {
+            v9_count += self.write_templates(&mut buf);
+            if self.sampled() {
+                v9_count += self.write_sampling_options(&mut buf);
+            }
+        }
+        let mut used = 0;
+        while used < records.len() {
+            let written = self.write_data_set(&mut buf, &records[used..]);
+            if written == 0 {
+                break;
+            }
+            used += written;
+        }
+        v9_count += used as u16;
+
+        let export_secs = ((self.boot_epoch_ms + uptime_ms) / 1000) as u32;
+        let mut header = Vec::with_capacity(self.header_len());
+        match self.protocol {
+            NetflowProtocol::V9 => {
+                push_u16(&mut header, 9);
+                push_u16(&mut header, v9_count);
+                // sysUptime is 32 bits, and wraps after 49.7 days
+                header.extend_from_slice(&(uptime_ms as u32).to_be_bytes());
+                header.extend_from_slice(&export_secs.to_be_bytes());
+                header.extend_from_slice(&self.sequence.to_be_bytes());
+                header.extend_from_slice(&self.observation_domain.to_be_bytes());
+                self.sequence = self.sequence.wrapping_add(1);
+            }
+            NetflowProtocol::Ipfix => {
+                push_u16(&mut header, 10);
+                push_u16(&mut header, buf.len() as u16);
+                header.extend_from_slice(&export_secs.to_be_bytes());
+                header.extend_from_slice(&self.sequence.to_be_bytes());
+                header.extend_from_slice(&self.observation_domain.to_be_bytes());
+                self.sequence = self.sequence.wrapping_add(used as u32);
+            }
+        }
+        buf[..header.len()].copy_from_slice(&header);
+        (buf, used)
+    }
+}
+
+/// Pads the set starting at `start` to a 4-byte boundary, and fills in
+/// its length.
+fn pad_set(buf: &mut Vec<u8>, start: usize) {
+    let padding = (4 - (buf.len() - start) % 4) % 4;
+    buf.resize(buf.len() + padding, 0);
+    let len = (buf.len() - start) as u16;
+    buf[start + 2..start + 4].copy_from_slice(&len.to_be_bytes());
+}
+
+fn push_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+fn write_address(buf: &mut Vec<u8>, ip: IpAddr, v6: bool) {
+    match (ip, v6) {
+        (IpAddr::V4(ip), false) => buf.extend_from_slice(&ip.octets()),
+        (IpAddr::V4(ip), true) => buf.extend_from_slice(&ip.to_ipv6_mapped().octets()),
+        (IpAddr::V6(ip), _) => buf.extend_from_slice(&ip.octets()),
+    }
+}
@@ -0,0 +1,528 @@
+//! Exports NetFlow v9 or IPFIX records to the collectors listed in the
+//! `[netflow]` section of `/etc/lqos`. Per-flow counters are exported
+//! if flow tracking is enabled, per-host counters otherwise.
+
+mod encoder;
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::BuildHasher,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use anyhow::{Error, Result};
+use encoder::{Encoder, FlowRecord};
+use log::{info, warn};
+use lqos_config::{EtcLqos, NetflowConfig};
+use lqos_sys::FlowKey;
+use tokio::{net::lookup_host, task, time};
+use crate::{flow_tracker, throughput_tracker::{self, HostTotals}};
+
+/// Sends encoded records to every configured collector.
+pub(crate) struct Exporter {
+    encoder: Encoder,
+    socket: UdpSocket,
+    collectors: Vec<SocketAddr>,
+    sampling_rate: u32,
+    /// Randomly keyed, so which flows are sampled can't be predicted.
+    sampler: RandomState,
+    /// Mixed into the sampling hash, so each export samples different
+    /// flows.
+    exports: u64,
+}
+
+impl Exporter {
+    pub(crate) async fn new(config: &NetflowConfig, boot_epoch_ms: u64) -> Result<Self> {
+        let mut collectors = Vec::new();
+        for collector in config.collectors.iter() {
+            match lookup_host(collector.as_str()).await {
+                Ok(addrs) => collectors.extend(addrs),
+                Err(e) => warn!("Unable to resolve NetFlow collector {collector}: {e:?}"),
+            }
+        }
+        if collectors.is_empty() {
+            return Err(Error::msg("No usable NetFlow collectors"));
+        }
+        let bind = if collectors.iter().all(|c| c.is_ipv4()) {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let sampling_rate = u32::max(config.sampling_rate, 1);
+        Ok(Self {
+            encoder: Encoder::new(
+                config.protocol,
+                config.observation_domain,
+                sampling_rate,
+                Duration::from_secs(config.template_refresh_secs),
+                boot_epoch_ms,
+            ),
+            socket: UdpSocket::bind(bind)?,
+            collectors,
+            sampling_rate,
+            sampler: RandomState::new(),
+            exports: 0,
+        })
+    }
+
+    /// True if `record` is one of the one-in-`sampling_rate` records
+    /// exported this time. Each flow has the same chance every export,
+    /// whatever order the records come in.
+    fn sample(&self, record: &FlowRecord) -> bool {
+        if self.sampling_rate == 1 {
+            return true;
+        }
+        self.sampler.hash_one((record.key(), self.exports)) % self.sampling_rate as u64 == 0
+    }
+
+    /// Samples, encodes and sends `records`. Returns the number of
+    /// records that were sampled. A collector that can't be reached is
+    /// logged, and doesn't stop the others from being sent to.
+    pub(crate) fn export(&mut self, records: &[FlowRecord], uptime_ms: u64) -> usize {
+        let sampled: Vec<FlowRecord> = records
+            .iter()
+            .filter(|r| self.sample(r))
+            .cloned()
+            .collect();
+        self.exports = self.exports.wrapping_add(1);
+        let packets = self.encoder.encode(&sampled, uptime_ms);
+        for collector in self.collectors.iter() {
+            let failures: Vec<std::io::Error> = packets
+                .iter()
+                .filter_map(|packet| self.socket.send_to(packet, collector).err())
+                .collect();
+            if let Some(e) = failures.first() {
+                warn!("Unable to send {} of {} NetFlow packets to {collector}: {e:?}", failures.len(), packets.len());
+            }
+        }
+        sampled.len()
+    }
+}
+
+/// Remembers the counters from the previous export, so that each
+/// export contains only the traffic since the last one.
+#[derive(Default)]
+struct DeltaTracker {
+    hosts: HashMap<IpAddr, HostTotals>,
+    flows: HashMap<FlowKey, (u64, u64)>,
+    last_export_ms: u64,
+}
+
+impl DeltaTracker {
+    fn host_records(&mut self, uptime_ms: u64) -> Vec<FlowRecord> {
+        let mut records = Vec::new();
+        let mut next = HashMap::new();
+        for host in throughput_tracker::host_totals() {
+            let ip = host.ip;
+            if let Some(prev) = self.hosts.get(&ip) {
+                let unspecified = match ip {
+                    IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                    IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                };
+                let download = (host.bytes.0.saturating_sub(prev.bytes.0), host.packets.0.saturating_sub(prev.packets.0));
+                let upload = (host.bytes.1.saturating_sub(prev.bytes.1), host.packets.1.saturating_sub(prev.packets.1));
+                for (src, dst, (bytes, packets)) in [(unspecified, ip, download), (ip, unspecified, upload)] {
+                    if packets > 0 {
+                        records.push(FlowRecord {
+                            src,
+                            dst,
+                            src_port: 0,
+                            dst_port: 0,
+                            protocol: 0,
+                            bytes,
+                            packets,
+                            start_ms: self.last_export_ms,
+                            end_ms: uptime_ms,
+                        });
+                    }
+                }
+            }
+            next.insert(ip, host);
+        }
+        self.hosts = next;
+        records
+    }
+
+    fn flow_records(&mut self, flows: Vec<(FlowKey, u64, u64, u64)>, uptime_ms: u64) -> Vec<FlowRecord> {
+        let kernel_now_ms = lqos_sys::kernel_now()
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let mut records = Vec::new();
+        let mut next = HashMap::new();
+        for (key, bytes, packets, last_seen_ns) in flows {
+            if let Some((prev_bytes, prev_packets)) = self.flows.get(&key) {
+                let packet_delta = packets.saturating_sub(*prev_packets);
+                if packet_delta > 0 {
+                    let age_ms = kernel_now_ms.saturating_sub(last_seen_ns / 1_000_000);
+                    records.push(FlowRecord {
+                        src: key.src.as_ip(),
+                        dst: key.dst.as_ip(),
+                        src_port: key.src_port,
+                        dst_port: key.dst_port,
+                        protocol: key.protocol,
+                        bytes: bytes.saturating_sub(*prev_bytes),
+                        packets: packet_delta,
+                        start_ms: self.last_export_ms,
+                        end_ms: u64::max(uptime_ms.saturating_sub(age_ms), self.last_export_ms),
+                    });
+                }
+            }
+            next.insert(key, (bytes, packets));
+        }
+        self.flows = next;
+        records
+    }
+
+    /// Builds the records to export. Counters seen for the first time
+    /// only set a baseline, so lifetime totals are never exported as a
+    /// single burst.
+    fn records(&mut self, uptime_ms: u64) -> Vec<FlowRecord> {
+        let flows = flow_tracker::flow_totals();
+        let records = if flows.is_empty() {
+            self.flows.clear();
+            self.host_records(uptime_ms)
+        } else {
+            self.hosts.clear();
+            self.flow_records(flows, uptime_ms)
+        };
+        self.last_export_ms = uptime_ms;
+        records
+    }
+}
+
+/// Starts the NetFlow exporter, if `/etc/lqos` has a `[netflow]` section.
+pub async fn spawn_netflow_exporter() {
+    let config = match EtcLqos::load() {
+        Ok(etc) => match etc.netflow {
+            Some(netflow) => netflow,
+            None => return,
+        },
+        Err(_) => return,
+    };
+    let boot_epoch_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    let mut exporter = match Exporter::new(&config, boot_epoch_ms).await {
+        Ok(exporter) => exporter,
+        Err(e) => {
+            warn!("NetFlow export disabled: {:?}", e);
+            return;
+        }
+    };
+    info!("Exporting {:?} flows to {:?}", config.protocol, config.collectors);
+
+    task::spawn(async move {
+        let started = Instant::now();
+        let mut deltas = DeltaTracker::default();
+        let mut interval = time::interval(Duration::from_secs(u64::max(config.export_interval_secs, 1)));
+        loop {
+            interval.tick().await;
+            let uptime_ms = started.elapsed().as_millis() as u64;
+            let records = deltas.records(uptime_ms);
+            exporter.export(&records, uptime_ms);
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::encoder::{MAX_PACKET_SIZE, TEMPLATE_ID_SAMPLING, TEMPLATE_ID_V4, TEMPLATE_ID_V6};
+    use lqos_config::NetflowProtocol;
+
+    struct DecodedPacket {
+        version: u16,
+        sequence: u32,
+        templates: HashMap<u16, Vec<(u16, u16)>>,
+        records: Vec<(u16, HashMap<u16, Vec<u8>>)>,
+    }
+
+    fn read_u16(buf: &[u8], pos: usize) -> u16 {
+        u16::from_be_bytes([buf[pos], buf[pos + 1]])
+    }
+
+    fn read_u32(buf: &[u8], pos: usize) -> u32 {
+        u32::from_be_bytes(buf[pos..pos + 4].try_into().unwrap())
+    }
+
+    fn read_u64(buf: &[u8]) -> u64 {
+        u64::from_be_bytes(buf.try_into().unwrap())
+    }
+
+    /// A minimal collector-side decoder. `known` holds templates from
+    /// earlier packets.
+    fn decode(buf: &[u8], known: &mut HashMap<u16, Vec<(u16, u16)>>) -> DecodedPacket {
+        let version = read_u16(buf, 0);
+        let (header_len, template_set, options_set) = match version {
+            9 => (20, 0, 1),
+            10 => {
+                assert_eq!(read_u16(buf, 2) as usize, buf.len());
+                (16, 2, 3)
+            }
+            _ => panic!("Unknown version {version}"),
+        };
+        let sequence = read_u32(buf, header_len - 8);
+        let mut result = DecodedPacket { version, sequence, templates: HashMap::new(), records: Vec::new() };
+        let mut pos = header_len;
+        while pos < buf.len() {
+            let set_id = read_u16(buf, pos);
+            let set_len = read_u16(buf, pos + 2) as usize;
+            let end = pos + set_len;
+            let mut p = pos + 4;
+            if set_id == template_set {
+                while p + 4 <= end {
+                    let id = read_u16(buf, p);
+                    let count = read_u16(buf, p + 2) as usize;
+                    p += 4;
+                    let fields = (0..count).map(|i| (read_u16(buf, p + i * 4), read_u16(buf, p + i * 4 + 2))).collect();
+                    p += count * 4;
+                    result.templates.insert(id, fields);
+                }
+                known.extend(result.templates.clone());
+            } else if set_id == options_set {
+                // One options template per set, then padding. Scope
+                // fields are listed first, like any other field.
+                let id = read_u16(buf, p);
+                let count = match version {
+                    9 => (read_u16(buf, p + 2) + read_u16(buf, p + 4)) as usize / 4,
+                    _ => read_u16(buf, p + 2) as usize,
+                };
+                p += 6;
+                let fields: Vec<(u16, u16)> =
+                    (0..count).map(|i| (read_u16(buf, p + i * 4), read_u16(buf, p + i * 4 + 2))).collect();
+                result.templates.insert(id, fields.clone());
+                known.insert(id, fields);
+            } else {
+                let fields = known.get(&set_id).expect("Data set without a template").clone();
+                let record_len: usize = fields.iter().map(|(_, len)| *len as usize).sum();
+                while p + record_len <= end {
+                    let mut record = HashMap::new();
+                    for (field, len) in fields.iter() {
+                        record.insert(*field, buf[p..p + *len as usize].to_vec());
+                        p += *len as usize;
+                    }
+                    result.records.push((set_id, record));
+                }
+            }
+            pos = end;
+        }
+        result
+    }
+
+    fn listener() -> (UdpSocket, String) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        (socket, addr)
+    }
+
+    fn config(protocol: NetflowProtocol, collector: String, sampling_rate: u32) -> NetflowConfig {
+        NetflowConfig {
+            collectors: vec![collector],
+            protocol,
+            sampling_rate,
+            export_interval_secs: 10,
+            template_refresh_secs: 60,
+            observation_domain: 42,
+        }
+    }
+
+    fn record(src: &str, dst: &str, bytes: u64) -> FlowRecord {
+        FlowRecord {
+            src: src.parse().unwrap(),
+            dst: dst.parse().unwrap(),
+            src_port: 443,
+            dst_port: 50000,
+            protocol: 6,
+            bytes,
+            packets: 3,
+            start_ms: 1000,
+            end_ms: 2000,
+        }
+    }
+
+    fn receive(socket: &UdpSocket) -> Vec<u8> {
+        let mut buf = [0u8; 2048];
+        let (len, _) = socket.recv_from(&mut buf).unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[tokio::test]
+    async fn ipfix_round_trip() {
+        let (socket, addr) = listener();
+        let mut exporter = Exporter::new(&config(NetflowProtocol::Ipfix, addr, 1), 1_000_000).await.unwrap();
+        let records = vec![record("1.2.3.4", "100.64.1.2", 1500), record("2001:db8::1", "2001:db8::2", 900)];
+        assert_eq!(exporter.export(&records, 2500), 2);
+
+        let mut known = HashMap::new();
+        let packet = decode(&receive(&socket), &mut known);
+        assert_eq!(packet.version, 10);
+        assert_eq!(packet.sequence, 0);
+        assert!(packet.templates.contains_key(&TEMPLATE_ID_V4));
+        assert!(packet.templates.contains_key(&TEMPLATE_ID_V6));
+        // Nothing is sampled, so there are no sampling options
+        assert!(!packet.templates.contains_key(&TEMPLATE_ID_SAMPLING));
+        assert_eq!(packet.records.len(), 2);
+
+        let (template, v4) = &packet.records[0];
+        assert_eq!(*template, TEMPLATE_ID_V4);
+        assert_eq!(v4[&8], vec![1, 2, 3, 4]);
+        assert_eq!(v4[&12], vec![100, 64, 1, 2]);
+        assert_eq!(read_u16(&v4[&7], 0), 443);
+        assert_eq!(read_u16(&v4[&11], 0), 50000);
+        assert_eq!(v4[&4], vec![6]);
+        assert_eq!(read_u64(&v4[&1]), 1500);
+        assert_eq!(read_u64(&v4[&2]), 3);
+        assert_eq!(read_u64(&v4[&152]), 1_001_000);
+        assert_eq!(read_u64(&v4[&153]), 1_002_000);
+
+        let (template, v6) = &packet.records[1];
+        assert_eq!(*template, TEMPLATE_ID_V6);
+        let src: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        assert_eq!(v6[&27], src.octets().to_vec());
+        assert_eq!(read_u64(&v6[&1]), 900);
+
+        // The next export carries on the sequence, without templates
+        exporter.export(&records[..1], 3000);
+        let packet = decode(&receive(&socket), &mut known);
+        assert_eq!(packet.sequence, 2);
+        assert!(packet.templates.is_empty());
+        assert_eq!(packet.records.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn v9_round_trip() {
+        let (socket, addr) = listener();
+        let mut exporter = Exporter::new(&config(NetflowProtocol::V9, addr, 1), 0).await.unwrap();
+        exporter.export(&[record("10.0.0.1", "10.0.0.2", 64)], 5000);
+
+        let raw = receive(&socket);
+        // 2 templates + 1 data record
+        assert_eq!(read_u16(&raw, 2), 3);
+        assert_eq!(read_u32(&raw, 4), 5000);
+        assert_eq!(read_u32(&raw, 16), 42);
+        let packet = decode(&raw, &mut HashMap::new());
+        assert_eq!(packet.version, 9);
+        assert_eq!(packet.records.len(), 1);
+        let (_, v4) = &packet.records[0];
+        assert_eq!(v4[&8], vec![10, 0, 0, 1]);
+        assert_eq!(read_u32(&v4[&22], 0), 1000);
+        assert_eq!(read_u32(&v4[&21], 0), 2000);
+    }
+
+    #[tokio::test]
+    async fn large_exports_are_split() {
+        let (socket, addr) = listener();
+        let mut exporter = Exporter::new(&config(NetflowProtocol::Ipfix, addr, 1), 0).await.unwrap();
+        let records: Vec<FlowRecord> = (0..200).map(|i| record("1.2.3.4", "5.6.7.8", i)).collect();
+        exporter.export(&records, 1);
+
+        let mut known = HashMap::new();
+        let mut received = 0;
+        while received < records.len() {
+            let raw = receive(&socket);
+            assert!(raw.len() <= MAX_PACKET_SIZE);
+            let packet = decode(&raw, &mut known);
+            assert_eq!(packet.sequence as usize, received);
+            for (_, r) in packet.records.iter() {
+                assert_eq!(read_u64(&r[&1]), received as u64);
+                received += 1;
+            }
+        }
+        assert_eq!(received, records.len());
+    }
+
+    /// `count` distinct flows; each one's byte count is its index.
+    fn distinct_flows(count: u64) -> Vec<FlowRecord> {
+        (0..count)
+            .map(|i| FlowRecord { src_port: i as u16, ..record("1.2.3.4", "5.6.7.8", i) })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn sampling_exports_about_one_in_n() {
+        let (socket, addr) = listener();
+        let mut exporter = Exporter::new(&config(NetflowProtocol::Ipfix, addr, 4), 0).await.unwrap();
+        let records = distinct_flows(2000);
+        let mut picked = Vec::new();
+        let mut known = HashMap::new();
+        for export in 0..2 {
+            let sampled = exporter.export(&records, 1);
+            assert!((400..600).contains(&sampled), "{sampled} of 2000 sampled");
+            let mut bytes = Vec::new();
+            while bytes.len() < sampled {
+                let packet = decode(&receive(&socket), &mut known);
+                bytes.extend(
+                    packet.records.iter().filter(|(id, _)| *id != TEMPLATE_ID_SAMPLING).map(|(_, r)| read_u64(&r[&1])),
+                );
+            }
+            assert_eq!(bytes.len(), sampled, "export {export}");
+            picked.push(bytes);
+        }
+        // The same records, in the same order, aren't sampled the same way twice
+        assert_ne!(picked[0], picked[1]);
+    }
+
+    #[tokio::test]
+    async fn sampling_rate_is_advertised() {
+        let (socket, addr) = listener();
+        let mut exporter = Exporter::new(&config(NetflowProtocol::Ipfix, addr, 10), 0).await.unwrap();
+        exporter.export(&[], 1);
+        let packet = decode(&receive(&socket), &mut HashMap::new());
+        let (_, options) = packet.records.iter().find(|(id, _)| *id == TEMPLATE_ID_SAMPLING).unwrap();
+        assert_eq!(read_u32(&options[&149], 0), 42);
+        assert_eq!(read_u32(&options[&305], 0), 1);
+        assert_eq!(read_u32(&options[&306], 0), 9);
+
+        let (socket, addr) = listener();
+        let mut exporter = Exporter::new(&config(NetflowProtocol::V9, addr, 10), 0).await.unwrap();
+        exporter.export(&[], 1);
+        let raw = receive(&socket);
+        // 2 templates, the options template and the options record
+        assert_eq!(read_u16(&raw, 2), 4);
+        let packet = decode(&raw, &mut HashMap::new());
+        let (_, options) = packet.records.iter().find(|(id, _)| *id == TEMPLATE_ID_SAMPLING).unwrap();
+        assert_eq!(read_u32(&options[&34], 0), 10);
+        assert_eq!(options[&35], vec![2]);
+    }
+
+    #[tokio::test]
+    async fn timestamps_survive_49_days() {
+        let uptime_ms = u32::MAX as u64 + 5000;
+        let late = FlowRecord { start_ms: uptime_ms - 1000, end_ms: uptime_ms, ..record("10.0.0.1", "10.0.0.2", 64) };
+
+        let (socket, addr) = listener();
+        let mut exporter = Exporter::new(&config(NetflowProtocol::Ipfix, addr, 1), 1_000_000).await.unwrap();
+        exporter.export(std::slice::from_ref(&late), uptime_ms);
+        let raw = receive(&socket);
+        assert_eq!(read_u32(&raw, 4) as u64, (1_000_000 + uptime_ms) / 1000);
+        let packet = decode(&raw, &mut HashMap::new());
+        let (_, r) = &packet.records[0];
+        assert_eq!(read_u64(&r[&152]), 1_000_000 + uptime_ms - 1000);
+        assert_eq!(read_u64(&r[&153]), 1_000_000 + uptime_ms);
+
+        // v9 times are 32-bit, and wrap along with sysUptime
+        let (socket, addr) = listener();
+        let mut exporter = Exporter::new(&config(NetflowProtocol::V9, addr, 1), 0).await.unwrap();
+        exporter.export(&[late], uptime_ms);
+        let raw = receive(&socket);
+        assert_eq!(read_u32(&raw, 4), 4999);
+        let packet = decode(&raw, &mut HashMap::new());
+        let (_, r) = &packet.records[0];
+        assert_eq!(read_u32(&r[&22], 0), 3999);
+        assert_eq!(read_u32(&r[&21], 0), 4999);
+    }
+
+    #[tokio::test]
+    async fn unreachable_collectors_dont_stop_the_rest() {
+        let (socket, addr) = listener();
+        let mut cfg = config(NetflowProtocol::Ipfix, addr, 1);
+        // A broadcast address can't be sent to without SO_BROADCAST
+        cfg.collectors.insert(0, "255.255.255.255:2055".to_string());
+        let mut exporter = Exporter::new(&cfg, 0).await.unwrap();
+        assert_eq!(exporter.collectors.len(), 2);
+        assert_eq!(exporter.export(&[record("10.0.0.1", "10.0.0.2", 64)], 1), 1);
+        let packet = decode(&receive(&socket), &mut HashMap::new());
+        assert_eq!(packet.records.len(), 1);
+    }
+}
@@ -0,0 +1,159 @@
+//! Rolls per-circuit throughput, RTT and drops up the `network.json`
+//! hierarchy, so that congested sites and AP backhauls stand out.
+
+use std::collections::HashMap;
+use lqos_bus::{BusResponse, NetworkNodeStats};
+use lqos_config::NetworkJson;
+use crate::{
+    libreqos_tracker::{NETWORK_JSON, QUEUE_STRUCTURE},
+    queue_tracker, throughput_tracker,
+};
+
+/// Current measurements for one circuit. Tuples are (download, upload).
+struct CircuitSample {
+    parent_node: String,
+    bits_per_second: (u64, u64),
+    rtts: Vec<f32>,
+    drops: (u64, u64),
+}
+
+fn percent(bits_per_second: u64, mbps: u64) -> f32 {
+    if mbps == 0 {
+        0.0
+    } else {
+        (bits_per_second as f64 / (mbps as f64 * 10_000.0)) as f32
+    }
+}
+
+pub(crate) fn median(values: &mut [f32]) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    values[values.len() / 2]
+}
+
+/// Adds each circuit to its parent node and every node above it.
+/// Circuits whose parent isn't in `network.json` are left out.
+fn aggregate(network: &NetworkJson, circuits: &[CircuitSample]) -> Vec<NetworkNodeStats> {
+    let mut result: Vec<NetworkNodeStats> = network
+        .nodes
+        .iter()
+        .map(|node| NetworkNodeStats {
+            name: node.name.clone(),
+            immediate_parent: node.immediate_parent,
+            bandwidth_mbps: (node.download_bandwidth_mbps, node.upload_bandwidth_mbps),
+            circuits: 0,
+            bits_per_second: (0, 0),
+            utilization_percent: (0.0, 0.0),
+            median_rtt_ms: 0.0,
+            drops: (0, 0),
+        })
+        .collect();
+    let mut rtts: Vec<Vec<f32>> = vec![Vec::new(); result.len()];
+
+    // Look nodes up by name once, rather than scanning for each circuit.
+    // As with `NetworkJson::find`, the first node with a name wins.
+    let mut by_name: HashMap<&str, usize> = HashMap::with_capacity(network.nodes.len());
+    for (index, node) in network.nodes.iter().enumerate() {
+        by_name.entry(node.name.as_str()).or_insert(index);
+    }
+
+    for circuit in circuits.iter() {
+        let Some(&index) = by_name.get(circuit.parent_node.as_str()) else { continue };
+        for parent in network.nodes[index].parents.iter() {
+            let node = &mut result[*parent];
+            node.circuits += 1;
+            node.bits_per_second.0 += circuit.bits_per_second.0;
+            node.bits_per_second.1 += circuit.bits_per_second.1;
+            node.drops.0 += circuit.drops.0;
+            node.drops.1 += circuit.drops.1;
+            rtts[*parent].extend_from_slice(&circuit.rtts);
+        }
+    }
+
+    for (node, rtts) in result.iter_mut().zip(rtts.iter_mut()) {
+        node.utilization_percent = (
+            percent(node.bits_per_second.0, node.bandwidth_mbps.0),
+            percent(node.bits_per_second.1, node.bandwidth_mbps.1),
+        );
+        node.median_rtt_ms = median(rtts);
+    }
+    result
+}
+
+/// Gathers the current measurements for every circuit in
+/// `queuingStructure.json`.
+fn circuit_samples() -> Vec<CircuitSample> {
+    let bits = throughput_tracker::bits_per_tc_handle();
+    let rtts = throughput_tracker::rtt_per_tc_handle();
+    let drops = queue_tracker::circuit_drops();
+    let mut result = Vec::new();
+    if let Ok(structure) = &*QUEUE_STRUCTURE.read() {
+        for circuit in structure.iter() {
+            let (Some(circuit_id), Some(parent_node)) = (&circuit.circuit_id, &circuit.parent_node) else { continue };
+            let down = bits.get(&circuit.class_id.as_u32()).map(|b| b.0).unwrap_or(0);
+            let up = bits.get(&circuit.up_class_id.as_u32()).map(|b| b.1).unwrap_or(0);
+            result.push(CircuitSample {
+                parent_node: parent_node.clone(),
+                bits_per_second: (down, up),
+                rtts: rtts.get(&circuit.class_id.as_u32()).cloned().unwrap_or_default(),
+                drops: drops.get(circuit_id).copied().unwrap_or((0, 0)),
+            });
+        }
+    }
+    result
+}
+
+pub(crate) fn network_tree() -> BusResponse {
+    let samples = circuit_samples();
+    BusResponse::NetworkTree(aggregate(&NETWORK_JSON.read(), &samples))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample(parent_node: &str, down_mbps: u64, rtt: f32) -> CircuitSample {
+        CircuitSample {
+            parent_node: parent_node.to_string(),
+            bits_per_second: (down_mbps * 1_000_000, 0),
+            rtts: vec![rtt],
+            drops: (1, 2),
+        }
+    }
+
+    #[test]
+    fn circuits_roll_up_to_every_parent() {
+        let network = NetworkJson::from_json(r#"{
+            "Site": {
+                "downloadBandwidthMbps": 1000,
+                "uploadBandwidthMbps": 1000,
+                "children": {
+                    "AP_A": { "downloadBandwidthMbps": 100, "uploadBandwidthMbps": 100 },
+                    "AP_B": { "downloadBandwidthMbps": 100, "uploadBandwidthMbps": 100 }
+                }
+            }
+        }"#).unwrap();
+        let circuits = vec![
+            sample("AP_A", 50, 10.0),
+            sample("AP_A", 40, 30.0),
+            sample("AP_B", 10, 20.0),
+            sample("Unknown", 500, 1.0),
+        ];
+        let result = aggregate(&network, &circuits);
+
+        let ap_a = &result[network.find("AP_A").unwrap()];
+        assert_eq!(ap_a.circuits, 2);
+        assert_eq!(ap_a.bits_per_second.0, 90_000_000);
+        assert!((ap_a.utilization_percent.0 - 90.0).abs() < 0.01);
+        assert_eq!(ap_a.drops, (2, 4));
+
+        let site = &result[network.find("Site").unwrap()];
+        assert_eq!(site.circuits, 3);
+        assert!((site.utilization_percent.0 - 10.0).abs() < 0.01);
+        assert_eq!(site.median_rtt_ms, 20.0);
+        assert_eq!(site.immediate_parent, None);
+        assert_eq!(ap_a.immediate_parent, Some(network.find("Site").unwrap()));
+    }
+}
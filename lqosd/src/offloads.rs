@@ -1,57 +1,319 @@
+//! Applies the `[tuning]` section of `/etc/lqos`. Each setting's
+//! previous value is read before it is changed, so that everything can
+//! be put back on shutdown (or with the `RestoreTuning` bus request).
+//! Offloads are tracked per kernel feature, so a short name such as
+//! `tso` is restored to exactly the parts that were on before.
+
+use anyhow::{Error, Result};
+use lazy_static::*;
+use log::{info, warn};
+use lqos_bus::{BusResponse, TuningResult};
 use lqos_config::Tunables;
-use tokio::process::Command;
+use lqos_sys::{Coalesce, Ethtool, Rings};
+use parking_lot::Mutex;
+use tokio::{process::Command, task::spawn_blocking};
 
-pub async fn bpf_sysctls() {
-    let _ = Command::new("/sbin/sysctl")
-        .arg(format!("net.core.bpf_jit_enable=1"))
-        .output().await;
+/// A system setting `lqosd` may change.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Setting {
+    Sysctl(&'static str),
+    Offload { interface: String, feature: String },
+    Coalesce { interface: String, parameter: &'static str },
+    Ring { interface: String, parameter: &'static str },
+    IrqBalance,
 }
 
-pub async fn stop_irq_balance() {
-    let _ = Command::new("/bin/systemctl")
-        .args(["stop", "irqbalance"])
-        .output().await;
+/// A setting that has been changed, and the value to put back.
+struct Applied {
+    setting: Setting,
+    previous: Option<String>,
+    value: String,
+    /// True if the new value was written, even if the driver then
+    /// reported something else (e.g. rounded coalescing times). Written
+    /// settings are restored.
+    written: bool,
+    error: Option<String>,
 }
 
-pub async fn netdev_budget(usecs: u32, packets: u32) {
-    let _ = Command::new("/sbin/sysctl")
-        .arg(format!("net.core.netdev_budget_usecs={usecs}"))
-        .output().await;
+impl Applied {
+    fn result(&self) -> TuningResult {
+        TuningResult {
+            setting: self.setting.describe(),
+            previous: self.previous.clone(),
+            value: self.value.clone(),
+            error: self.error.clone(),
+        }
+    }
+}
+
+lazy_static! {
+    static ref APPLIED: Mutex<Vec<Applied>> = Mutex::new(Vec::new());
+}
 
-    let _ = Command::new("/sbin/sysctl")
-        .arg(format!("net.core.netdev_budget={packets}"))
-        .output().await;
+async fn run(command: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(command).args(args).output().await?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(Error::msg(format!(
+            "{command} {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
 }
 
-async fn disable_individual_offload(interface: &str, feature: &str) {
-    let _ = Command::new("/sbin/ethtool")
-        .args(["--offload", interface, feature, "off"])
-        .output().await;
+/// Runs ethtool netlink calls, which block, off the async runtime.
+async fn ethtool<T, F>(f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut Ethtool) -> Result<T> + Send + 'static,
+{
+    spawn_blocking(move || f(&mut Ethtool::new()?)).await?
 }
 
-pub async fn ethtool_tweaks(interface: &str, tuning: &Tunables) {
-    // Disabling individually to avoid complaints that a card doesn't support a feature anyway
-    for feature in tuning.disable_offload.iter() {
-        disable_individual_offload(interface, feature).await;
+/// The kernel features to turn off for `feature`. If none of them can
+/// be changed, the short name is kept so the failure is reported.
+async fn offload_parts(interface: &str, feature: &str) -> Vec<String> {
+    let interface = interface.to_string();
+    match ethtool(move |e| e.features(&interface)).await {
+        Ok(features) => {
+            let parts = features.changeable_parts(feature);
+            if parts.is_empty() { vec![feature.to_string()] } else { parts }
+        }
+        Err(_) => vec![feature.to_string()],
     }
-    
-    let _ = Command::new("/sbin/ethtool")
-        .args(["-C", interface, "rx-usecs", &format!("\"{}\"", tuning.rx_usecs)])
-        .output().await;
+}
 
-    let _ = Command::new("/sbin/ethtool")
-        .args(["-C", interface, "tx-usecs", &format!("\"{}\"", tuning.tx_usecs)])
-        .output().await;
+impl Setting {
+    fn describe(&self) -> String {
+        match self {
+            Setting::Sysctl(name) => format!("sysctl {name}"),
+            Setting::Offload { interface, feature } => format!("{interface} offload {feature}"),
+            Setting::Coalesce { interface, parameter } => format!("{interface} coalesce {parameter}"),
+            Setting::Ring { interface, parameter } => format!("{interface} ring {parameter}"),
+            Setting::IrqBalance => "irqbalance".to_string(),
+        }
+    }
 
-    if tuning.disable_rxvlan {
-        let _ = Command::new("/sbin/ethtool")
-            .args(["-K", interface, "rxvlan", "off"])
-            .output().await;
+    async fn read(&self) -> Result<String> {
+        let not_found = || Error::msg(format!("Unable to read {}", self.describe()));
+        match self {
+            Setting::Sysctl(name) => Ok(run("/sbin/sysctl", &["-n", name]).await?.trim().to_string()),
+            Setting::Offload { interface, feature } => {
+                let interface = interface.clone();
+                let features = ethtool(move |e| e.features(&interface)).await?;
+                Ok(on_off(features.is_active(feature)))
+            }
+            Setting::Coalesce { interface, parameter } => {
+                let interface = interface.clone();
+                let coalesce = ethtool(move |e| e.coalesce(&interface)).await?;
+                let value = if *parameter == "rx-usecs" { coalesce.rx_usecs } else { coalesce.tx_usecs };
+                value.map(|v| v.to_string()).ok_or_else(not_found)
+            }
+            Setting::Ring { interface, parameter } => {
+                let interface = interface.clone();
+                let rings = ethtool(move |e| e.rings(&interface)).await?;
+                let value = if *parameter == "rx" { rings.rx } else { rings.tx };
+                value.map(|v| v.to_string()).ok_or_else(not_found)
+            }
+            Setting::IrqBalance => {
+                // `is-active` exits non-zero when the service is stopped
+                let output = Command::new("/bin/systemctl")
+                    .args(["is-active", "irqbalance"])
+                    .output()
+                    .await?;
+                Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+            }
+        }
     }
 
-    if tuning.disable_txvlan {
-        let _ = Command::new("/sbin/ethtool")
-            .args(["-K", interface, "txvlan", "off"])
-            .output().await;
+    async fn write(&self, value: &str) -> Result<()> {
+        match self {
+            Setting::Sysctl(name) => {
+                run("/sbin/sysctl", &[&format!("{name}={value}")]).await?;
+            }
+            Setting::Offload { interface, feature } => {
+                let (interface, feature, on) = (interface.clone(), feature.clone(), value == "on");
+                ethtool(move |e| e.set_features(&interface, &[(feature.as_str(), on)])).await?;
+            }
+            Setting::Coalesce { interface, parameter } => {
+                let value = Some(value.parse()?);
+                let coalesce = if *parameter == "rx-usecs" {
+                    Coalesce { rx_usecs: value, ..Default::default() }
+                } else {
+                    Coalesce { tx_usecs: value, ..Default::default() }
+                };
+                let interface = interface.clone();
+                ethtool(move |e| e.set_coalesce(&interface, &coalesce)).await?;
+            }
+            Setting::Ring { interface, parameter } => {
+                let value = Some(value.parse()?);
+                let rings = if *parameter == "rx" {
+                    Rings { rx: value, ..Default::default() }
+                } else {
+                    Rings { tx: value, ..Default::default() }
+                };
+                let interface = interface.clone();
+                ethtool(move |e| e.set_rings(&interface, &rings)).await?;
+            }
+            Setting::IrqBalance => {
+                let action = if value == "active" { "start" } else { "stop" };
+                run("/bin/systemctl", &[action, "irqbalance"]).await?;
+            }
+        };
+        Ok(())
     }
-}
\ No newline at end of file
+}
+
+fn on_off(on: bool) -> String {
+    if on { "on" } else { "off" }.to_string()
+}
+
+/// Drivers may silently round or ignore values, so every change is
+/// read back to confirm it.
+async fn check(setting: &Setting, value: &str) -> Result<()> {
+    let now = setting.read().await?;
+    if now == value {
+        Ok(())
+    } else {
+        Err(Error::msg(format!("the driver reports {now}")))
+    }
+}
+
+async fn write_and_check(setting: &Setting, value: &str) -> Result<()> {
+    setting.write(value).await?;
+    check(setting, value).await
+}
+
+async fn apply(setting: Setting, value: String) -> Applied {
+    let previous = setting.read().await.ok();
+    let (written, error) = if previous.as_deref() == Some(value.as_str()) {
+        (false, None)
+    } else {
+        match setting.write(&value).await {
+            Ok(()) => (true, check(&setting, &value).await.err().map(|e| e.to_string())),
+            Err(e) => (false, Some(e.to_string())),
+        }
+    };
+    match &error {
+        None => info!("Tuning: set {} to {value} (was {})", setting.describe(), previous.as_deref().unwrap_or("unknown")),
+        Some(e) => warn!("Tuning: unable to set {} to {value}: {e}", setting.describe()),
+    }
+    Applied { setting, previous, value, written, error }
+}
+
+/// The settings `tuning` asks for, and their values.
+async fn wanted_settings(tuning: &Tunables, interfaces: &[&str]) -> Vec<(Setting, String)> {
+    let mut settings = vec![
+        (Setting::Sysctl("net.core.bpf_jit_enable"), "1".to_string()),
+        (Setting::Sysctl("net.core.netdev_budget_usecs"), tuning.netdev_budget_usecs.to_string()),
+        (Setting::Sysctl("net.core.netdev_budget"), tuning.netdev_budget_packets.to_string()),
+    ];
+    if tuning.stop_irq_balance {
+        settings.push((Setting::IrqBalance, "inactive".to_string()));
+    }
+    for interface in interfaces.iter().filter(|i| !i.is_empty()) {
+        // Individually, so that one unsupported feature doesn't stop the rest
+        let mut features = tuning.disable_offload.clone();
+        if tuning.disable_rxvlan {
+            features.push("rxvlan".to_string());
+        }
+        if tuning.disable_txvlan {
+            features.push("txvlan".to_string());
+        }
+        for feature in features {
+            for part in offload_parts(interface, &feature).await {
+                settings.push((Setting::Offload { interface: interface.to_string(), feature: part }, "off".to_string()));
+            }
+        }
+        settings.push((Setting::Coalesce { interface: interface.to_string(), parameter: "rx-usecs" }, tuning.rx_usecs.to_string()));
+        settings.push((Setting::Coalesce { interface: interface.to_string(), parameter: "tx-usecs" }, tuning.tx_usecs.to_string()));
+        if let Some(size) = tuning.rx_ring_size {
+            settings.push((Setting::Ring { interface: interface.to_string(), parameter: "rx" }, size.to_string()));
+        }
+        if let Some(size) = tuning.tx_ring_size {
+            settings.push((Setting::Ring { interface: interface.to_string(), parameter: "tx" }, size.to_string()));
+        }
+    }
+    settings
+}
+
+/// Applies `tuning` to the system and the given interfaces,
+/// remembering the previous values.
+pub async fn apply_tuning(tuning: &Tunables, interfaces: &[&str]) {
+    let mut applied = Vec::new();
+    for (setting, value) in wanted_settings(tuning, interfaces).await {
+        applied.push(apply(setting, value).await);
+    }
+    APPLIED.lock().extend(applied);
+}
+
+/// Brings the system in line with a reloaded `[tuning]` section,
+/// touching only what differs from the current tuning. Restoring and
+/// re-applying everything would toggle offloads and rings (which resets
+/// many NICs) and briefly start `irqbalance`. Settings that are no
+/// longer wanted are restored; changed ones keep the value from before
+/// `lqosd` first changed them, to restore on shutdown.
+pub async fn retune(tuning: Option<&Tunables>, interfaces: &[&str]) {
+    let wanted = match tuning {
+        Some(tuning) => wanted_settings(tuning, interfaces).await,
+        None => Vec::new(),
+    };
+    let (mut current, unwanted): (Vec<Applied>, Vec<Applied>) = std::mem::take(&mut *APPLIED.lock())
+        .into_iter()
+        .partition(|a| wanted.iter().any(|(setting, _)| *setting == a.setting));
+    restore(unwanted).await;
+
+    let mut applied = Vec::new();
+    for (setting, value) in wanted {
+        let old = current.iter().position(|a| a.setting == setting).map(|i| current.remove(i));
+        match old {
+            Some(old) if old.value == value && old.error.is_none() => applied.push(old),
+            Some(old) => {
+                let new = apply(setting, value).await;
+                applied.push(Applied {
+                    previous: old.previous,
+                    written: old.written || new.written,
+                    ..new
+                });
+            }
+            None => applied.push(apply(setting, value).await),
+        }
+    }
+    APPLIED.lock().extend(applied);
+}
+
+/// Puts back every setting that was written, newest first, including
+/// those the driver didn't set exactly as asked. Returns the outcome of
+/// each restore; calling it again does nothing.
+pub async fn restore_tuning() -> Vec<TuningResult> {
+    let applied = std::mem::take(&mut *APPLIED.lock());
+    restore(applied).await
+}
+
+async fn restore(applied: Vec<Applied>) -> Vec<TuningResult> {
+    let mut results = Vec::new();
+    for change in applied.iter().rev().filter(|a| a.written) {
+        let Some(previous) = &change.previous else { continue };
+        if *previous == change.value {
+            continue;
+        }
+        let error = write_and_check(&change.setting, previous).await.err().map(|e| e.to_string());
+        match &error {
+            None => info!("Tuning: restored {} to {previous}", change.setting.describe()),
+            Some(e) => warn!("Tuning: unable to restore {}: {e}", change.setting.describe()),
+        }
+        results.push(TuningResult {
+            setting: change.setting.describe(),
+            previous: Some(change.value.clone()),
+            value: previous.clone(),
+            error,
+        });
+    }
+    results
+}
+
+pub(crate) fn tuning_status() -> BusResponse {
+    BusResponse::Tuning(APPLIED.lock().iter().map(|a| a.result()).collect())
+}
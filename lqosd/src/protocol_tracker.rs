@@ -0,0 +1,104 @@
+//! Tracks the XDP per-circuit protocol bucket counters, calculating
+//! per-bucket rates once per second.
+
+use std::collections::HashMap;
+use lazy_static::*;
+use lqos_bus::{BusResponse, ProtocolBucket, ProtocolStats};
+use lqos_sys::{ProtocolCounters, PROTOCOL_BUCKETS};
+use parking_lot::RwLock;
+use crate::libreqos_tracker::QUEUE_STRUCTURE;
+
+#[derive(Default, Clone)]
+struct BucketEntry {
+    bytes: [(u64, u64); PROTOCOL_BUCKETS],
+    prev_bytes: [(u64, u64); PROTOCOL_BUCKETS],
+    bytes_per_second: [(u64, u64); PROTOCOL_BUCKETS],
+}
+
+lazy_static! {
+    /// Bucket counters, keyed by TC handle.
+    static ref PROTOCOL_TRACKER: RwLock<HashMap<u32, BucketEntry>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Updates the per-bucket rates from the XDP map. Called once per
+/// second by the throughput monitor.
+pub(crate) fn tick(counters: &[(u32, ProtocolCounters)]) {
+    let mut tracker = PROTOCOL_TRACKER.write();
+    let mut next = HashMap::with_capacity(counters.len());
+    for (tc_handle, c) in counters.iter() {
+        let (mut entry, is_new) = match tracker.remove(tc_handle) {
+            Some(entry) => (entry, false),
+            None => (BucketEntry::default(), true),
+        };
+        for i in 0..PROTOCOL_BUCKETS {
+            entry.bytes[i] = (c.download_bytes[i], c.upload_bytes[i]);
+            if is_new {
+                // Don't report everything counted so far as one second
+                entry.prev_bytes[i] = entry.bytes[i];
+            }
+            entry.bytes_per_second[i] = (
+                entry.bytes[i].0.saturating_sub(entry.prev_bytes[i].0),
+                entry.bytes[i].1.saturating_sub(entry.prev_bytes[i].1),
+            );
+            entry.prev_bytes[i] = entry.bytes[i];
+        }
+        next.insert(*tc_handle, entry);
+    }
+    *tracker = next;
+}
+
+/// Returns the TC handles (download and upload) used by a circuit.
+fn circuit_handles(circuit_id: &str) -> Vec<u32> {
+    let mut handles = Vec::new();
+    if let Ok(structure) = &*QUEUE_STRUCTURE.read() {
+        for node in structure
+            .iter()
+            .filter(|n| n.circuit_id.as_deref() == Some(circuit_id))
+        {
+            for handle in [node.class_id.as_u32(), node.up_class_id.as_u32()] {
+                if handle != 0 && !handles.contains(&handle) {
+                    handles.push(handle);
+                }
+            }
+        }
+    }
+    handles
+}
+
+/// Returns the protocol breakdown for a circuit, or for all traffic if
+/// `circuit_id` is `None`.
+pub(crate) fn protocol_breakdown(circuit_id: &Option<String>) -> BusResponse {
+    let handles = circuit_id.as_ref().map(|id| circuit_handles(id));
+    if let (Some(id), Some(handles)) = (circuit_id, &handles) {
+        if handles.is_empty() {
+            return BusResponse::Fail(format!("Circuit {id} not found in queuingStructure.json"));
+        }
+    }
+
+    let mut totals = [(0u64, 0u64); PROTOCOL_BUCKETS];
+    let mut rates = [(0u64, 0u64); PROTOCOL_BUCKETS];
+    let tracker = PROTOCOL_TRACKER.read();
+    for (_, entry) in tracker.iter().filter(|(tc_handle, _)| match &handles {
+        Some(handles) => handles.contains(tc_handle),
+        None => true,
+    }) {
+        for i in 0..PROTOCOL_BUCKETS {
+            totals[i].0 += entry.bytes[i].0;
+            totals[i].1 += entry.bytes[i].1;
+            rates[i].0 += entry.bytes_per_second[i].0;
+            rates[i].1 += entry.bytes_per_second[i].1;
+        }
+    }
+
+    let result = ProtocolBucket::ALL
+        .iter()
+        .enumerate()
+        .map(|(i, bucket)| ProtocolStats {
+            bucket: *bucket,
+            bits_per_second: (rates[i].0 * 8, rates[i].1 * 8),
+            total_bytes: totals[i],
+        })
+        .collect();
+    BusResponse::ProtocolBreakdown(result)
+}
@@ -121,6 +121,16 @@ pub async fn spawn_queue_monitor() {
     });
 }
 
+/// Returns the packets dropped by each circuit's (download, upload)
+/// queues, as of the last time the queues were read.
+pub(crate) fn circuit_drops() -> HashMap<String, (u64, u64)> {
+    CIRCUIT_TO_QUEUE
+        .read()
+        .iter()
+        .map(|(circuit_id, (down, up))| (circuit_id.clone(), (down.drops(), up.drops())))
+        .collect()
+}
+
 pub fn get_raw_circuit_data(circuit_id: &str) -> BusResponse {
     let reader = CIRCUIT_TO_QUEUE.read();
     if let Some(circuit) = reader.get(circuit_id) {
@@ -3,6 +3,7 @@ mod tc_htb;
 mod tc_fq_codel;
 mod tc_cake;
 use anyhow::{Result, Error};
+use lqos_bus::{CakeTinStats, QueueStats};
 use serde::Serialize;
 use serde_json::Value;
 use std::process::Command;
@@ -27,6 +28,52 @@ impl QueueType {
             _ => Err(Error::msg(format!("Unknown queue kind: {kind}"))),
         }   
     }
+
+    /// Packets dropped by a circuit queue since it was created.
+    pub(crate) fn drops(&self) -> u64 {
+        match self {
+            QueueType::Cake(cake) => cake.drops,
+            QueueType::FqCodel(fq) => fq.drops,
+            _ => 0,
+        }
+    }
+
+    /// Counters for a circuit queue, or `None` for queue types that
+    /// circuits don't use.
+    pub(crate) fn stats(&self) -> Option<QueueStats> {
+        match self {
+            QueueType::Cake(cake) => Some(QueueStats {
+                kind: "cake".to_string(),
+                bytes: cake.bytes,
+                packets: cake.packets,
+                drops: cake.drops,
+                backlog: cake.backlog,
+                tins: cake
+                    .tins
+                    .iter()
+                    .map(|tin| CakeTinStats {
+                        sent_bytes: tin.sent_bytes,
+                        sent_packets: tin.sent_packets,
+                        drops: tin.drops,
+                        ecn_marks: tin.ecn_marks,
+                        backlog_bytes: tin.backlog_bytes,
+                        avg_delay_us: tin.avg_delay_us,
+                        peak_delay_us: tin.peak_delay_us,
+                        base_delay_us: tin.base_delay_us,
+                    })
+                    .collect(),
+            }),
+            QueueType::FqCodel(fq) => Some(QueueStats {
+                kind: "fq_codel".to_string(),
+                bytes: fq.bytes,
+                packets: fq.packets,
+                drops: fq.drops,
+                backlog: fq.backlog,
+                tins: Vec::new(),
+            }),
+            _ => None,
+        }
+    }
 }
 
 pub(crate) fn read_tc_queues(interface: &str) -> Result<Vec<QueueType>> {
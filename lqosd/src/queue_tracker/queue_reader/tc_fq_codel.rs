@@ -15,12 +15,12 @@ pub(crate) struct TcFqCodel {
     handle: TcHandle,
     pub(crate) parent: TcHandle,
     options: TcFqCodelOptions,
-    bytes: u64,
-    packets: u64,
-    drops: u64,
+    pub(crate) bytes: u64,
+    pub(crate) packets: u64,
+    pub(crate) drops: u64,
     overlimits: u64,
     requeues: u64,
-    backlog: u64,
+    pub(crate) backlog: u64,
     qlen: u64,
     maxpacket: u64,
     drop_overlimit: u64,
@@ -0,0 +1,69 @@
+//! Integration with `systemd` (readiness and watchdog notifications),
+//! and reloading the configuration on `SIGHUP`.
+//!
+//! Notifications are ignored when `lqosd` isn't started by `systemd`.
+
+use std::time::Duration;
+use log::{info, warn};
+use lqos_config::{EtcLqos, LibreQoSConfig};
+use lqos_sys::LibreQoSKernels;
+use sd_notify::NotifyState;
+use crate::{audit, cpu_balancer, libreqos_tracker, offloads, tracking_filter};
+
+fn notify(state: NotifyState) {
+    let _ = sd_notify::notify(false, &[state]);
+}
+
+/// Tells `systemd` that start-up has finished.
+pub(crate) fn notify_ready() {
+    notify(NotifyState::Ready);
+}
+
+/// Tells `systemd` that `lqosd` is shutting down.
+pub(crate) fn notify_stopping() {
+    notify(NotifyState::Stopping);
+}
+
+/// If the unit sets `WatchdogSec`, pings the watchdog at half that
+/// interval. The ping comes from the runtime, so a stalled runtime
+/// gets `lqosd` restarted.
+pub(crate) fn spawn_watchdog() {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return;
+    }
+    let period = Duration::from_micros(usec / 2);
+    info!("Pinging the systemd watchdog every {:?}", period);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            notify(NotifyState::Watchdog);
+        }
+    });
+}
+
+/// Re-reads `/etc/lqos`, `ispConfig.py`, `ShapedDevices.csv` and
+/// `queuingStructure.json`, and applies what can change without
+/// re-attaching the XDP/TC programs: tuning, tracking filters and
+/// excluded CPUs.
+pub(crate) async fn reload(kernels: &mut LibreQoSKernels) {
+    notify(NotifyState::Reloading);
+    match (EtcLqos::load(), LibreQoSConfig::load()) {
+        (Ok(etc_lqos), Ok(config)) => {
+            offloads::retune(etc_lqos.tuning.as_ref(), &[&config.internet_interface, &config.isp_interface]).await;
+            tracking_filter::setup_tracking_filter(&etc_lqos, &config);
+            let excluded_cpus = etc_lqos.kernel.clone().unwrap_or_default().excluded_cpus;
+            cpu_balancer::set_excluded_cpus(&excluded_cpus);
+            if let Err(e) = kernels.set_excluded_cpus(&excluded_cpus) {
+                warn!("Unable to update the CPU maps: {:?}", e);
+            }
+            audit::set_audit_config(&etc_lqos);
+            info!("Configuration reloaded");
+        }
+        (Err(e), _) | (_, Err(e)) => warn!("Unable to reload configuration, keeping the old one: {:?}", e),
+    }
+    libreqos_tracker::reload_shaped_devices();
+    libreqos_tracker::reload_queue_structure();
+    notify(NotifyState::Ready);
+}
@@ -1,12 +1,12 @@
 mod tracking_data;
 mod throughput_entry;
 use lazy_static::*;
-use lqos_bus::{BusResponse, IpStats, XdpPpingResult, TcHandle};
-use lqos_sys::{XdpIpAddress, get_throughput_map};
+use lqos_bus::{BusResponse, IpStats, XdpPpingResult, TcHandle, UnknownIp};
+use lqos_sys::{XdpIpAddress, get_throughput_map, MapBackend};
 use parking_lot::RwLock;
-use std::time::{Duration, Instant};
+use std::{collections::HashMap, net::IpAddr, time::{Duration, Instant}};
 use tokio::{task, time};
-use crate::throughput_tracker::tracking_data::ThroughputTracker;
+use crate::{throughput_tracker::tracking_data::ThroughputTracker, flow_tracker, ip_mapping::BPF_MAPS, protocol_tracker, tracking_filter, unknown_ips};
 
 const RETIRE_AFTER_SECONDS: u64 = 30;
 
@@ -21,14 +21,7 @@ pub async fn spawn_throughput_monitor() {
 
         loop {
             let now = Instant::now();
-            let _ = task::spawn_blocking(move || {
-                let rtt = lqos_sys::get_tcp_round_trip_times();
-                if let Ok(value_dump) = get_throughput_map() {
-                    let mut thoughput = THROUGHPUT_TRACKER.write();
-                    let _ = thoughput.tick(&value_dump, rtt);
-                }
-            })
-            .await;
+            let _ = task::spawn_blocking(move || poll_maps(&*BPF_MAPS)).await;
             let elapsed = now.elapsed();
             //println!("Tick consumed {:.2} seconds.", elapsed.as_secs_f32());
             if elapsed.as_secs_f32() < 1.0 {
@@ -42,6 +35,22 @@ pub async fn spawn_throughput_monitor() {
     });
 }
 
+/// Reads the XDP maps, and updates the throughput, flow and protocol
+/// trackers.
+fn poll_maps(maps: &impl MapBackend) {
+    let rtt = lqos_sys::get_tcp_round_trip_times(maps);
+    if let Ok(value_dump) = get_throughput_map(maps) {
+        let mut thoughput = THROUGHPUT_TRACKER.write();
+        let _ = thoughput.tick(&value_dump, rtt);
+    }
+    if let Ok(flows) = lqos_sys::get_flow_map(maps) {
+        flow_tracker::tick(&flows);
+    }
+    if let Ok(protocols) = lqos_sys::get_protocol_map(maps) {
+        protocol_tracker::tick(&protocols);
+    }
+}
+
 pub fn current_throughput() -> BusResponse {
     let (bits_per_second, packets_per_second, shaped_bits_per_second) = {
         let tp = THROUGHPUT_TRACKER.read();
@@ -65,6 +74,7 @@ pub fn top_n(n: u32) -> BusResponse {
         tp.raw_data
             .iter()
             .filter(|(ip, _)| !ip.as_ip().is_loopback())
+            .filter(|(ip, _)| tracking_filter::is_reported(ip.as_ip()))
             .filter(|(_, d)| retire_check(tp.cycle, d.most_recent_cycle))
             .map(|(ip, te)| {
                 (
@@ -100,6 +110,7 @@ pub fn worst_n(n: u32) -> BusResponse {
         tp.raw_data
             .iter()
             .filter(|(ip, _)| !ip.as_ip().is_loopback())
+            .filter(|(ip, _)| tracking_filter::is_reported(ip.as_ip()))
             .filter(|(_, d)| retire_check(tp.cycle, d.most_recent_cycle))
             .map(|(ip, te)| {
                 (
@@ -203,7 +214,9 @@ pub fn all_unknown_ips() -> BusResponse {
         tp.raw_data
             .iter()
             .filter(|(ip, _)| !ip.as_ip().is_loopback())
+            .filter(|(ip, _)| tracking_filter::is_reported(ip.as_ip()))
             .filter(|(_, d)| d.tc_handle.as_u32() == 0)
+            .filter(|(ip, _)| !unknown_ips::is_mapped(ip.as_ip()))
             .map(|(ip, te)| {
                 (
                     *ip,
@@ -230,4 +243,174 @@ pub fn all_unknown_ips() -> BusResponse {
         )
         .collect();
     BusResponse::AllUnknownIps(result)
-}
\ No newline at end of file
+}
+
+pub fn unknown_ip_details() -> BusResponse {
+    let mut result: Vec<UnknownIp> = {
+        let tp = THROUGHPUT_TRACKER.read();
+        tp.raw_data
+            .iter()
+            .filter(|(ip, _)| !ip.as_ip().is_loopback())
+            .filter(|(ip, _)| tracking_filter::is_reported(ip.as_ip()))
+            .filter(|(_, d)| d.tc_handle.as_u32() == 0)
+            .filter(|(ip, _)| !unknown_ips::is_mapped(ip.as_ip()))
+            .map(|(ip, te)| {
+                let ip = ip.as_ip();
+                UnknownIp {
+                    ip_address: ip.to_string(),
+                    classification: unknown_ips::classify(ip),
+                    subnet: tracking_filter::allowed_subnet(ip),
+                    first_seen_secs_ago: tp.cycle - te.first_cycle,
+                    last_seen_secs_ago: tp.cycle - u64::max(te.first_cycle, te.most_recent_cycle),
+                    total_bytes: te.bytes,
+                    total_packets: te.packets,
+                }
+            })
+            .collect()
+    };
+    result.sort_by_key(|ip| ip.last_seen_secs_ago);
+    BusResponse::UnknownIpDetails(result)
+}
+
+/// Returns the bits per second (download, upload) counted by the XDP
+/// program on each CPU.
+pub fn xdp_bits_per_cpu() -> Vec<(u64, u64)> {
+    THROUGHPUT_TRACKER
+        .read()
+        .cpu_bytes_per_second
+        .iter()
+        .map(|(down, up)| (down * 8, up * 8))
+        .collect()
+}
+
+/// Returns the current bits per second (download, upload) of mapped
+/// traffic, summed by TC handle.
+pub fn bits_per_tc_handle() -> HashMap<u32, (u64, u64)> {
+    let mut result = HashMap::new();
+    let tp = THROUGHPUT_TRACKER.read();
+    for te in tp.raw_data
+        .values()
+        .filter(|d| d.tc_handle.as_u32() != 0)
+        .filter(|d| retire_check(tp.cycle, d.most_recent_cycle))
+    {
+        let entry = result.entry(te.tc_handle.as_u32()).or_insert((0, 0));
+        entry.0 += te.bytes_per_second.0 * 8;
+        entry.1 += te.bytes_per_second.1 * 8;
+    }
+    result
+}
+
+/// Returns the median RTT (in ms) of each mapped host that has one,
+/// grouped by TC handle.
+pub fn rtt_per_tc_handle() -> HashMap<u32, Vec<f32>> {
+    let mut result: HashMap<u32, Vec<f32>> = HashMap::new();
+    let tp = THROUGHPUT_TRACKER.read();
+    for te in tp.raw_data
+        .values()
+        .filter(|d| d.tc_handle.as_u32() != 0)
+        .filter(|d| retire_check(tp.cycle, d.most_recent_cycle))
+    {
+        let rtt = te.median_latency();
+        if rtt > 0.0 {
+            result.entry(te.tc_handle.as_u32()).or_default().push(rtt);
+        }
+    }
+    result
+}
+
+/// Current traffic of each mapped host, grouped by TC handle, busiest
+/// first.
+pub fn hosts_per_tc_handle() -> HashMap<u32, Vec<IpStats>> {
+    let mut result: HashMap<u32, Vec<IpStats>> = HashMap::new();
+    let tp = THROUGHPUT_TRACKER.read();
+    for (ip, te) in tp.raw_data
+        .iter()
+        .filter(|(_, d)| d.tc_handle.as_u32() != 0)
+        .filter(|(_, d)| retire_check(tp.cycle, d.most_recent_cycle))
+    {
+        result.entry(te.tc_handle.as_u32()).or_default().push(IpStats {
+            ip_address: ip.as_ip().to_string(),
+            bits_per_second: (te.bytes_per_second.0 * 8, te.bytes_per_second.1 * 8),
+            packets_per_second: te.packets_per_second,
+            median_tcp_rtt: te.median_latency(),
+            tc_handle: te.tc_handle,
+        });
+    }
+    for hosts in result.values_mut() {
+        hosts.sort_by(|a, b| b.bits_per_second.0.cmp(&a.bits_per_second.0));
+    }
+    result
+}
+
+/// Current counters for a tracked host. Tuples are (download, upload).
+pub struct HostTotals {
+    pub ip: IpAddr,
+    pub bytes: (u64, u64),
+    pub packets: (u64, u64),
+}
+
+/// Returns the current byte and packet counters for every tracked host.
+/// Used for NetFlow export.
+pub fn host_totals() -> Vec<HostTotals> {
+    let tp = THROUGHPUT_TRACKER.read();
+    tp.raw_data
+        .iter()
+        .filter(|(ip, _)| !ip.as_ip().is_loopback())
+        .map(|(ip, te)| HostTotals {
+            ip: ip.as_ip(),
+            bytes: te.bytes,
+            packets: te.packets,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lqos_sys::{HostCounter, InMemoryMaps, RttTrackingEntry};
+
+    fn counter(bytes: u64, packets: u64) -> HostCounter {
+        HostCounter {
+            download_bytes: bytes,
+            download_packets: packets,
+            upload_bytes: bytes / 10,
+            upload_packets: packets,
+            tc_handle: TcHandle::from_string("1:5").unwrap().as_u32(),
+        }
+    }
+
+    #[test]
+    fn pipeline_from_in_memory_maps() {
+        let maps = InMemoryMaps::new();
+        let ip = XdpIpAddress::from_ip("192.168.1.1".parse().unwrap());
+        maps.set_per_cpu("map_traffic", &ip, vec![counter(600, 6), counter(400, 4)]).unwrap();
+        poll_maps(&maps);
+
+        // Counters keep incrementing; one second later 2,000 more bytes
+        // have been downloaded.
+        maps.set_per_cpu("map_traffic", &ip, vec![counter(2000, 20), counter(1000, 10)]).unwrap();
+        let mut rtt = RttTrackingEntry::default();
+        rtt.rtt[0] = 1500;
+        rtt.has_fresh_data = 1;
+        maps.insert("rtt_tracker", &ip.0, &rtt).unwrap();
+        poll_maps(&maps);
+
+        match current_throughput() {
+            BusResponse::CurrentThroughput { bits_per_second, packets_per_second, shaped_bits_per_second } => {
+                assert_eq!(bits_per_second, (16000, 1600));
+                assert_eq!(packets_per_second, (20, 20));
+                assert_eq!(shaped_bits_per_second, bits_per_second);
+            }
+            _ => panic!("Unexpected response"),
+        }
+        match top_n(10) {
+            BusResponse::TopDownloaders(hosts) => {
+                assert_eq!(hosts.len(), 1);
+                assert_eq!(hosts[0].ip_address, "192.168.1.1");
+                assert_eq!(hosts[0].median_tcp_rtt, 15.0);
+                assert_eq!(hosts[0].tc_handle.to_string(), "1:5");
+            }
+            _ => panic!("Unexpected response"),
+        }
+    }
+}
@@ -10,6 +10,9 @@ pub struct ThroughputTracker {
     pub(crate) bytes_per_second: (u64, u64),
     pub(crate) packets_per_second: (u64, u64),
     pub(crate) shaped_bytes_per_second: (u64, u64),
+    /// Byte counters summed by the CPU on which XDP counted them
+    cpu_bytes: Vec<(u64, u64)>,
+    pub(crate) cpu_bytes_per_second: Vec<(u64, u64)>,
 }
 
 impl ThroughputTracker {
@@ -23,6 +26,8 @@ impl ThroughputTracker {
             bytes_per_second: (0, 0),
             packets_per_second: (0, 0),
             shaped_bytes_per_second: (0, 0),
+            cpu_bytes: Vec::new(),
+            cpu_bytes_per_second: Vec::new(),
         }
     }
 
@@ -86,6 +91,27 @@ impl ThroughputTracker {
             }
         });
 
+        // Per-CPU totals. Counters for evicted hosts disappear, so
+        // saturate rather than underflow.
+        let mut cpu_bytes = Vec::new();
+        for (_, counts) in value_dump.iter() {
+            cpu_bytes.resize(usize::max(cpu_bytes.len(), counts.len()), (0, 0));
+            for (cpu, c) in counts.iter().enumerate() {
+                cpu_bytes[cpu].0 += c.download_bytes;
+                cpu_bytes[cpu].1 += c.upload_bytes;
+            }
+        }
+        self.cpu_bytes_per_second = if cpu_bytes.len() == self.cpu_bytes.len() {
+            cpu_bytes
+                .iter()
+                .zip(self.cpu_bytes.iter())
+                .map(|(now, prev)| (now.0.saturating_sub(prev.0), now.1.saturating_sub(prev.1)))
+                .collect()
+        } else {
+            vec![(0, 0); cpu_bytes.len()]
+        };
+        self.cpu_bytes = cpu_bytes;
+
         // Apply RTT data
         if let Ok(rtt_dump) = rtt {
             for (raw_ip, rtt) in rtt_dump {
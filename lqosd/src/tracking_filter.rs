@@ -0,0 +1,65 @@
+//! Decides which addresses from `map_traffic` are reported in the
+//! top-N, worst-RTT and unknown-IP lists, based on the `[tracking]`
+//! section of `/etc/lqos` (falling back to `ispConfig.py`).
+
+use std::net::IpAddr;
+use lazy_static::*;
+use log::{info, warn};
+use lqos_config::{EtcLqos, LibreQoSConfig, SubnetMatcher};
+use parking_lot::RwLock;
+use crate::ip_mapping::BPF_MAPS;
+
+struct TrackingFilter {
+    allowed: SubnetMatcher,
+    ignored: SubnetMatcher,
+}
+
+lazy_static! {
+    static ref TRACKING_FILTER: RwLock<TrackingFilter> = RwLock::new(TrackingFilter {
+        allowed: SubnetMatcher::new(&[]),
+        ignored: SubnetMatcher::new(&[]),
+    });
+}
+
+/// Builds the tracking filter from the configuration, and - if requested -
+/// loads the ignored subnets into the XDP program. Must be called after
+/// the XDP/TC kernels are loaded.
+pub(crate) fn setup_tracking_filter(etc: &EtcLqos, config: &LibreQoSConfig) {
+    let tracking = etc.tracking.clone().unwrap_or_default();
+    let allowed = if tracking.allowed_subnets.is_empty() {
+        &config.allowed_subnets
+    } else {
+        &tracking.allowed_subnets
+    };
+    let ignored = if tracking.ignore_subnets.is_empty() {
+        &config.ignore_subnets
+    } else {
+        &tracking.ignore_subnets
+    };
+    info!("Tracking filter: {} allowed subnets, {} ignored subnets", allowed.len(), ignored.len());
+
+    let xdp_ignored: &[String] = if tracking.ignore_in_xdp { ignored } else { &[] };
+    if let Err(e) = lqos_sys::set_ignored_subnets(&*BPF_MAPS, xdp_ignored) {
+        warn!("Unable to load ignored subnets into XDP: {:?}", e);
+    }
+
+    *TRACKING_FILTER.write() = TrackingFilter {
+        allowed: SubnetMatcher::new(allowed),
+        ignored: SubnetMatcher::new(ignored),
+    };
+}
+
+/// Returns true if `ip` should appear in reports: it isn't in an ignored
+/// subnet, and is in an allowed subnet (if any are configured).
+pub(crate) fn is_reported(ip: IpAddr) -> bool {
+    let filter = TRACKING_FILTER.read();
+    if filter.ignored.contains(ip) {
+        return false;
+    }
+    filter.allowed.is_empty() || filter.allowed.contains(ip)
+}
+
+/// Returns the allowed subnet containing `ip`, if there is one.
+pub(crate) fn allowed_subnet(ip: IpAddr) -> Option<String> {
+    TRACKING_FILTER.read().allowed.find(ip).map(|s| s.to_string())
+}
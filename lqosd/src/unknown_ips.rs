@@ -0,0 +1,60 @@
+//! Classification of IP addresses that show up in `map_traffic`
+//! without being mapped to a circuit, and suggestions for adding
+//! them to `ShapedDevices.csv`.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use lqos_bus::{BusResponse, IpClassification};
+use crate::libreqos_tracker::SHAPED_DEVICES;
+
+/// Returns true if `ip` is covered by an entry in `ShapedDevices.csv`.
+pub(crate) fn is_mapped(ip: IpAddr) -> bool {
+    let lookup = match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+    SHAPED_DEVICES.read().trie.longest_match(lookup).is_some()
+}
+
+/// Classifies an address as private, CGNAT, link-local or public.
+pub(crate) fn classify(ip: IpAddr) -> IpClassification {
+    match ip {
+        IpAddr::V4(ip) => classify_v4(ip),
+        IpAddr::V6(ip) => classify_v6(ip),
+    }
+}
+
+fn classify_v4(ip: Ipv4Addr) -> IpClassification {
+    let octets = ip.octets();
+    if ip.is_private() {
+        IpClassification::Private
+    } else if octets[0] == 100 && (octets[1] & 0xC0) == 64 {
+        IpClassification::CarrierGradeNat
+    } else if ip.is_link_local() {
+        IpClassification::LinkLocal
+    } else {
+        IpClassification::Public
+    }
+}
+
+fn classify_v6(ip: Ipv6Addr) -> IpClassification {
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        return classify_v4(v4);
+    }
+    let first = ip.segments()[0];
+    if (first & 0xFE00) == 0xFC00 {
+        IpClassification::Private
+    } else if (first & 0xFFC0) == 0xFE80 {
+        IpClassification::LinkLocal
+    } else {
+        IpClassification::Public
+    }
+}
+
+/// Builds a pre-filled `ShapedDevice` for an unknown IP address, with
+/// the parent node guessed from nearby mapped addresses.
+pub(crate) fn suggest_shaped_device(ip: &str) -> BusResponse {
+    match ip.trim().parse::<IpAddr>() {
+        Ok(ip) => BusResponse::SuggestedDevice(SHAPED_DEVICES.read().suggest_device(ip)),
+        Err(..) => BusResponse::Fail(format!("Unable to parse IP address: {ip}")),
+    }
+}
@@ -1,7 +1,7 @@
 use anyhow::Result;
 use crossterm::{event::KeyCode, terminal::enable_raw_mode};
 use lqos_bus::{
-    decode_response, encode_request, BusRequest, BusResponse, BusSession, IpStats, BUS_BIND_ADDRESS,
+    decode_response, encode_request, BusRequest, BusResponse, BusSession, IpStats, bus_address,
 };
 use std::{io, time::Duration};
 use tokio::{
@@ -22,21 +22,22 @@ struct DataResult {
     top: Vec<IpStats>,
 }
 
-async fn get_data(n_rows: u16) -> Result<DataResult> {
+async fn get_data(bus_address: &str, n_rows: u16) -> Result<DataResult> {
     let mut result = DataResult {
         totals: (0, 0, 0, 0),
         top: Vec::new(),
     };
-    let mut stream = TcpStream::connect(BUS_BIND_ADDRESS).await?;
+    let mut stream = TcpStream::connect(bus_address).await?;
     let test = BusSession {
         auth_cookie: 1234,
+        client: None,
         requests: vec![
             BusRequest::GetCurrentThroughput,
             BusRequest::GetTopNDownloaders(n_rows as u32),
         ],
     };
     let msg = encode_request(&test)?;
-    stream.write(&msg).await?;
+    stream.write_all(&msg).await?;
     let mut buf = Vec::new();
     let _ = stream.read_to_end(&mut buf).await.unwrap();
     let reply = decode_response(&buf)?;
@@ -190,9 +191,10 @@ pub async fn main() -> Result<()> {
     let mut terminal = Terminal::new(backend)?;
     terminal.clear()?;
     let mut n_rows = 10;
+    let bus_address = bus_address();
 
     loop {
-        if let Ok(result) = get_data(n_rows).await {
+        if let Ok(result) = get_data(&bus_address, n_rows).await {
             let (bits_down, bits_up, packets_down, packets_up) = result.totals;
             packets = (packets_down, packets_up);
             bits = (bits_down, bits_up);
@@ -1,13 +1,20 @@
 #!/bin/bash
-sudo rm -v /sys/fs/bpf/map_traffic
-sudo rm -v /sys/fs/bpf/map_ip_to_cpu_and_tc
-sudo rm -v /sys/fs/bpf/cpu_map
-sudo rm -v /sys/fs/bpf/cpus_available
-sudo rm -v /sys/fs/bpf/packet_ts
-sudo rm -v /sys/fs/bpf/flow_state
-sudo rm -v /sys/fs/bpf/rtt_tracker
-sudo rm -v /sys/fs/bpf/map_ip_to_cpu_and_tc_recip
-sudo rm -v /sys/fs/bpf/tc/globals/map_txq_config
-sudo rm -v /sys/fs/bpf/bifrost_interface_map
-sudo rm -v /sys/fs/bpf/bifrost_vlan_map
-
+# Removes pinned LibreQoS maps. Pass the pin_root from /etc/lqos if it
+# isn't the default. lqosd can do this itself on exit: see
+# "unpin_on_exit" in lqosd/README.md.
+PIN_ROOT=${1:-/sys/fs/bpf}
+sudo rm -v $PIN_ROOT/map_traffic
+sudo rm -v $PIN_ROOT/map_ip_to_cpu_and_tc
+sudo rm -v $PIN_ROOT/cpu_map
+sudo rm -v $PIN_ROOT/cpus_available
+sudo rm -v $PIN_ROOT/packet_ts
+sudo rm -v $PIN_ROOT/flow_state
+sudo rm -v $PIN_ROOT/rtt_tracker
+sudo rm -v $PIN_ROOT/map_ip_to_cpu_and_tc_recip
+sudo rm -v $PIN_ROOT/tc/globals/map_txq_config
+sudo rm -v $PIN_ROOT/map_txq_config
+sudo rm -v $PIN_ROOT/bifrost_interface_map
+sudo rm -v $PIN_ROOT/bifrost_vlan_map
+sudo rm -v $PIN_ROOT/map_ignore_subnets
+sudo rm -v $PIN_ROOT/flow_tracker
+sudo rm -v $PIN_ROOT/map_protocol_buckets
@@ -2,7 +2,7 @@ use anyhow::{Error, Result};
 use clap::{Parser, Subcommand};
 use lqos_bus::{
     decode_response, encode_request, BusRequest, BusResponse, BusSession, IpMapping,
-    BUS_BIND_ADDRESS, TcHandle,
+    bus_address, TcHandle,
 };
 use std::process::exit;
 use tokio::{
@@ -53,13 +53,14 @@ enum Commands {
 }
 
 async fn talk_to_server(command: BusRequest) -> Result<()> {
-    let mut stream = TcpStream::connect(BUS_BIND_ADDRESS).await?;
+    let mut stream = TcpStream::connect(bus_address()).await?;
     let test = BusSession {
         auth_cookie: 1234,
+        client: Some("xdp_iphash_to_cpu_cmdline".to_string()),
         requests: vec![command],
     };
     let msg = encode_request(&test)?;
-    stream.write(&msg).await?;
+    stream.write_all(&msg).await?;
     let mut buf = Vec::new();
     let _ = stream.read_to_end(&mut buf).await.unwrap();
     let reply = decode_response(&buf)?;
@@ -1,16 +1,17 @@
 use anyhow::Result;
-use lqos_bus::{BUS_BIND_ADDRESS, BusSession, BusRequest, encode_request, decode_response, BusResponse};
+use lqos_bus::{bus_address, BusSession, BusRequest, encode_request, decode_response, BusResponse};
 use tokio::{net::TcpStream, io::{AsyncReadExt, AsyncWriteExt}};
 
 #[tokio::main(flavor = "current_thread")]
 pub async fn main() -> Result<()> {
-    let mut stream = TcpStream::connect(BUS_BIND_ADDRESS).await?;
+    let mut stream = TcpStream::connect(bus_address()).await?;
     let test = BusSession {
         auth_cookie: 1234,
+        client: None,
         requests: vec![BusRequest::XdpPping],
     };
     let msg = encode_request(&test)?;
-    stream.write(&msg).await?;
+    stream.write_all(&msg).await?;
     let mut buf = Vec::new();
     let _ = stream.read_to_end(&mut buf).await.unwrap();
     let reply = decode_response(&buf)?;